@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use super::key_forge::{parse_value, ParsedValue};
+
+/// One option accepted by `getopt`, written `name`, `name|n`, or with a trailing `:` when the
+/// option takes a value (`output|o:`). A one-letter name is also accepted as the short form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionSpec {
+    pub name: String,
+    pub short: Option<char>,
+    pub takes_value: bool,
+}
+
+impl OptionSpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (body, takes_value) = match spec.strip_suffix(':') {
+            Some(body) => (body, true),
+            None => (spec, false),
+        };
+        let (name, short) = match body.split_once('|') {
+            Some((name, short)) => (name, Some(short)),
+            None => (body, None),
+        };
+        let valid_name = |name: &str| {
+            name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if !valid_name(name) {
+            return Err(format!("Invalid option spec '{}' (use name, name|n or name|n:)", spec));
+        }
+
+        let mut chars = short.unwrap_or(name).chars();
+        let short = match (chars.next(), chars.next(), short) {
+            (Some(c), None, _) if c.is_ascii_alphanumeric() => Some(c),
+            (_, _, None) => None,
+            _ => return Err(format!("Short option in '{}' must be a single letter or digit", spec)),
+        };
+        Ok(Self { name: name.to_string(), short, takes_value })
+    }
+}
+
+/// Result of `getopt`: one entry per option, plus the arguments that are not options.
+#[derive(Debug, PartialEq)]
+pub struct ParsedOptions {
+    /// Flags are `true`/`false`; options with a value hold it, or `null` when not given
+    pub options: HashMap<String, ParsedValue>,
+    pub positional: Vec<String>,
+}
+
+/// Splits `args` into options and positional arguments, like GNU `getopt`: long options are
+/// `--name`, `--name value` or `--name=value`, short ones `-n value` or `-nvalue`, short flags
+/// can be grouped (`-vq`), options may follow positional arguments, and `--` ends the options.
+pub fn getopt(specs: &[OptionSpec], args: &[String]) -> Result<ParsedOptions, String> {
+    let mut options = HashMap::new();
+    for spec in specs {
+        let default = if spec.takes_value { ParsedValue::Null } else { ParsedValue::Bool(false) };
+        if options.insert(spec.name.clone(), default).is_some() {
+            return Err(format!("Option '{}' is declared twice", spec.name));
+        }
+    }
+
+    let mut positional = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--" {
+            positional.extend(rest.by_ref().cloned());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let spec = specs
+                .iter()
+                .find(|spec| spec.name == name)
+                .ok_or_else(|| format!("Unknown option '--{}'", name))?;
+            let value = match (spec.takes_value, inline) {
+                (true, Some(value)) => parse_value(&value),
+                (true, None) => parse_value(rest.next().ok_or_else(|| missing_value(spec))?),
+                (false, Some(_)) => return Err(format!("Option '--{}' does not take a value", name)),
+                (false, None) => ParsedValue::Bool(true),
+            };
+            options.insert(spec.name.clone(), value);
+        } else if let Some(shorts) = arg.strip_prefix('-').filter(|shorts| !shorts.is_empty()) {
+            for (i, c) in shorts.char_indices() {
+                let spec = specs
+                    .iter()
+                    .find(|spec| spec.short == Some(c))
+                    .ok_or_else(|| format!("Unknown option '-{}'", c))?;
+                if !spec.takes_value {
+                    options.insert(spec.name.clone(), ParsedValue::Bool(true));
+                    continue;
+                }
+                // The rest of the word is the value, or else the next argument is
+                let attached = &shorts[i + c.len_utf8()..];
+                let value = if attached.is_empty() {
+                    rest.next().ok_or_else(|| missing_value(spec))?.as_str()
+                } else {
+                    attached
+                };
+                options.insert(spec.name.clone(), parse_value(value));
+                break;
+            }
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+
+    Ok(ParsedOptions { options, positional })
+}
+
+fn missing_value(spec: &OptionSpec) -> String {
+    format!("Option '--{}' needs a value", spec.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_option_specs() {
+        let spec = OptionSpec::parse("output|o:").unwrap();
+        assert_eq!((spec.name.as_str(), spec.short, spec.takes_value), ("output", Some('o'), true));
+        assert_eq!(OptionSpec::parse("v").unwrap().short, Some('v'));
+        assert_eq!(OptionSpec::parse("dry-run").unwrap().short, None);
+        assert!(OptionSpec::parse("out|oo").is_err());
+        assert!(OptionSpec::parse("--verbose").is_err());
+    }
+
+    #[test]
+    fn test_getopt_forms() {
+        let specs: Vec<OptionSpec> = ["verbose|v", "quiet|q", "output|o:", "count|n:", "format:"]
+            .iter()
+            .map(|spec| OptionSpec::parse(spec).unwrap())
+            .collect();
+
+        let parsed = getopt(&specs, &strings(&["-vq", "in.txt", "-n5", "--output", "out.txt", "--", "-x"])).unwrap();
+        assert_eq!(parsed.options["verbose"], ParsedValue::Bool(true));
+        assert_eq!(parsed.options["quiet"], ParsedValue::Bool(true));
+        assert_eq!(parsed.options["count"], ParsedValue::Int(5));
+        assert_eq!(parsed.options["output"], ParsedValue::String("out.txt".to_string()));
+        assert_eq!(parsed.options["format"], ParsedValue::Null);
+        assert_eq!(parsed.positional, ["in.txt", "-x"]);
+
+        let parsed = getopt(&specs, &strings(&["--format=json", "-o", "-", "-"])).unwrap();
+        assert_eq!(parsed.options["format"], ParsedValue::String("json".to_string()));
+        assert_eq!(parsed.options["output"], ParsedValue::String("-".to_string()));
+        assert_eq!(parsed.options["verbose"], ParsedValue::Bool(false));
+        assert_eq!(parsed.positional, ["-"]);
+
+        assert_eq!(getopt(&specs, &strings(&["-x"])).unwrap_err(), "Unknown option '-x'");
+        assert_eq!(getopt(&specs, &strings(&["--output"])).unwrap_err(), "Option '--output' needs a value");
+        assert!(getopt(&specs, &strings(&["--verbose=yes"])).is_err());
+    }
+}
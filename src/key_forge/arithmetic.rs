@@ -1,16 +1,113 @@
-use super::key_forge::{get_variable_store, ParsedValue};
+use std::fmt;
+
+use super::ast::ArithOp;
+use super::error::KeyForgeError;
+use super::key_forge::{ParsedValue, Variables};
+
+/// Result of an arithmetic expression. Operations on two ints stay ints (checked for
+/// overflow); as soon as one side is a float the result is a float.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    /// Reads a number from command output or a string variable.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        text.parse::<i64>()
+            .map(Number::Int)
+            .or_else(|_| text.parse::<f64>().map(Number::Float))
+            .ok()
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(v) => v as f64,
+            Number::Float(v) => v,
+        }
+    }
+
+    fn is_zero(self) -> bool {
+        match self {
+            Number::Int(v) => v == 0,
+            Number::Float(v) => v == 0.0,
+        }
+    }
+
+    pub fn negate(self) -> Result<Self, KeyForgeError> {
+        match self {
+            Number::Int(v) => v.checked_neg().map(Number::Int).ok_or_else(|| KeyForgeError::runtime("Integer overflow in negation")),
+            Number::Float(v) => Ok(Number::Float(-v)),
+        }
+    }
+
+    pub fn apply(op: ArithOp, left: Self, right: Self) -> Result<Self, KeyForgeError> {
+        if matches!(op, ArithOp::Div | ArithOp::Rem) && right.is_zero() {
+            return Err(KeyForgeError::runtime(if op == ArithOp::Div { "Division by zero" } else { "Modulo by zero" }));
+        }
+
+        let (a, b) = match (left, right) {
+            (Number::Int(a), Number::Int(b)) => (a, b),
+            _ => {
+                let (a, b) = (left.as_f64(), right.as_f64());
+                return Ok(Number::Float(match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => a / b,
+                    ArithOp::Rem => a % b,
+                    ArithOp::Pow => a.powf(b),
+                }));
+            }
+        };
+
+        let result = match op {
+            ArithOp::Add => a.checked_add(b),
+            ArithOp::Sub => a.checked_sub(b),
+            ArithOp::Mul => a.checked_mul(b),
+            // Integer division truncates, like the `div` command
+            ArithOp::Div => a.checked_div(b),
+            ArithOp::Rem => a.checked_rem(b),
+            ArithOp::Pow if b < 0 => return Ok(Number::Float((a as f64).powf(b as f64))),
+            ArithOp::Pow => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        };
+
+        result
+            .map(Number::Int)
+            .ok_or_else(|| KeyForgeError::runtime(format!("Integer overflow in {} {} {}", a, op.symbol(), b)))
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(v) => write!(f, "{}", v),
+            Number::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<Number> for ParsedValue {
+    fn from(number: Number) -> Self {
+        match number {
+            Number::Int(v) => ParsedValue::Int(v),
+            Number::Float(v) => ParsedValue::Float(v),
+        }
+    }
+}
 
 // Helper function for arithmetic operations
-pub fn perform_arithmetic(operation: &str, var_name: &str, value: ParsedValue) -> Result<(), String> {
-    let mut store = get_variable_store().lock().unwrap();
-    
+pub fn perform_arithmetic(store: &mut Variables, operation: &str, var_name: &str, value: ParsedValue) -> Result<(), KeyForgeError> {
     match operation {
         
         "add" => {
             match value {
                 ParsedValue::Int(iv) => {
                     if let Ok(val) = store.get_int_data(var_name) {
-                        store.add_data_to_int(var_name.to_string(), val + iv);
+                        let result = val.checked_add(iv).ok_or_else(|| KeyForgeError::runtime("Integer overflow"))?;
+                        store.add_data_to_int(var_name.to_string(), result);
                         return Ok(());
                     }
                     if let Ok(val) = store.get_float_data(var_name) {
@@ -38,18 +135,29 @@ pub fn perform_arithmetic(operation: &str, var_name: &str, value: ParsedValue) -
                         return Ok(());
                     }
                 }
+                ParsedValue::Bytes(bytes) => {
+                    if let Ok(mut val) = store.get_bytes_data(var_name) {
+                        val.extend(bytes);
+                        store.set_value(var_name.to_string(), ParsedValue::Bytes(val));
+                        return Ok(());
+                    }
+                }
                 ParsedValue::Array(_) | ParsedValue::Dictionary(_) => {
-                    return Err(format!("Cannot add array or dictionary to '{}'", var_name));
+                    return Err(KeyForgeError::type_error(format!("Cannot add array or dictionary to '{}'", var_name)));
+                }
+                ParsedValue::Bool(_) | ParsedValue::Null => {
+                    return Err(KeyForgeError::type_error(format!("Cannot add a boolean or null to '{}'", var_name)));
                 }
             }
-            Err(format!("Variable {} not found or incompatible type", var_name))
+            Err(store.wrong_type(var_name, "compatible with the added value"))
         }
         
         "sub" => {
             match value {
                 ParsedValue::Int(iv) => {
                     if let Ok(val) = store.get_int_data(var_name) {
-                        store.add_data_to_int(var_name.to_string(), val - iv);
+                        let result = val.checked_sub(iv).ok_or_else(|| KeyForgeError::runtime("Integer overflow"))?;
+                        store.add_data_to_int(var_name.to_string(), result);
                         return Ok(());
                     }
                     if let Ok(val) = store.get_float_data(var_name) {
@@ -67,16 +175,17 @@ pub fn perform_arithmetic(operation: &str, var_name: &str, value: ParsedValue) -
                         return Ok(());
                     }
                 }
-                _ => return Err("Cannot subtract non-numeric value".to_string()),
+                _ => return Err(KeyForgeError::type_error("Cannot subtract non-numeric value")),
             }
-            Err(format!("Variable {} not found or not a number", var_name))
+            Err(store.wrong_type(var_name, "a number"))
         }
         
         "mul" => {
             match value {
                 ParsedValue::Int(iv) => {
                     if let Ok(val) = store.get_int_data(var_name) {
-                        store.add_data_to_int(var_name.to_string(), val * iv);
+                        let result = val.checked_mul(iv).ok_or_else(|| KeyForgeError::runtime("Integer overflow"))?;
+                        store.add_data_to_int(var_name.to_string(), result);
                         return Ok(());
                     }
                     if let Ok(val) = store.get_float_data(var_name) {
@@ -94,19 +203,20 @@ pub fn perform_arithmetic(operation: &str, var_name: &str, value: ParsedValue) -
                         return Ok(());
                     }
                 }
-                _ => return Err("Cannot multiply by non-numeric value".to_string()),
+                _ => return Err(KeyForgeError::type_error("Cannot multiply by non-numeric value")),
             }
-            Err(format!("Variable {} not found or not a number", var_name))
+            Err(store.wrong_type(var_name, "a number"))
         }
         
         "div" => {
             match value {
                 ParsedValue::Int(iv) => {
                     if iv == 0 {
-                        return Err("Division by zero".to_string());
+                        return Err(KeyForgeError::runtime("Division by zero"));
                     }
                     if let Ok(val) = store.get_int_data(var_name) {
-                        store.add_data_to_int(var_name.to_string(), val / iv);
+                        let result = val.checked_div(iv).ok_or_else(|| KeyForgeError::runtime("Integer overflow"))?;
+                        store.add_data_to_int(var_name.to_string(), result);
                         return Ok(());
                     }
                     if let Ok(val) = store.get_float_data(var_name) {
@@ -116,7 +226,7 @@ pub fn perform_arithmetic(operation: &str, var_name: &str, value: ParsedValue) -
                 }
                 ParsedValue::Float(fv) => {
                     if fv == 0.0 {
-                        return Err("Division by zero".to_string());
+                        return Err(KeyForgeError::runtime("Division by zero"));
                     }
                     if let Ok(val) = store.get_int_data(var_name) {
                         store.add_data_to_float(var_name.to_string(), val as f64 / fv);
@@ -127,10 +237,54 @@ pub fn perform_arithmetic(operation: &str, var_name: &str, value: ParsedValue) -
                         return Ok(());
                     }
                 }
-                _ => return Err("Cannot divide by non-numeric value".to_string()),
+                _ => return Err(KeyForgeError::type_error("Cannot divide by non-numeric value")),
             }
-            Err(format!("Variable {} not found or not a number", var_name))
+            Err(store.wrong_type(var_name, "a number"))
         }
-        _ => Err(format!("Unknown operation: {}", operation)),
+        _ => Err(KeyForgeError::runtime(format!("Unknown operation: {}", operation))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_promotion_and_errors() {
+        assert_eq!(Number::apply(ArithOp::Div, Number::Int(7), Number::Int(2)), Ok(Number::Int(3)));
+        assert_eq!(Number::apply(ArithOp::Div, Number::Int(7), Number::Float(2.0)), Ok(Number::Float(3.5)));
+        assert_eq!(Number::apply(ArithOp::Rem, Number::Int(-7), Number::Int(3)), Ok(Number::Int(-1)));
+        assert_eq!(Number::apply(ArithOp::Pow, Number::Int(2), Number::Int(10)), Ok(Number::Int(1024)));
+        assert_eq!(Number::apply(ArithOp::Pow, Number::Int(2), Number::Int(-1)), Ok(Number::Float(0.5)));
+
+        assert!(Number::apply(ArithOp::Div, Number::Int(1), Number::Int(0)).is_err());
+        assert!(Number::apply(ArithOp::Div, Number::Float(1.0), Number::Float(0.0)).is_err());
+        assert!(Number::apply(ArithOp::Rem, Number::Int(1), Number::Int(0)).is_err());
+        assert!(Number::apply(ArithOp::Mul, Number::Int(i64::MAX), Number::Int(2)).is_err());
+    }
+
+    #[test]
+    fn test_perform_arithmetic_checks_integer_overflow() {
+        let cases = [
+            ("add", i64::MAX, 1),
+            ("sub", i64::MIN, 1),
+            ("mul", i64::MAX, 2),
+            ("mul", i64::MIN, -1),
+            ("div", i64::MIN, -1),
+        ];
+        for (operation, start, operand) in cases {
+            let mut store = Variables::new();
+            store.add_data_to_int("n".to_string(), start);
+            let err = perform_arithmetic(&mut store, operation, "n", ParsedValue::Int(operand)).unwrap_err();
+            assert_eq!(err.message(), "Integer overflow", "{} {} {}", operation, start, operand);
+            assert_eq!(store.get_int_data("n"), Ok(start));
+        }
+
+        let mut store = Variables::new();
+        store.add_data_to_int("n".to_string(), i64::MAX - 1);
+        perform_arithmetic(&mut store, "add", "n", ParsedValue::Int(1)).unwrap();
+        perform_arithmetic(&mut store, "div", "n", ParsedValue::Int(-1)).unwrap();
+        perform_arithmetic(&mut store, "sub", "n", ParsedValue::Int(1)).unwrap();
+        assert_eq!(store.get_int_data("n"), Ok(i64::MIN));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// Location of a piece of source text. `start`/`end` are byte offsets, `line`/`column` are
+/// 1-based and point at `start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+/// One word of a statement with its quotes removed. `$name` and `$(command)` are kept verbatim
+/// so command handlers can resolve them the way they always have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    pub quoted: bool,
+    pub span: Span,
+}
+
+impl Word {
+    /// True when the word is the bare (unquoted) keyword `keyword`.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        !self.quoted && self.text == keyword
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub span: Span,
+    /// True for `{ ... }` blocks, which get their own variable scope. Inline bodies such as
+    /// `then print x` run in the enclosing scope.
+    pub scoped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    /// A regular command such as `set x 10`; the first word names the command.
+    Command(Vec<Word>),
+    /// A standalone `{ ... }` block
+    Block(Block),
+    If {
+        condition: Expr,
+        then_branch: Block,
+        else_branch: Option<Block>,
+    },
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    /// `for i in 0..10 step 2`, `for item in $array` or `for key, value in $dict`
+    For {
+        /// One or two loop variable names
+        variables: Vec<String>,
+        /// A `start..end` range or the value to iterate over
+        iterable: Word,
+        step: Option<Word>,
+        body: Block,
+    },
+    /// `fn name(a, b) { ... }`; the definition is registered when the statement runs.
+    Function(Rc<FunctionDef>),
+    /// `return [value...]`
+    Return(Vec<Word>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    /// Operand made of one or more words, joined with single spaces when evaluated.
+    Words(Vec<Word>),
+    Int(i64),
+    Float(f64),
+    /// `$name` inside an arithmetic expression
+    Variable(String),
+    /// `$(command)` inside an arithmetic expression; its output must be a number
+    Substitution(String),
+    Negate(Box<Expr>),
+    Arithmetic {
+        op: ArithOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Compare {
+        op: CompareOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Logical {
+        op: LogicalOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+impl Expr {
+    /// True for nodes that compute something, as opposed to plain operands and literals.
+    pub fn has_operator(&self) -> bool {
+        match &self.kind {
+            ExprKind::Negate(operand) => !matches!(operand.kind, ExprKind::Int(_) | ExprKind::Float(_)),
+            ExprKind::Arithmetic { .. } | ExprKind::Compare { .. } | ExprKind::Logical { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// True if a variable or command substitution appears anywhere in the expression.
+    pub fn has_reference(&self) -> bool {
+        match &self.kind {
+            ExprKind::Variable(_) | ExprKind::Substitution(_) => true,
+            ExprKind::Negate(operand) => operand.has_reference(),
+            ExprKind::Arithmetic { left, right, .. }
+            | ExprKind::Compare { left, right, .. }
+            | ExprKind::Logical { left, right, .. } => left.has_reference() || right.has_reference(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+impl ArithOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Pow => "**",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+}
+
+impl CompareOp {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "==" | "eq" => Some(Self::Eq),
+            "!=" | "ne" => Some(Self::Ne),
+            ">" | "gt" => Some(Self::Gt),
+            "<" | "lt" => Some(Self::Lt),
+            ">=" | "ge" => Some(Self::Ge),
+            "<=" | "le" => Some(Self::Le),
+            _ => None,
+        }
+    }
+
+    /// Compares numerically when both sides parse as numbers, otherwise as strings.
+    pub fn apply(self, left: &str, right: &str) -> bool {
+        // Integers are compared exactly; as f64 they would lose precision past 2^53
+        if let (Ok(left_int), Ok(right_int)) = (left.parse::<i64>(), right.parse::<i64>()) {
+            return match self {
+                Self::Eq => left_int == right_int,
+                Self::Ne => left_int != right_int,
+                Self::Gt => left_int > right_int,
+                Self::Lt => left_int < right_int,
+                Self::Ge => left_int >= right_int,
+                Self::Le => left_int <= right_int,
+            };
+        }
+        if let (Ok(left_num), Ok(right_num)) = (left.parse::<f64>(), right.parse::<f64>()) {
+            match self {
+                Self::Eq => (left_num - right_num).abs() < f64::EPSILON,
+                Self::Ne => (left_num - right_num).abs() > f64::EPSILON,
+                Self::Gt => left_num > right_num,
+                Self::Lt => left_num < right_num,
+                Self::Ge => left_num >= right_num,
+                Self::Le => left_num <= right_num,
+            }
+        } else {
+            match self {
+                Self::Eq => left == right,
+                Self::Ne => left != right,
+                Self::Gt => left > right,
+                Self::Lt => left < right,
+                Self::Ge => left >= right,
+                Self::Le => left <= right,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+/// Syntax error found while parsing, pointing at the offending source text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,141 @@
+use base64::engine::general_purpose::{STANDARD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use data_encoding::{BASE32, BASE32_NOPAD};
+
+use super::key_forge::ParsedValue;
+
+/// Text encodings for binary data, as used by the `*_encode`/`*_decode` commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Lower-case hex; decoding also takes upper case and a `0x` prefix
+    Hex,
+    /// RFC 4648 base32; decoding takes input with or without `=` padding
+    Base32 { padding: bool },
+    /// Bitcoin alphabet, no padding
+    Base58,
+    /// RFC 4648 standard alphabet with padding
+    Base64,
+    /// RFC 4648 URL-safe alphabet; encodes without padding, decodes either way
+    Base64Url,
+}
+
+impl Encoding {
+    /// The encoding a `<name>_encode` or `<name>_decode` command works with.
+    pub fn from_command(command: &str) -> Option<Self> {
+        let name = command.strip_suffix("_encode").or_else(|| command.strip_suffix("_decode"))?;
+        Self::from_name(name)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(Self::Hex),
+            "base32" => Some(Self::Base32 { padding: true }),
+            "base58" => Some(Self::Base58),
+            "base64" => Some(Self::Base64),
+            "base64url" => Some(Self::Base64Url),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Base32 { .. } => "base32",
+            Self::Base58 => "base58",
+            Self::Base64 => "base64",
+            Self::Base64Url => "base64url",
+        }
+    }
+
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Hex => to_hex(bytes),
+            Self::Base32 { padding: true } => BASE32.encode(bytes),
+            Self::Base32 { padding: false } => BASE32_NOPAD.encode(bytes),
+            Self::Base58 => bs58::encode(bytes).into_string(),
+            Self::Base64 => STANDARD.encode(bytes),
+            Self::Base64Url => URL_SAFE_NO_PAD.encode(bytes),
+        }
+    }
+
+    pub fn decode(self, text: &str) -> Result<Vec<u8>, String> {
+        let text = text.trim();
+        let decoded = match self {
+            Self::Hex => from_hex(text.strip_prefix("0x").unwrap_or(text)),
+            Self::Base32 { .. } => BASE32_NOPAD
+                .decode(text.trim_end_matches('=').to_ascii_uppercase().as_bytes())
+                .map_err(|e| e.to_string()),
+            Self::Base58 => bs58::decode(text).into_vec().map_err(|e| e.to_string()),
+            Self::Base64 => STANDARD.decode(text).map_err(|e| e.to_string()),
+            Self::Base64Url if text.ends_with('=') => URL_SAFE.decode(text).map_err(|e| e.to_string()),
+            Self::Base64Url => URL_SAFE_NO_PAD.decode(text).map_err(|e| e.to_string()),
+        };
+        decoded.map_err(|e| format!("Invalid {} input: {}", self.name(), e))
+    }
+}
+
+/// Lower-case hex digits of `bytes`, without a prefix.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes hex digits of either case. Whitespace, signs and prefixes are rejected; callers
+/// strip what their syntax allows first.
+pub fn from_hex(input: &str) -> Result<Vec<u8>, String> {
+    if input.len() % 2 != 0 {
+        return Err("Hex string must have an even number of digits".to_string());
+    }
+    if !input.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex string '{}'", input));
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// Decoded data as a value: a string when it is valid UTF-8, bytes otherwise.
+pub fn bytes_to_value(bytes: Vec<u8>) -> ParsedValue {
+    match String::from_utf8(bytes) {
+        Ok(text) => ParsedValue::String(text),
+        Err(e) => ParsedValue::Bytes(e.into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc4648_and_bitcoin_vectors() {
+        let cases = [
+            (Encoding::Hex, "foobar", "666f6f626172"),
+            (Encoding::Base32 { padding: true }, "fooba", "MZXW6YTB"),
+            (Encoding::Base32 { padding: true }, "foob", "MZXW6YQ="),
+            (Encoding::Base32 { padding: false }, "foob", "MZXW6YQ"),
+            (Encoding::Base58, "Hello World!", "2NEpo7TZRRrLZSi2U"),
+            (Encoding::Base64, "fo", "Zm8="),
+            (Encoding::Base64Url, "fo", "Zm8"),
+        ];
+        for (encoding, plain, encoded) in cases {
+            assert_eq!(encoding.encode(plain.as_bytes()), encoded, "{:?}", encoding);
+            assert_eq!(encoding.decode(encoded).unwrap(), plain.as_bytes(), "{:?}", encoding);
+        }
+
+        // Padding is optional when decoding, and URL-safe output never needs escaping
+        assert_eq!(Encoding::Base32 { padding: false }.decode("MZXW6YQ=").unwrap(), b"foob");
+        assert_eq!(Encoding::Base64Url.encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(Encoding::Base58.encode(&[0, 0, 1]), "112");
+        assert!(Encoding::Base58.decode("0OIl").is_err());
+        assert_eq!(Encoding::Hex.decode("0xDEAD").unwrap(), vec![0xde, 0xad]);
+        assert!(from_hex("+f+f").is_err());
+        assert!(from_hex("abc").is_err());
+    }
+
+    #[test]
+    fn test_non_utf8_decodes_to_bytes() {
+        assert_eq!(bytes_to_value(vec![0xff, 0x00]), ParsedValue::Bytes(vec![0xff, 0x00]));
+        assert_eq!(bytes_to_value(b"key".to_vec()), ParsedValue::String("key".to_string()));
+    }
+}
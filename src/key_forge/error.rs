@@ -0,0 +1,345 @@
+use std::fmt;
+use std::fs;
+
+use super::ast::{ParseError, Span};
+
+/// Error raised while parsing or running a KeyForge script. The variant tells embedders what
+/// went wrong; the context says where, as precisely as it is known. The context is boxed to
+/// keep `Result`s small.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyForgeError {
+    /// Syntax error in a script, REPL line or included file
+    Parse { message: String, context: Box<ErrorContext> },
+    /// A value has the wrong type, e.g. a string where a number is needed
+    Type { message: String, context: Box<ErrorContext> },
+    /// A variable that does not exist was read
+    UndefinedVariable {
+        name: String,
+        message: String,
+        context: Box<ErrorContext>,
+    },
+    /// Reading or writing a file failed
+    Io { message: String, context: Box<ErrorContext> },
+    /// A command or function was called with the wrong number of arguments
+    Arity { message: String, context: Box<ErrorContext> },
+    /// Anything else that fails while a script runs, such as division by zero
+    Runtime { message: String, context: Box<ErrorContext> },
+    /// `exit` or `quit` ran. Not a failure: it unwinds the script like an error so the host
+    /// decides what the code means, e.g. main.rs ends the process with it
+    Exit {
+        code: i32,
+        message: String,
+        context: Box<ErrorContext>,
+    },
+}
+
+/// Where an error happened: the file (`None` for REPL input and command line arguments), the
+/// statement or syntax element, and the function calls and included files that led there,
+/// innermost first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorContext {
+    pub file: Option<String>,
+    pub span: Option<Span>,
+    pub stack: Vec<Frame>,
+}
+
+/// One function call or `execute_file` the error passed through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// File of the calling statement, `None` for REPL input and command line arguments
+    pub file: Option<String>,
+    /// The calling statement; `None` until the error reaches it
+    pub span: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameKind {
+    Function(String),
+    Include(String),
+}
+
+impl KeyForgeError {
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::Parse {
+            message: message.into(),
+            context: Box::default(),
+        }
+    }
+
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self::Type {
+            message: message.into(),
+            context: Box::default(),
+        }
+    }
+
+    pub fn undefined(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Self::UndefinedVariable {
+            message: format!("Variable '{}' not found", name),
+            name,
+            context: Box::default(),
+        }
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        Self::Io {
+            message: message.into(),
+            context: Box::default(),
+        }
+    }
+
+    pub fn arity(message: impl Into<String>) -> Self {
+        Self::Arity {
+            message: message.into(),
+            context: Box::default(),
+        }
+    }
+
+    pub fn runtime(message: impl Into<String>) -> Self {
+        Self::Runtime {
+            message: message.into(),
+            context: Box::default(),
+        }
+    }
+
+    pub fn exit(code: i32) -> Self {
+        Self::Exit {
+            code,
+            message: format!("Program exit with code {}", code),
+            context: Box::default(),
+        }
+    }
+
+    /// The code passed to `exit`, `None` for real errors.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::Exit { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Error description without the location.
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Parse { message, .. }
+            | Self::Type { message, .. }
+            | Self::UndefinedVariable { message, .. }
+            | Self::Io { message, .. }
+            | Self::Arity { message, .. }
+            | Self::Runtime { message, .. }
+            | Self::Exit { message, .. } => message,
+        }
+    }
+
+    pub fn context(&self) -> &ErrorContext {
+        match self {
+            Self::Parse { context, .. }
+            | Self::Type { context, .. }
+            | Self::UndefinedVariable { context, .. }
+            | Self::Io { context, .. }
+            | Self::Arity { context, .. }
+            | Self::Runtime { context, .. }
+            | Self::Exit { context, .. } => context,
+        }
+    }
+
+    pub fn context_mut(&mut self) -> &mut ErrorContext {
+        match self {
+            Self::Parse { context, .. }
+            | Self::Type { context, .. }
+            | Self::UndefinedVariable { context, .. }
+            | Self::Io { context, .. }
+            | Self::Arity { context, .. }
+            | Self::Runtime { context, .. }
+            | Self::Exit { context, .. } => context,
+        }
+    }
+
+    /// Prepends `prefix` to the message, keeping the variant and context.
+    pub fn prefixed(mut self, prefix: &str) -> Self {
+        match &mut self {
+            Self::Parse { message, .. }
+            | Self::Type { message, .. }
+            | Self::UndefinedVariable { message, .. }
+            | Self::Io { message, .. }
+            | Self::Arity { message, .. }
+            | Self::Runtime { message, .. } => *message = format!("{}: {}", prefix, message),
+            Self::Exit { .. } => {}
+        }
+        self
+    }
+
+    /// Records the statement the error happened in, unless a more precise location is already
+    /// known. Once the location is set, the statement completes the innermost pending frame
+    /// instead, i.e. it is the call site of the function or include the error came out of.
+    pub fn locate(mut self, file: Option<&str>, span: Span) -> Self {
+        let context = self.context_mut();
+        if context.span.is_none() {
+            context.span = Some(span);
+            context.file = file.map(str::to_string);
+        } else if let Some(frame) = context.stack.iter_mut().rev().find(|frame| frame.span.is_none()) {
+            frame.span = Some(span);
+            frame.file = file.map(str::to_string);
+        }
+        self
+    }
+
+    /// Adds a function call or include to the stack. Its call site is filled in by `locate`.
+    pub fn push_frame(mut self, kind: FrameKind) -> Self {
+        // A frame that never got a call site (e.g. a call made from `$(...)`) is dropped
+        // rather than shown without a location
+        let context = self.context_mut();
+        if context.span.is_none() {
+            return self;
+        }
+        context.stack.retain(|frame| frame.span.is_some());
+        context.stack.push(Frame {
+            kind,
+            file: None,
+            span: None,
+        });
+        self
+    }
+
+    /// Drops the location and stack, for errors whose positions refer to text that is not part
+    /// of any script (such as the command inside `$(...)`).
+    pub fn without_location(mut self) -> Self {
+        *self.context_mut() = ErrorContext::default();
+        self
+    }
+
+    /// Multi-line description with the offending line and a caret under the failing part.
+    /// `input` is the source to quote when the error did not happen in a file (a REPL line
+    /// or the joined command line arguments); files are read again from disk.
+    pub fn render(&self, input: Option<&str>) -> String {
+        let context = self.context();
+        let mut out = format!("error: {}", self.message());
+
+        if let Some(span) = context.span {
+            let location = match &context.file {
+                Some(file) => format!("{}:{}:{}", file, span.line, span.column),
+                None => format!("line {}, column {}", span.line, span.column),
+            };
+            out.push_str(&format!("\n --> {}", location));
+
+            let source = match &context.file {
+                Some(file) => fs::read_to_string(file).ok(),
+                None => input.map(str::to_string),
+            };
+            if let Some(snippet) = source.as_deref().and_then(|source| snippet(source, span)) {
+                out.push_str(&snippet);
+            }
+        }
+
+        // Recursion repeats the same frame up to the call depth limit; show each run once
+        let mut frames = context.stack.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let (what, verb) = match &frame.kind {
+                FrameKind::Function(name) => (format!("function '{}'", name), "called"),
+                FrameKind::Include(file) => (format!("file '{}'", file), "included"),
+            };
+            let location = match (frame.span, &frame.file) {
+                (Some(span), Some(file)) => format!(" at {}:{}:{}", file, span.line, span.column),
+                (Some(span), None) => format!(" at line {}, column {}", span.line, span.column),
+                (None, _) => String::new(),
+            };
+            out.push_str(&format!("\n  = in {} {}{}", what, verb, location));
+
+            let mut repeats = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                let plural = if repeats == 1 { "" } else { "s" };
+                out.push_str(&format!("\n  = ... {} more frame{} of {}", repeats, plural, what));
+            }
+        }
+
+        out
+    }
+}
+
+/// The line holding `span.start` with a gutter, and carets under the part covered by the span.
+fn snippet(source: &str, span: Span) -> Option<String> {
+    let line = source.lines().nth(span.line.checked_sub(1)?)?;
+    let line_start = line.as_ptr() as usize - source.as_ptr() as usize;
+    let start = span.start.checked_sub(line_start)?.min(line.len());
+    let end = span.end.saturating_sub(line_start).clamp(start, line.len());
+
+    let indent = line.get(..start)?.chars().count();
+    let width = line.get(start..end).map_or(0, |text| text.chars().count()).max(1);
+    let gutter = " ".repeat(span.line.to_string().len());
+
+    Some(format!(
+        "\n{gutter} |\n{number} | {line}\n{gutter} | {pad}{carets}",
+        number = span.line,
+        pad = " ".repeat(indent),
+        carets = "^".repeat(width),
+    ))
+}
+
+impl fmt::Display for KeyForgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = self.context();
+        match (context.span, &context.file) {
+            (Some(span), Some(file)) => write!(f, "{}:{}:{}: {}", file, span.line, span.column, self.message()),
+            (Some(span), None) => write!(f, "line {}, column {}: {}", span.line, span.column, self.message()),
+            (None, _) => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for KeyForgeError {}
+
+impl From<ParseError> for KeyForgeError {
+    fn from(error: ParseError) -> Self {
+        Self::Parse {
+            message: error.message,
+            context: Box::new(ErrorContext {
+                span: Some(error.span),
+                ..ErrorContext::default()
+            }),
+        }
+    }
+}
+
+impl From<std::io::Error> for KeyForgeError {
+    fn from(error: std::io::Error) -> Self {
+        Self::io(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_fills_innermost_location_then_call_sites() {
+        let inner = Span { start: 4, end: 12, line: 2, column: 3 };
+        let call = Span { start: 20, end: 25, line: 5, column: 1 };
+
+        let error = KeyForgeError::undefined("x")
+            .locate(Some("lib.kf"), inner)
+            .push_frame(FrameKind::Function("f".to_string()))
+            .locate(Some("main.kf"), call);
+
+        assert!(matches!(&error, KeyForgeError::UndefinedVariable { name, .. } if name == "x"));
+        assert_eq!(error.context().span, Some(inner));
+        assert_eq!(error.context().stack[0].span, Some(call));
+        assert_eq!(error.to_string(), "lib.kf:2:3: Variable 'x' not found");
+    }
+
+    #[test]
+    fn test_render_points_at_span() {
+        let source = "set a 1\n  print $missing\n";
+        let span = Span { start: 10, end: 24, line: 2, column: 3 };
+        let rendered = KeyForgeError::undefined("missing").locate(None, span).render(Some(source));
+
+        assert_eq!(
+            rendered,
+            "error: Variable 'missing' not found\n --> line 2, column 3\n  |\n2 |   print $missing\n  |   ^^^^^^^^^^^^^^"
+        );
+    }
+}
@@ -2,1359 +2,1506 @@ use crate::key_forge::arithmetic::perform_arithmetic;
 use crate::key_forge::help;
 use clear_screen::clear;
 use colored::Colorize;
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use std::sync::MutexGuard;
 
-use super::arithmetic;
+use super::error::{FrameKind, KeyForgeError};
+use super::interpreter::Interpreter;
+use super::parser::parse_arithmetic;
+use super::hashing;
+use super::encoding::{self, Encoding};
+use super::identifiers;
+use super::json;
+use super::password;
+use super::tokens::{self, Checksum};
+use super::arguments::{self, OptionSpec};
+use super::state::{self, LoadOptions, StateFormat};
 use super::{
     //expression,
     key_forge::{
         get_random_char, get_random_num,
-        get_variable_store, is_valid_identifier, load_state_from_file, parse_value,
-        resolve_filename, resolve_to_string, save_state_to_file,
-        value_to_string, input_mode, setters, base64, utils, ParsedValue, Variables,
+        is_positional, is_valid_identifier, is_variable_reference, parse_value,
+        resolve_filename, resolve_to_string, store_parsed_value,
+        substitute_variables_in_string, value_to_string, input_mode, setters, utils,
+        ParsedValue, VariableFilter,
     },
 };
 
-pub fn execute_command(args: &[String], capture_output: bool) -> Result<String, String> {
-    if args.is_empty() {
-        return Ok(String::new());
-    }
-
-    match args[0].as_str() {
-        "//" => Ok(String::new()),
+/// Names handled by `execute_command` itself. User functions cannot reuse them.
+pub const BUILTIN_COMMANDS: &[&str] = &[
+    "add", "base32_decode", "base32_encode", "base58_decode", "base58_encode", "base64_decode",
+    "base64_encode", "base64url_decode", "base64url_encode", "break", "clear", "command_list",
+    "continue", "div", "execute_file", "exit", "gen_bytes", "gen_password", "gen_token", "get",
+    "get_random_char", "get_random_num", "getopt", "global", "help", "hex_decode", "hex_encode",
+    "hmac_sign", "hmac_verify", "json_get", "json_parse", "json_stringify", "keys", "len",
+    "load_state", "mul", "multi_arg_command", "num_to_string", "pop", "print", "push",
+    "push_to_string_back", "quit", "read_file", "remove_string_char", "repeat", "rm",
+    "save_state", "seed", "set", "sha224", "sha256", "sha512", "sub", "to_file", "type_of",
+    "uuid_nil", "uuid_parse", "uuid_v4", "uuid_v7", "values", "verify_token", "vl",
+    "write_file",
+];
+
+impl Interpreter {
+    pub fn execute_command(&mut self, args: &[String], capture_output: bool) -> Result<String, KeyForgeError> {
+        if args.is_empty() {
+            return Ok(String::new());
+        }
 
-        "get_random_num" => {
-            if args.len() != 3 {
-                return Err(format!("Usage: get_random_num <min> <max>"));
-            }
+        match args[0].as_str() {
+            "//" => Ok(String::new()),
 
-            // Try parsing as int
-            if let (Ok(min), Ok(max)) = (args[1].parse::<i32>(), args[2].parse::<i32>()) {
-                if min >= max {
-                    return Err("min must be less than max".to_string());
-                }
-                let n: i32 = get_random_num(min, max);
-                return if capture_output {
-                    Ok(n.to_string())
-                } else {
-                    println!("{}", n);
-                    Ok(String::new())
+            "seed" => {
+                let message = match &args[1..] {
+                    [] => match self.rng.seed() {
+                        Some(seed) => format!("seed {}", seed),
+                        None => "os".to_string(),
+                    },
+                    [reset] if reset == "reset" => {
+                        self.set_seed(None);
+                        "Random commands use OS entropy again".to_string()
+                    }
+                    [value] => {
+                        let seed = resolve_to_string(&self.variables, value)?
+                            .parse::<u64>()
+                            .map_err(|_| KeyForgeError::type_error(format!("Seed must be an unsigned 64-bit integer, got '{}'", value)))?;
+                        self.set_seed(Some(seed));
+                        format!("Random commands are seeded with {}; their output is reproducible, not secret", seed)
+                    }
+                    _ => return Err(KeyForgeError::arity("Usage: seed [<u64>|reset]")),
                 };
-            }
 
-            // Try parsing as floats
-            if let (Ok(min), Ok(max)) = (args[1].parse::<f64>(), args[2].parse::<f64>()) {
-                if min >= max {
-                    return Err("min must be less than max".to_string());
-                }
-                let n: f64 = get_random_num(min, max);
-                return if capture_output {
-                    Ok(n.to_string())
+                if capture_output {
+                    // Only the query has a result worth capturing
+                    Ok(if args.len() == 1 { message } else { String::new() })
                 } else {
-                    println!("{}", n);
+                    self.write_line(&message)?;
                     Ok(String::new())
-                };
+                }
             }
 
-            Err("Arguments must be numbers (integers or floats)".to_string())
-        }
-
-        "get_random_char" => {
-            let mode = if args.len() == 2 {
-                args[1].parse::<i32>().unwrap_or(0)
-            } else {
-                0
-            };
+            "get_random_num" => {
+                if args.len() != 3 {
+                    return Err(KeyForgeError::arity("Usage: get_random_num <min> <max>"));
+                }
 
-            match get_random_char(mode) {
-                Ok(c) => {
-                    if capture_output {
-                        Ok(c.to_string())
+                // Try parsing as int
+                if let (Ok(min), Ok(max)) = (args[1].parse::<i64>(), args[2].parse::<i64>()) {
+                    if min >= max {
+                        return Err(KeyForgeError::runtime("min must be less than max"));
+                    }
+                    let n: i64 = get_random_num(&mut self.rng, min, max);
+                    return if capture_output {
+                        Ok(n.to_string())
                     } else {
-                        println!("{}", c);
+                        self.write_line(&n)?;
                         Ok(String::new())
+                    };
+                }
+
+                // Try parsing as floats
+                if let (Ok(min), Ok(max)) = (args[1].parse::<f64>(), args[2].parse::<f64>()) {
+                    if min >= max {
+                        return Err(KeyForgeError::runtime("min must be less than max"));
                     }
+                    let n: f64 = get_random_num(&mut self.rng, min, max);
+                    return if capture_output {
+                        Ok(n.to_string())
+                    } else {
+                        self.write_line(&n)?;
+                        Ok(String::new())
+                    };
                 }
-                Err(e) => Err(e),
+
+                Err(KeyForgeError::type_error("Arguments must be numbers (integers or floats)"))
             }
-        }
 
-        "quit" | "exit" if !capture_output => {
-            if args.len() >= 2 {
-                match args[1].parse::<i32>() {
-                    Ok(exit_code) => {
-                        println!(
-                            "{}",
-                            format!("Program exit with code {}", exit_code)
-                                .green()
-                                .bold()
-                        );
-                        std::process::exit(exit_code);
-                    }
-                    Err(_) => {
-                        println!("{}", "Program exit with code 0".green().bold());
-                        std::process::exit(0);
+            "get_random_char" => {
+                let mode = if args.len() == 2 {
+                    args[1].parse::<i32>().unwrap_or(0)
+                } else {
+                    0
+                };
+
+                match get_random_char(&mut self.rng, mode) {
+                    Ok(c) => {
+                        if capture_output {
+                            Ok(c.to_string())
+                        } else {
+                            self.write_line(&c)?;
+                            Ok(String::new())
+                        }
                     }
+                    Err(e) => Err(e),
                 }
             }
-            println!("{}", "Program exit with code 0".green().bold());
-            std::process::exit(0);
-        }
 
-        "help" if !capture_output => {
-            if args.len() >= 2 {
-                help::show_command_help(&args[1]);
-            } else {
-                help::show_all_help();
+            "gen_password" => {
+                // Variable references are resolved so length and option values can come from variables
+                let mut option_args = Vec::with_capacity(args.len() - 1);
+                for arg in &args[1..] {
+                    if arg.starts_with('$') && is_variable_reference(&arg[1..]) {
+                        option_args.push(resolve_to_string(&self.variables, arg)?);
+                    } else {
+                        option_args.push(arg.clone());
+                    }
+                }
+
+                // Every failure comes from the arguments: an unknown option, a bad length or --min
+                let options = password::PasswordOptions::from_args(&option_args).map_err(KeyForgeError::arity)?;
+                let generated = password::generate_password(&options, self.secret_rng()).map_err(KeyForgeError::arity)?;
+
+                if capture_output {
+                    Ok(generated)
+                } else {
+                    self.write_line(&generated)?;
+                    Ok(String::new())
+                }
             }
-            Ok(String::new())
-        }
 
-        "command_list" if !capture_output => {
-            help::show_command_list();
-            Ok(String::new())
-        }
+            "gen_bytes" => {
+                const USAGE: &str = "Usage: gen_bytes <n> [hex|base64|base64url|bytes]";
+                let (count, format) = match &args[1..] {
+                    [count] => (count, "hex"),
+                    [count, format] => (count, format.as_str()),
+                    _ => return Err(KeyForgeError::arity(USAGE)),
+                };
+                let count: usize = resolve_to_string(&self.variables, count)?
+                    .parse()
+                    .map_err(|_| KeyForgeError::type_error(format!("Byte count must be a positive integer, got '{}'", count)))?;
+
+                let bytes = tokens::random_bytes(count, self.secret_rng()).map_err(KeyForgeError::arity)?;
+                let generated = match format {
+                    "bytes" => value_to_string(&ParsedValue::Bytes(bytes)),
+                    "hex" | "base64" | "base64url" => Encoding::from_name(format).expect("known encoding").encode(&bytes),
+                    _ => return Err(KeyForgeError::runtime(format!("Unknown format '{}' ({})", format, USAGE))),
+                };
 
-        "repeat" => {
-            if args.len() < 3 {
-                return Err("Usage: repeat <count> <command...>".to_string());
+                if capture_output {
+                    Ok(generated)
+                } else {
+                    self.write_line(&generated)?;
+                    Ok(String::new())
+                }
             }
 
-            // Parse count - can be variable, command substitution, or direct number
-            let count_raw = &args[1];
-            let count = if count_raw.starts_with("$(") && count_raw.ends_with(')') {
-                // Handle command substitution for count
-                let command_content = &count_raw[2..count_raw.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-
-                match execute_command(&command_args, true) {
-                    Ok(output) => output.trim().parse::<usize>().map_err(|_| {
-                        "Command output must be a valid positive integer".to_string()
-                    })?,
-                    Err(e) => return Err(format!("Error executing count command: {}", e)),
-                }
-            } else if count_raw.starts_with('$') && is_valid_identifier(&count_raw[1..]) {
-                // Handle variable for count
-                let store = get_variable_store().lock().unwrap();
-                let var_name = &count_raw[1..];
-
-                if let Ok(int_val) = store.get_int_data(var_name) {
-                    if int_val < 0 {
-                        return Err("Count cannot be negative".to_string());
+            "gen_token" => {
+                const USAGE: &str = "Usage: gen_token <prefix> <n> [--checksum crc32|sha256]";
+                let (prefix, length, checksum) = match &args[1..] {
+                    [prefix, length] => (prefix, length, None),
+                    [prefix, length, flag, name] if flag == "--checksum" => {
+                        (prefix, length, Some(Checksum::from_name(name).map_err(KeyForgeError::arity)?))
                     }
-                    int_val as usize
+                    _ => return Err(KeyForgeError::arity(USAGE)),
+                };
+                let prefix = resolve_to_string(&self.variables, prefix)?;
+                let length: usize = resolve_to_string(&self.variables, length)?
+                    .parse()
+                    .map_err(|_| KeyForgeError::type_error(format!("Token length must be a positive integer, got '{}'", length)))?;
+
+                let token = tokens::generate_token(&prefix, length, checksum, self.secret_rng()).map_err(KeyForgeError::arity)?;
+
+                if capture_output {
+                    Ok(token)
                 } else {
-                    return Err(format!(
-                        "Variable '{}' not found or not an integer",
-                        var_name
-                    ));
+                    self.write_line(&token)?;
+                    Ok(String::new())
                 }
-            } else {
-                // Handle direct number
-                count_raw
-                    .parse::<usize>()
-                    .map_err(|_| "Count must be a valid positive integer".to_string())?
-            };
+            }
 
-            let raw_command = args[2..].join(" ");
+            "verify_token" => {
+                if args.len() != 3 {
+                    return Err(KeyForgeError::arity("Usage: verify_token <token|$var> <crc32|sha256>"));
+                }
 
-            let mut results = Vec::new();
+                let token = self.resolve_input(&args[1])?;
+                let valid = tokens::verify_token(&token, Checksum::from_name(&args[2]).map_err(KeyForgeError::arity)?);
+                let result = valid.to_string();
 
-            if raw_command.starts_with("$(") && raw_command.ends_with(')') {
-                let command_content = &raw_command[2..raw_command.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+                if capture_output {
+                    Ok(result)
+                } else {
+                    self.write_line(&result)?;
+                    Ok(String::new())
+                }
+            }
 
-                for _ in 0..count {
-                    match execute_command(&command_args, true) {
-                        Ok(res) => {
-                            if capture_output {
-                                if !res.is_empty() {
-                                    results.push(res);
-                                }
-                            } else {
-                                if !res.is_empty() {
-                                    println!("{}", res);
-                                }
-                            }
-                        }
-                        Err(e) => return Err(format!("Error executing inner command: {}", e)),
-                    }
+            "sha224" | "sha256" | "sha512" => {
+                let usage = format!("Usage: {} <text|$var|$(command)> [--hex|--base64] OR {} --file <filename> [--hex|--base64]", args[0], args[0]);
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity(usage));
                 }
-            } else {
-                // Execute the command directly (not as substitution)
-                let command_args: Vec<String> = input_mode::tokenize_input(&raw_command);
 
-                for _ in 0..count {
-                    match execute_command(&command_args, true) {
-                        Ok(res) => {
-                            if capture_output {
-                                if !res.is_empty() {
-                                    results.push(res);
-                                }
-                            } else {
-                                if !res.is_empty() {
-                                    println!("{}", res);
-                                }
-                            }
-                        }
-                        Err(e) => return Err(format!("Error executing inner command: {}", e)),
+                let algorithm = hashing::HashAlgorithm::from_name(&args[0]).map_err(KeyForgeError::arity)?;
+                let mut encoding = hashing::DigestEncoding::Hex;
+                let mut from_file = false;
+                let mut input_parts: Vec<&str> = Vec::new();
+
+                for arg in &args[1..] {
+                    match arg.as_str() {
+                        "--file" => from_file = true,
+                        "--hex" | "--base64" => encoding = hashing::DigestEncoding::from_name(arg).map_err(KeyForgeError::arity)?,
+                        other => input_parts.push(other),
                     }
                 }
-            }
 
-            if capture_output {
-                Ok(results.join("\n"))
-            } else {
-                Ok(String::new())
-            }
-        }
+                if input_parts.is_empty() {
+                    return Err(KeyForgeError::arity(usage));
+                }
+                let raw_input = input_parts.join(" ");
 
-        "set" => {
-            if args.len() < 3 {
-                return Err("Usage: set <name> <value> OR set <collection_name> <key/index> <value>".to_string());
-            }
+                let data: Vec<u8> = if from_file {
+                    let filename = resolve_filename(self, &raw_input)?;
+                    std::fs::read(&filename)
+                        .map_err(|e| KeyForgeError::io(format!("Failed to read file '{}': {}", filename, e)))?
+                } else {
+                    self.resolve_binary_input(&raw_input)?
+                };
 
-            // Decide whether this is a simple assignment (set name <value...>) or
-            // a collection element assignment (set collection key value).
-            // Tokenization can split array literals like "[1, 2, 3]" into multiple
-            // args (e.g. "[1,", "2,", "3]"). To support array/dict literals
-            // and quoted values, treat any value that starts with '[', '{', '"', '\''
-            // or a command substitution '$(...' as a single value and join the
-            // remaining args into the value string. Otherwise, when there are 4+
-            // args we assume the user intended to set a collection element.
-
-            let third = args[2].as_str();
-
-            let is_literal_start = third.starts_with('[')
-                || third.starts_with('{')
-                || third.starts_with('"')
-                || third.starts_with('\'')
-                || (third.starts_with("$(") && third.ends_with(')'))
-                || third.starts_with("$(");
-
-            if args.len() == 3 || is_literal_start {
-                let name = args[1].clone();
-                let raw_value = args[2..].join(" ");
+                let digest = encoding.encode(&algorithm.digest(&data));
 
-                // Use expression evaluator for complex values including arrays
-                match crate::key_forge::expression::evaluate_expression(&raw_value) {
-                    Ok(parsed_value) => {
-                        crate::key_forge::key_forge::store_parsed_value(name, parsed_value, None)?;
-                        Ok(String::new())
-                    }
-                    Err(e) => Err(format!("Error evaluating expression: {}", e)),
+                if capture_output {
+                    Ok(digest)
+                } else {
+                    self.write_line(&digest)?;
+                    Ok(String::new())
                 }
-            } else {
-                setters::set_collection_element(&args)
-            }
-        }
-                
-        "rm" => {
-            let mut store = get_variable_store().lock().unwrap();
-            let k = &args[1];
-            
-            if store.int_variables.contains_key(k) {
-                store.remove_int_data(k);
-                return Ok(String::new());
             }
 
-            if store.float_variables.contains_key(k) {
-                store.remove_float_data(k);
-                return Ok(String::new());
-            }
+            "hmac_sign" | "hmac_verify" => {
+                let is_sign = args[0] == "hmac_sign";
+                let expected_args = if is_sign { 4 } else { 5 };
+                if args.len() < expected_args {
+                    return Err(KeyForgeError::arity(if is_sign {
+                        "Usage: hmac_sign <sha224|sha256|sha512> <key> <message> [--hex|--base64]"
+                    } else {
+                        "Usage: hmac_verify <sha224|sha256|sha512> <key> <message> <tag>"
+                    }));
+                }
 
-            if store.string_variables.contains_key(k) {
-                store.remove_string_data(k);
-                return Ok(String::new());
+                let algorithm = hashing::HashAlgorithm::from_name(&args[1]).map_err(KeyForgeError::arity)?;
+                // A bytes variable is the key itself; text may name an encoding with hex: or base64:
+                let key = if self.is_bytes_input(&args[2]) {
+                    self.resolve_binary_input(&args[2])?
+                } else {
+                    hashing::decode_key(&self.resolve_input(&args[2])?).map_err(KeyForgeError::type_error)?
+                };
+                let message = self.resolve_binary_input(&args[3])?;
+
+                let result = if is_sign {
+                    let encoding = match args.get(4) {
+                        Some(flag) => hashing::DigestEncoding::from_name(flag).map_err(KeyForgeError::arity)?,
+                        None => hashing::DigestEncoding::Hex,
+                    };
+                    encoding.encode(&algorithm.hmac_sign(&key, &message))
+                } else {
+                    let tag = if self.is_bytes_input(&args[4]) {
+                        self.resolve_binary_input(&args[4])?
+                    } else {
+                        hashing::decode_tag(&self.resolve_input(&args[4])?).map_err(KeyForgeError::type_error)?
+                    };
+                    algorithm.hmac_verify(&key, &message, &tag).to_string()
+                };
+
+                if capture_output {
+                    Ok(result)
+                } else {
+                    self.write_line(&result)?;
+                    Ok(String::new())
+                }
             }
 
-            Err(format!("Variable {} not found", k))
-        }
+            "uuid_v4" | "uuid_v7" | "uuid_nil" => {
+                let id = match args[0].as_str() {
+                    "uuid_v4" => identifiers::uuid_v4(&mut self.rng),
+                    "uuid_v7" => {
+                        // A seeded session counts up from a fixed time so its UUIDs are reproducible
+                        let now = match self.rng.seed() {
+                            Some(_) => identifiers::SEEDED_V7_MILLIS,
+                            None => identifiers::now_millis(),
+                        };
+                        identifiers::uuid_v7(&mut self.uuid_clock, &mut self.rng, now)
+                    }
+                    _ => identifiers::uuid_nil(),
+                };
 
-        "print" => {
-            if args.len() < 2 {
-                return Err("Usage: print <name or literal>".to_string());
+                if capture_output {
+                    Ok(id)
+                } else {
+                    self.write_line(&id)?;
+                    Ok(String::new())
+                }
             }
 
-            let raw_value = args[1..].join(" ");
+            "uuid_parse" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: uuid_parse <uuid|$var>"));
+                }
 
-            if raw_value.starts_with("$(") && raw_value.ends_with(')') {
-                let command_content = &raw_value[2..raw_value.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+                let info = identifiers::uuid_parse(&self.resolve_input(&args[1])?).map_err(KeyForgeError::type_error)?;
+                let result = value_to_string(&info);
 
-                match execute_command(&command_args, capture_output) {
-                    Ok(result) => {
-                        if capture_output {
-                            return Ok(result);
-                        } else {
-                            println!("{}", result);
-                            return Ok(String::new());
-                        }
-                    }
-                    Err(e) => return Err(format!("Error executing command: {}", e)),
+                if capture_output {
+                    Ok(result)
+                } else {
+                    self.write_line(&result)?;
+                    Ok(String::new())
                 }
             }
 
-            // Substitute variables in the string before printing
-            let substituted_string = super::key_forge::substitute_variables_in_string(&raw_value);
+            "quit" | "exit" if !capture_output => {
+                // A missing or unreadable code means a normal exit
+                let exit_code = args.get(1).and_then(|code| code.parse::<i32>().ok()).unwrap_or(0);
+                self.write_line(&format!("Program exit with code {}", exit_code).green().bold())?;
+                Err(KeyForgeError::exit(exit_code))
+            }
 
-            // Use the substituted string instead of raw_value
-            if capture_output {
-                Ok(substituted_string)
-            } else {
-                println!("{}", substituted_string);
+            "help" if !capture_output => {
+                if args.len() >= 2 {
+                    help::show_command_help(&mut self.output, &args[1])?;
+                } else {
+                    help::show_all_help(&mut self.output)?;
+                }
                 Ok(String::new())
             }
-        }
 
-        "execute_file" => {
-            if args.len() < 2 {
-                return Err(format!("Usage: execute_file <filename>"));
+            "command_list" if !capture_output => {
+                help::show_command_list(&mut self.output)?;
+                Ok(String::new())
             }
 
-            input_mode::file_mode(&args[1]);
-            Ok(String::new())
-        }
+            "repeat" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: repeat <count> <command...>"));
+                }
 
-        "vl" => {
-            let mode: &str = if args.len() >= 2 {
-                args[1].as_str()
-            } else {
-                ""
-            };
-            let store: MutexGuard<'_, Variables> = get_variable_store().lock().unwrap();
-
-            if capture_output {
-                use std::fmt::Write;
-                let mut output: String = String::new();
-
-                fn collect_section<T: std::fmt::Display>(
-                    output: &mut String,
-                    title: &str,
-                    vars: &HashMap<String, T>,
-                    suffix: &str,
-                ) {
-                    writeln!(output, "{}", title).unwrap();
-                    for (k, v) in vars {
-                        writeln!(output, "{}: {}{}", k, v, suffix).unwrap();
+                // Parse count - can be variable, command substitution, or direct number
+                let count_raw = &args[1];
+                let count = if count_raw.starts_with("$(") && count_raw.ends_with(')') {
+                    // Handle command substitution for count
+                    let command_content = &count_raw[2..count_raw.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    match self.execute_command(&command_args, true) {
+                        Ok(output) => output.trim().parse::<usize>().map_err(|_| {
+                            KeyForgeError::type_error("Command output must be a valid positive integer")
+                        })?,
+                        Err(e) => return Err(e.prefixed("Error executing count command")),
                     }
-                    writeln!(output).unwrap();
-                }
-
-                match mode {
-                    "i" => collect_section(
-                        &mut output,
-                        "=== Integer Variables (i32) ===",
-                        &store.int_variables,
-                        " (i32)",
-                    ),
-                    "f" => collect_section(
-                        &mut output,
-                        "=== Float Variables (f64) ===",
-                        &store.float_variables,
-                        " (f64)",
-                    ),
-                    "s" => collect_section(
-                        &mut output,
-                        "=== String Variables (String) ===",
-                        &store.string_variables,
-                        " (String)",
-                    ),
-                    _ => {
-                        collect_section(
-                            &mut output,
-                            "=== Integer Variables (i32) ===",
-                            &store.int_variables,
-                            " (i32)",
-                        );
-                        collect_section(
-                            &mut output,
-                            "=== Float Variables (f64) ===",
-                            &store.float_variables,
-                            " (f64)",
-                        );
-                        collect_section(
-                            &mut output,
-                            "=== String Variables (String) ===",
-                            &store.string_variables,
-                            " (String)",
-                        );
+                } else if count_raw.starts_with('$') && is_variable_reference(&count_raw[1..]) {
+                    // Handle variable for count
+                    let store = &self.variables;
+                    let var_name = &count_raw[1..];
+
+                    let int_val = store.get_int_data(var_name)?;
+                    if int_val < 0 {
+                        return Err(KeyForgeError::runtime("Count cannot be negative"));
                     }
+                    int_val as usize
+                } else {
+                    // Handle direct number
+                    count_raw
+                        .parse::<usize>()
+                        .map_err(|_| KeyForgeError::type_error("Count must be a valid positive integer"))?
+                };
+
+                let raw_command = args[2..].join(" ");
+
+                let mut results = Vec::new();
+
+                if raw_command.starts_with("$(") && raw_command.ends_with(')') {
+                    let command_content = &raw_command[2..raw_command.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    for _ in 0..count {
+                        match self.execute_command(&command_args, true) {
+                            Ok(res) => {
+                                if capture_output {
+                                    if !res.is_empty() {
+                                        results.push(res);
+                                    }
+                                } else {
+                                    if !res.is_empty() {
+                                        self.write_line(&res)?;
+                                    }
+                                }
+                            }
+                            Err(e) => return Err(e.prefixed("Error executing inner command")),
+                        }
+                    }
+                } else {
+                    // Execute the command directly (not as substitution)
+                    let command_args: Vec<String> = input_mode::tokenize_input(&raw_command);
+
+                    for _ in 0..count {
+                        match self.execute_command(&command_args, true) {
+                            Ok(res) => {
+                                if capture_output {
+                                    if !res.is_empty() {
+                                        results.push(res);
+                                    }
+                                } else {
+                                    if !res.is_empty() {
+                                        self.write_line(&res)?;
+                                    }
+                                }
+                            }
+                            Err(e) => return Err(e.prefixed("Error executing inner command")),
+                        }
+                    }
+                }
+
+                if capture_output {
+                    Ok(results.join("\n"))
+                } else {
+                    Ok(String::new())
+                }
+            }
+
+            "getopt" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: getopt <dict_name> [name|n[:]...]"));
+                }
+                if !is_valid_identifier(&args[1]) {
+                    return Err(KeyForgeError::runtime(format!("Invalid variable name: {}", args[1])));
                 }
 
-                Ok(output)
-            } else {
-                store.vl(mode);
+                let mut specs = Vec::with_capacity(args.len() - 2);
+                for raw in &args[2..] {
+                    specs.push(OptionSpec::parse(&self.resolve_input(raw)?).map_err(KeyForgeError::parse)?);
+                }
+                let parsed = arguments::getopt(&specs, &self.script_args).map_err(KeyForgeError::arity)?;
+                self.variables.set_value(args[1].clone(), ParsedValue::Dictionary(parsed.options));
+                // Like `set -- $(getopt ...)` in a shell, the non-option arguments become $1..$N
+                self.set_positional_arguments(parsed.positional);
                 Ok(String::new())
             }
-        }
 
-        "to_file" => {
-            if args.len() < 3 {
-                return Err("Usage: to_file <filename> <command...>".to_string());
+            "global" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: global <name> [value]"));
+                }
+                if !is_valid_identifier(&args[1]) {
+                    return Err(KeyForgeError::runtime(format!("Invalid variable name: {}", args[1])));
+                }
+
+                self.variables.declare_global(&args[1])?;
+                if args.len() > 2 {
+                    let mut set_args = vec!["set".to_string()];
+                    set_args.extend_from_slice(&args[1..]);
+                    self.execute_command(&set_args, capture_output)?;
+                }
+                Ok(String::new())
             }
 
-            let filename = &args[1];
-            let command_args = &args[2..];
+            "set" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: set <name> <value> OR set <collection_name> <key/index> <value>"));
+                }
+                if is_positional(&args[1]) {
+                    return Err(KeyForgeError::runtime(format!("'${}' is a script argument and cannot be assigned", args[1])));
+                }
 
-            // Handle command substitution
-            let raw_command = command_args.join(" ");
-            if raw_command.starts_with("$(") && raw_command.ends_with(')') {
-                let command_content = &raw_command[2..raw_command.len() - 1];
-                let inner_command_args: Vec<String> = input_mode::tokenize_input(command_content);
+                // Decide whether this is a simple assignment (set name <value...>) or
+                // a collection element assignment (set collection key value).
+                // Tokenization can split array literals like "[1, 2, 3]" into multiple
+                // args (e.g. "[1,", "2,", "3]"). To support array/dict literals
+                // and quoted values, treat any value that starts with '[', '{', '"', '\''
+                // or a command substitution '$(...' as a single value and join the
+                // remaining args into the value string. Otherwise, when there are 4+
+                // args we assume the user intended to set a collection element.
 
-                match execute_command(&inner_command_args, true) {
-                    Ok(output) => {
-                        use std::fs::OpenOptions;
+                let third = args[2].as_str();
+                let raw_value = args[2..].join(" ");
 
-                        let mut file: File = OpenOptions::new()
-                            .create(true)
-                            .append(true)
-                            .open(filename)
-                            .map_err(|e| format!("Error opening file '{}': {}", filename, e))?;
+                let is_literal_start = third.starts_with('[')
+                    || third.starts_with('{')
+                    || third.starts_with('"')
+                    || third.starts_with('\'')
+                    || (third.starts_with("$(") && third.ends_with(')'))
+                    || third.starts_with("$(");
+
+                // `set z $x * 2` is arithmetic unless z is an existing array or dictionary,
+                // in which case `set z <key> <value>` assigns an element
+                let is_collection = self.variables.get_array_data(&args[1]).is_ok()
+                    || self.variables.get_dict_data(&args[1]).is_ok();
+                let is_arithmetic = !is_collection && parse_arithmetic(&raw_value).is_some();
+
+                if args.len() == 3 || is_literal_start || is_arithmetic {
+                    let name = args[1].clone();
+
+                    // Use expression evaluator for complex values including arrays
+                    match self.evaluate_expression(&raw_value) {
+                        Ok(parsed_value) => {
+                            store_parsed_value(&mut self.variables, name, parsed_value)?;
+                            Ok(String::new())
+                        }
+                        Err(e) => Err(e.prefixed("Error evaluating expression")),
+                    }
+                } else {
+                    setters::set_collection_element(self, args)
+                }
+            }
 
-                        writeln!(file, "{}", output)
-                            .map_err(|e| format!("Error writing to file '{}': {}", filename, e))?;
+            "rm" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: rm <name...>"));
+                }
 
-                        Ok(String::new())
+                for name in &args[1..] {
+                    let name = name.strip_prefix('$').unwrap_or(name);
+                    if self.variables.remove(name).is_none() {
+                        return Err(KeyForgeError::undefined(name));
                     }
-                    Err(e) => return Err(format!("Error executing inner command: {}", e)),
                 }
-            } else {
-                // If it's not a command substitution, execute the command directly
-                match execute_command(command_args, true) {
-                    Ok(output) => {
-                        use std::fs::OpenOptions;
+                Ok(String::new())
+            }
 
-                        let mut file = OpenOptions::new()
-                            .create(true)
-                            .append(true)
-                            .open(filename)
-                            .map_err(|e| format!("Error opening file '{}': {}", filename, e))?;
+            "type_of" => {
+                if args.len() != 2 {
+                    return Err(KeyForgeError::arity("Usage: type_of <name>"));
+                }
 
-                        writeln!(file, "{}", output)
-                            .map_err(|e| format!("Error writing to file '{}': {}", filename, e))?;
+                let name = args[1].strip_prefix('$').unwrap_or(&args[1]);
+                let type_name = self
+                    .variables
+                    .lookup(name)
+                    .map(ParsedValue::type_name)
+                    .ok_or_else(|| KeyForgeError::undefined(name))?;
 
-                        Ok(String::new())
-                    }
-                    Err(e) => return Err(format!("Error executing command: {}", e)),
+                if capture_output {
+                    Ok(type_name.to_string())
+                } else {
+                    self.write_line(type_name)?;
+                    Ok(String::new())
                 }
             }
-        }
 
-        "add" | "sub" | "mul" | "div" => {
-            let command_args = &args[2..];
-            let raw_command = command_args.join(" ");
+            "print" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: print <name or literal>"));
+                }
+
+                let raw_value = args[1..].join(" ");
 
-            if raw_command.starts_with("$(") && raw_command.ends_with(')') {
-                let command_content = &raw_command[2..raw_command.len() - 1];
-                let inner_command_args: Vec<String> = input_mode::tokenize_input(command_content);
+                if raw_value.starts_with("$(") && raw_value.ends_with(')') {
+                    let command_content = &raw_value[2..raw_value.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
 
-                match execute_command(&inner_command_args, true) {
-                    Ok(output) => {
-                        let parsed_value = parse_value(&output);
-                        arithmetic::perform_arithmetic(&args[0], &args[1], parsed_value)?;
-                        Ok(String::new())
+                    match self.execute_command(&command_args, capture_output) {
+                        Ok(result) => {
+                            if capture_output {
+                                return Ok(result);
+                            } else {
+                                self.write_line(&result)?;
+                                return Ok(String::new());
+                            }
+                        }
+                        Err(e) => return Err(e.prefixed("Error executing command")),
                     }
-                    Err(e) => Err(format!("Error executing inner command: {}", e)),
-                }
-            } else {
-                // Parse the value - it could be a direct value or a variable name
-                let parsed_value = if is_valid_identifier(&raw_command) {
-                    // Try to get value from variable
-                    let store = get_variable_store().lock().unwrap();
-                    if store.has_variable(&raw_command) {
-                        // Get the value from the variable
-                        if let Ok(int_val) = store.get_int_data(&raw_command) {
-                            ParsedValue::Int(int_val)
-                        } else if let Ok(float_val) = store.get_float_data(&raw_command) {
-                            ParsedValue::Float(float_val)
-                        } else if let Ok(string_val) = store.get_string_data(&raw_command) {
-                            ParsedValue::String(string_val)
+                }
+
+                // Substitute variables in the string before printing
+                let substituted_string = substitute_variables_in_string(&self.variables, &raw_value);
+
+                // Use the substituted string instead of raw_value
+                if capture_output {
+                    Ok(substituted_string)
+                } else {
+                    self.write_line(&substituted_string)?;
+                    Ok(String::new())
+                }
+            }
+
+            "execute_file" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: execute_file <filename> [arg...]"));
+                }
+
+                // Words after the file name become its arguments; without any it shares ours
+                let script_args = if args.len() > 2 {
+                    let mut script_args = Vec::with_capacity(args.len() - 2);
+                    for arg in &args[2..] {
+                        if arg.starts_with('$') && is_variable_reference(&arg[1..]) {
+                            script_args.push(resolve_to_string(&self.variables, arg)?);
                         } else {
-                            parse_value(&raw_command)
+                            script_args.push(arg.clone());
                         }
-                    } else {
-                        parse_value(&raw_command)
                     }
+                    Some(script_args)
                 } else {
-                    parse_value(&raw_command)
+                    None
                 };
 
-                perform_arithmetic(&args[0], &args[1], parsed_value)?;
-                Ok(String::new())
+                self.with_arguments(&args[1], script_args, |session| session.execute_file(&args[1], capture_output))
+                    .map_err(|e| e.push_frame(FrameKind::Include(args[1].clone())))
             }
-        }
 
-        "num_to_string" => {
-            if args.len() < 2 {
-                return Err("Usage: num_to_string <source>".to_string());
+            "vl" => {
+                let filter = VariableFilter::parse(&args[1..])?;
+
+                if capture_output {
+                    let mut output = Vec::new();
+                    self.variables.vl(&filter, &mut output)?;
+                    Ok(String::from_utf8_lossy(&output).into_owned())
+                } else {
+                    self.variables.vl(&filter, &mut self.output)?;
+                    Ok(String::new())
+                }
             }
 
-            let raw_value = args[1..].join(" ").trim().to_string();
+            "to_file" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: to_file <filename> <command...>"));
+                }
+
+                let filename = &args[1];
+                let command_args = &args[2..];
 
-            let string_val = if raw_value.starts_with("$(") && raw_value.ends_with(')') {
                 // Handle command substitution
-                let command_content = &raw_value[2..raw_value.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-
-                match execute_command(&command_args, true) {
-                    Ok(result) => result,
-                    Err(e) => return Err(format!("Error executing command: {}", e)),
-                }
-            } else {
-                // Handle direct value or variable reference
-                resolve_to_string(&raw_value)?
-            };
-
-            let string_val = utils::wrap_string(&string_val, '"');
-            
-            return if capture_output {
-                Ok(string_val)
-            } else {
-                println!("num_to_string: {}", string_val);
-                Ok(String::new())
-            };
-        }
+                let raw_command = command_args.join(" ");
+                if raw_command.starts_with("$(") && raw_command.ends_with(')') {
+                    let command_content = &raw_command[2..raw_command.len() - 1];
+                    let inner_command_args: Vec<String> = input_mode::tokenize_input(command_content);
 
-        "push_to_string_back" => {
-            if args.len() < 3 {
-                return Err("Usage: push_to_string_back <variable_name> <value>".to_string());
-            }
+                    match self.execute_command(&inner_command_args, true) {
+                        Ok(output) => {
+                            use std::fs::OpenOptions;
 
-            let var_name = &args[1];
-            let raw_value = args[2..].join(" ");
-            
-            // Handle command substitution
-            let value_to_push = if raw_value.starts_with("$(") && raw_value.ends_with(')') {
-                let command_content = &raw_value[2..raw_value.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-                
-                match execute_command(&command_args, true) {
-                    Ok(output) => output,
-                    Err(e) => return Err(format!("Error executing inner command: {}", e)),
-                }
-            } else {
-                // Check if it's a variable reference
-                if is_valid_identifier(&raw_value) {
-                    let store = get_variable_store().lock().unwrap();
-                    
-                    // Try to get value from existing variable
-                    if let Ok(int_val) = store.get_int_data(&raw_value) {
-                        int_val.to_string()
-                    } else if let Ok(float_val) = store.get_float_data(&raw_value) {
-                        float_val.to_string()
-                    } else if let Ok(string_val) = store.get_string_data(&raw_value) {
-                        string_val
-                    } else {
-                        // If variable doesn't exist, use the raw value
-                        raw_value
+                            let mut file: File = OpenOptions::new()
+                                .create(true)
+                                .append(true)
+                                .open(filename)
+                                .map_err(|e| KeyForgeError::io(format!("Error opening file '{}': {}", filename, e)))?;
+
+                            writeln!(file, "{}", output)
+                                .map_err(|e| KeyForgeError::io(format!("Error writing to file '{}': {}", filename, e)))?;
+
+                            Ok(String::new())
+                        }
+                        Err(e) => Err(e.prefixed("Error executing inner command")),
                     }
                 } else {
-                    // Direct value
-                    raw_value
-                }
-            };
-            
-            // Get the variable store and append to the string
-            let mut store = get_variable_store().lock().unwrap();
-            
-            if let Ok(current_value) = store.get_string_data(var_name) {
-                let new_value = current_value + &value_to_push;
-                store.add_data_to_string(var_name.to_string(), new_value);
-                Ok(String::new())
-            } else {
-                // If variable doesn't exist, create it with the value
-                store.add_data_to_string(var_name.to_string(), value_to_push);
-                Ok(String::new())
-            }
-        }
-        
-        "clear" => {
-            if capture_output {
-                return Err("Command 'clear' cannot be used in variable assignment".to_string());
-            }
-            
-            clear();
-            Ok(String::new())
-        }
-        
-        "if" => {
-            if args.len() < 4 {
-                return Err("Usage: if <condition> then <command> [else <command>]".to_string());
-            }
-            
-            // found index "then"
-            let then_index: usize = args
-                .iter()
-                .position(|arg| arg == "then")
-                .ok_or("Expected 'then' after condition".to_string())?;
-
-            let condition_parts = &args[1..then_index];
-            let condition = condition_parts.join(" ");
-
-            // check condition
-            let condition_result: bool = super::key_forge::evaluate_condition(&condition)?;
-
-            if condition_result {
-                // execute then part
-                let then_command_start: usize = then_index + 1;
-                let else_index = args.iter().position(|arg| arg == "else");
-
-                let then_args = if let Some(else_idx) = else_index {
-                    &args[then_command_start..else_idx]
-                } else {
-                    &args[then_command_start..]
-                };
+                    // If it's not a command substitution, execute the command directly
+                    match self.execute_command(command_args, true) {
+                        Ok(output) => {
+                            use std::fs::OpenOptions;
 
-                if !then_args.is_empty() {
-                    // Support block form: then { ... }
-                    let is_block = then_args[0] == "{";
-                    if is_block {
-                        // Extract block content (between braces) and parse into commands
-                        let block_content = if then_args.len() > 1 {
-                            then_args[1..then_args.len() - 1].join(" ")
-                        } else {
-                            String::new()
-                        };
-                        let commands = super::key_forge::parse_block_commands(block_content.trim());
-                        for cmd in &commands {
-                            let cmd_args = super::key_forge::input_mode::tokenize_input(cmd);
-                            execute_command(&cmd_args, capture_output)?;
+                            let mut file = OpenOptions::new()
+                                .create(true)
+                                .append(true)
+                                .open(filename)
+                                .map_err(|e| KeyForgeError::io(format!("Error opening file '{}': {}", filename, e)))?;
+
+                            writeln!(file, "{}", output)
+                                .map_err(|e| KeyForgeError::io(format!("Error writing to file '{}': {}", filename, e)))?;
+
+                            Ok(String::new())
                         }
-                    } else {
-                        execute_command(then_args, capture_output)?;
+                        Err(e) => Err(e.prefixed("Error executing command")),
                     }
                 }
-            } else {
-                // execute else part
-                if let Some(else_index) = args.iter().position(|arg| arg == "else") {
-                    let else_args = &args[else_index + 1..];
-                    if !else_args.is_empty() {
-                        // Support block form: else { ... }
-                        let is_block = else_args[0] == "{";
-                        if is_block {
-                            let block_content = if else_args.len() > 1 {
-                                else_args[1..else_args.len() - 1].join(" ")
+            }
+
+            "add" | "sub" | "mul" | "div" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity(format!("Usage: {} <var> <value>", args[0])));
+                }
+
+                let command_args = &args[2..];
+                let raw_command = command_args.join(" ");
+
+                if raw_command.starts_with("$(") && raw_command.ends_with(')') {
+                    let command_content = &raw_command[2..raw_command.len() - 1];
+                    let inner_command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    match self.execute_command(&inner_command_args, true) {
+                        Ok(output) => {
+                            let parsed_value = parse_value(&output);
+                            perform_arithmetic(&mut self.variables, &args[0], &args[1], parsed_value)?;
+                            Ok(String::new())
+                        }
+                        Err(e) => Err(e.prefixed("Error executing inner command")),
+                    }
+                } else {
+                    // Parse the value - it could be a direct value or a variable name
+                    let parsed_value = if is_valid_identifier(&raw_command) {
+                        // Try to get value from variable
+                        let store = &self.variables;
+                        if store.has_variable(&raw_command) {
+                            // Get the value from the variable
+                            if let Ok(int_val) = store.get_int_data(&raw_command) {
+                                ParsedValue::Int(int_val)
+                            } else if let Ok(float_val) = store.get_float_data(&raw_command) {
+                                ParsedValue::Float(float_val)
+                            } else if let Ok(string_val) = store.get_string_data(&raw_command) {
+                                ParsedValue::String(string_val)
                             } else {
-                                String::new()
-                            };
-                            let commands =
-                                super::key_forge::parse_block_commands(block_content.trim());
-                            for cmd in &commands {
-                                let cmd_args = super::key_forge::input_mode::tokenize_input(cmd);
-                                execute_command(&cmd_args, capture_output)?;
+                                parse_value(&raw_command)
                             }
                         } else {
-                            execute_command(else_args, capture_output)?;
+                            parse_value(&raw_command)
                         }
-                    }
-                }
-            }
-
-            Ok(String::new())
-        }
+                    } else {
+                        parse_value(&raw_command)
+                    };
 
-        "while" => {
-            if args.len() < 4 {
-                return Err("Usage: while <condition> do <command>".to_string());
+                    perform_arithmetic(&mut self.variables, &args[0], &args[1], parsed_value)?;
+                    Ok(String::new())
+                }
             }
 
-            let do_index: usize = args
-                .iter()
-                .position(|arg| arg == "do")
-                .ok_or("Expected 'do' after condition".to_string())?;
+            "num_to_string" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: num_to_string <source>"));
+                }
 
-            let condition_parts = &args[1..do_index];
-            let command_args = &args[do_index + 1..];
+                let raw_value = args[1..].join(" ").trim().to_string();
 
-            if command_args.is_empty() {
-                return Err("No command specified after 'do'".to_string());
-            }
+                let string_val = if raw_value.starts_with("$(") && raw_value.ends_with(')') {
+                    // Handle command substitution
+                    let command_content = &raw_value[2..raw_value.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
 
-            let is_block = !command_args.is_empty() && command_args[0] == "{";
-            let commands = if is_block {
-                let block_content = if command_args.len() > 1 {
-                    command_args[1..command_args.len() - 1].join(" ") // Remove the closing brace
+                    match self.execute_command(&command_args, true) {
+                        Ok(result) => result,
+                        Err(e) => return Err(e.prefixed("Error executing command")),
+                    }
                 } else {
-                    String::new()
+                    // Handle direct value or variable reference
+                    resolve_to_string(&self.variables, &raw_value)?
                 };
-                super::key_forge::parse_block_commands(&block_content)
-            } else {
-                vec![command_args.join(" ")]
-            };
 
-            let condition = condition_parts.join(" ");
+                let string_val = utils::wrap_string(&string_val, '"');
 
-            // Execute the while loop
-            loop {
-                // Check if we should break
-                if super::key_forge::should_break() {
-                    super::key_forge::reset_loop_flags();
-                    break;
+                if capture_output {
+                    Ok(string_val)
+                } else {
+                    self.write_line(&format!("num_to_string: {}", string_val))?;
+                    Ok(String::new())
                 }
+            }
 
-                // Check condition
-                let condition_result = super::key_forge::evaluate_condition(&condition)?;
-                if !condition_result {
-                    break;
+            "push_to_string_back" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: push_to_string_back <variable_name> <value>"));
                 }
 
-                // Reset continue flag at start of iteration
-                super::key_forge::set_continue_flag(false);
+                let var_name = &args[1];
+                let raw_value = args[2..].join(" ");
+
+                // Handle command substitution
+                let value_to_push = if raw_value.starts_with("$(") && raw_value.ends_with(')') {
+                    let command_content = &raw_value[2..raw_value.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
 
-                // Execute commands in the block
-                for cmd in &commands {
-                    // Check if we should break or continue
-                    if super::key_forge::should_break() {
-                        break;
+                    match self.execute_command(&command_args, true) {
+                        Ok(output) => output,
+                        Err(e) => return Err(e.prefixed("Error executing inner command")),
                     }
-                    if super::key_forge::should_continue() {
-                        break;
+                } else {
+                    // Check if it's a variable reference
+                    if is_valid_identifier(&raw_value) {
+                        let store = &self.variables;
+
+                        // Try to get value from existing variable
+                        if let Ok(int_val) = store.get_int_data(&raw_value) {
+                            int_val.to_string()
+                        } else if let Ok(float_val) = store.get_float_data(&raw_value) {
+                            float_val.to_string()
+                        } else if let Ok(string_val) = store.get_string_data(&raw_value) {
+                            string_val
+                        } else {
+                            // If variable doesn't exist, use the raw value
+                            raw_value
+                        }
+                    } else {
+                        // Direct value
+                        raw_value
                     }
+                };
 
-                    let cmd_args = super::key_forge::input_mode::tokenize_input(cmd);
-                    execute_command(&cmd_args, capture_output)?;
-                }
-
-                // If we hit a break, exit the loop
-                if super::key_forge::should_break() {
-                    super::key_forge::reset_loop_flags();
-                    break;
-                }
+                // Get the variable store and append to the string
+                let store = &mut self.variables;
 
-                // Reset continue flag for next iteration
-                if super::key_forge::should_continue() {
-                    super::key_forge::set_continue_flag(false);
+                if let Ok(current_value) = store.get_string_data(var_name) {
+                    let new_value = current_value + &value_to_push;
+                    store.add_data_to_string(var_name.to_string(), new_value);
+                    Ok(String::new())
+                } else {
+                    // If variable doesn't exist, create it with the value
+                    store.add_data_to_string(var_name.to_string(), value_to_push);
+                    Ok(String::new())
                 }
             }
 
-            Ok(String::new())
-        }
-
-        "for" => {
-            if args.len() < 5 {
-                return Err("Usage: for <variable> in <start>..<end> do <command>".to_string());
-            }
+            "clear" => {
+                if capture_output {
+                    return Err(KeyForgeError::runtime("Command 'clear' cannot be used in variable assignment"));
+                }
 
-            let var_name = &args[1];
-            if args[2] != "in" {
-                return Err("Expected 'in' after variable name".to_string());
+                clear();
+                Ok(String::new())
             }
 
-            let range_str = &args[3];
-            if !range_str.contains("..") {
-                return Err("Expected range in format start..end".to_string());
+            "if" | "while" | "for" | "fn" | "return" => {
+                // Control flow is parsed from source text; callers that already split the
+                // statement into words get it re-assembled and parsed here
+                let source: Vec<String> = args.iter().map(|arg| quote_word(arg)).collect();
+                self.execute_substitution(&source.join(" "), capture_output)
             }
 
-            // Find command start: prefer explicit 'do', otherwise look for '{', otherwise take remaining args
-            let command_args_slice: &[String] =
-                if let Some(do_idx) = args.iter().position(|a| a == "do") {
-                    &args[do_idx + 1..]
-                } else if let Some(brace_idx) = args.iter().position(|a| a == "{") {
-                    &args[brace_idx..]
+            "break" => {
+                if !self.in_loop() {
+                    Err(KeyForgeError::runtime("break outside of a loop"))
+                } else if !capture_output {
+                    self.set_break_flag(true);
+                    Ok(String::new())
                 } else {
-                    &args[4..]
-                };
-
-            let range_parts: Vec<&str> = range_str.split("..").collect();
-            if range_parts.len() != 2 {
-                return Err("Invalid range format. Use: start..end".to_string());
+                    Err(KeyForgeError::runtime("break cannot be used in variable assignment"))
+                }
             }
 
-            let start = range_parts[0]
-                .parse::<i32>()
-                .map_err(|_| "Start must be an integer".to_string())?;
-            let end = range_parts[1]
-                .parse::<i32>()
-                .map_err(|_| "End must be an integer".to_string())?;
-
-            let is_block = !command_args_slice.is_empty() && command_args_slice[0] == "{";
-            let commands = if is_block {
-                // Extract the content between braces
-                // command_args_slice starts with "{"
-                let block_content = if command_args_slice.len() > 1 {
-                    command_args_slice[1..command_args_slice.len() - 1].join(" ")
-                // Join all parts after "{" and before closing "}"
+            "continue" => {
+                if !self.in_loop() {
+                    Err(KeyForgeError::runtime("continue outside of a loop"))
+                } else if !capture_output {
+                    self.set_continue_flag(true);
+                    Ok(String::new())
                 } else {
-                    String::new()
-                };
-                super::key_forge::parse_block_commands(block_content.trim())
-            } else {
-                vec![command_args_slice.join(" ")]
-            };
-
-            for i in start..end {
-                let mut store = super::key_forge::get_variable_store().lock().unwrap();
-                store.add_data_to_int(var_name.to_string(), i);
-                drop(store);
-
-                for cmd in &commands {
-                    if super::key_forge::should_break() {
-                        super::key_forge::reset_loop_flags();
-                        return Ok(String::new());
-                    }
-                    if super::key_forge::should_continue() {
-                        break;
-                    }
+                    Err(KeyForgeError::runtime("continue cannot be used in variable assignment"))
+                }
+            }
 
-                    let cmd_args = super::key_forge::input_mode::tokenize_input(cmd);
-                    execute_command(&cmd_args, capture_output)?;
+            "save_state" => {
+                const USAGE: &str = "Usage: save_state <filename> [--format json|toml] [--encrypt]";
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity(USAGE));
                 }
 
-                if super::key_forge::should_continue() {
-                    super::key_forge::set_continue_flag(false);
+                let (filename_raw, options) = split_filename_args(&args[1..]);
+                let filename = resolve_filename(self, &filename_raw)?;
+                let mut format = StateFormat::for_path(&filename);
+                let mut encrypt = false;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    match option.as_str() {
+                        "--format" => {
+                            let name = options.next().ok_or_else(|| KeyForgeError::arity(USAGE))?;
+                            format = StateFormat::from_name(name)?;
+                        }
+                        "--encrypt" => encrypt = true,
+                        _ => return Err(KeyForgeError::arity(USAGE)),
+                    }
                 }
-            }
 
-            Ok(String::new())
-        }
+                let passphrase = if encrypt { Some(self.read_passphrase(true)?) } else { None };
+                state::save_state_to_file(&filename, &self.variables, format, passphrase.as_deref())?;
 
-        "break" => {
-            if !capture_output {
-                super::key_forge::set_break_flag(true);
-                Ok(String::new())
-            } else {
-                Err("break cannot be used in variable assignment".to_string())
-            }
-        }
-
-        "continue" => {
-            if !capture_output {
-                super::key_forge::set_continue_flag(true);
+                if !capture_output {
+                    self.write_line(&format!("State saved to {}", filename))?;
+                }
                 Ok(String::new())
-            } else {
-                Err("continue cannot be used in variable assignment".to_string())
-            }
-        }
-
-        "save_state" => {
-            if args.len() < 2 {
-                return Err("Usage: save_state <filename>".to_string());
             }
 
-            let filename_raw = &args[1..].join(" ");
-            let filename = resolve_filename(filename_raw)?;
-
-            let store = get_variable_store().lock().unwrap();
-            save_state_to_file(&filename, &store)?;
-
-            if !capture_output {
-                println!("State saved to {}", filename);
-            }
-            Ok(String::new())
-        }
-
-        "load_state" => {
-            if args.len() < 2 {
-                return Err("Usage: load_state <filename>".to_string());
-            }
+            "load_state" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: load_state <filename> [--merge] [--decrypt] [name...]"));
+                }
 
-            let filename_raw = &args[1..].join(" ");
-            let filename = resolve_filename(filename_raw)?;
+                let (filename_raw, rest) = split_filename_args(&args[1..]);
+                let filename = resolve_filename(self, &filename_raw)?;
+                let mut options = LoadOptions::default();
+                let mut decrypt = false;
+                for arg in rest {
+                    match arg.as_str() {
+                        "--merge" => options.merge = true,
+                        "--decrypt" => decrypt = true,
+                        name => options.names.push(name.strip_prefix('$').unwrap_or(name).to_string()),
+                    }
+                }
 
-            let mut store = get_variable_store().lock().unwrap();
-            load_state_from_file(&filename, &mut store)?;
+                let passphrase = if decrypt { Some(self.read_passphrase(false)?) } else { None };
+                let count = state::load_state_from_file(&filename, &mut self.variables, &options, passphrase.as_deref())?;
 
-            if !capture_output {
-                println!("State loaded from {}", filename);
+                if !capture_output {
+                    self.write_line(&format!("State loaded from {} ({} variables)", filename, count))?;
+                }
+                Ok(String::new())
             }
-            Ok(String::new())
-        }
 
-        "base64_encode" => {
-            
-            if args.len() < 2 {
-                return Err("Usage: base64_encode <encode string variable name>".to_string());
-            }
-    
-            let raw = &args[1];
+            "hex_encode" | "base32_encode" | "base58_encode" | "base64_encode" | "base64url_encode" => {
+                let mut encoding = Encoding::from_command(&args[0]).expect("encode command names an encoding");
+                let usage = match encoding {
+                    Encoding::Base32 { .. } => format!("Usage: {} <text|$var|$(command)> [--no-pad]", args[0]),
+                    _ => format!("Usage: {} <text|$var|$(command)>", args[0]),
+                };
 
-            println!("{}", raw);
+                let mut input_parts: Vec<&str> = Vec::new();
+                for arg in &args[1..] {
+                    match (arg.as_str(), &mut encoding) {
+                        ("--no-pad", Encoding::Base32 { padding }) => *padding = false,
+                        (other, _) => input_parts.push(other),
+                    }
+                }
+                if input_parts.is_empty() {
+                    return Err(KeyForgeError::arity(usage));
+                }
 
-            let raw = if raw.starts_with("$(") {
-                let command_content = &raw[2..raw.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+                let input = self.resolve_binary_input(&input_parts.join(" "))?;
+                let encoded = encoding.encode(&input);
 
-                match execute_command(&command_args, true) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
+                if !capture_output {
+                    self.write_line(&encoded)?;
+                    Ok(String::new())
+                } else {
+                    Ok(encoded)
                 }
             }
-            else if raw.starts_with('$') {
-                let var_name = &raw[1..];
-                let store = get_variable_store().lock().unwrap();
-                match store.get_string_data(var_name) {
-                    Ok(value) => value,
-                    Err(e) => return Err(format!("Undefined variable: {}", e)),
-                }
-            } else {
-                raw.to_string()
-            };
-
-            // bebug
-            println!("raw: {}", raw);
-              
-
-            let store = get_variable_store().lock().unwrap();
-            let input = store.get_string_data(&args[1]).map_err(|e| e)?;
-
-            let encode_string = base64::encode_base64(&input);
-
-            if !capture_output {
-                println!("{}", encode_string);
-                return Ok(String::new());
-            } else {
-                return Ok(encode_string);
-            }
-        }
 
-        "base64_decode" => {
-            if args.len() < 2 {
-                return Err("Usage: base64_decode <decode string variable name>".to_string());
-            }
+            "hex_decode" | "base32_decode" | "base58_decode" | "base64_decode" | "base64url_decode" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity(format!("Usage: {} <text|$var|$(command)>", args[0])));
+                }
 
-            let store = get_variable_store().lock().unwrap();
-            let input = store.get_string_data(&args[1]).map_err(|e| e)?;
+                let encoding = Encoding::from_command(&args[0]).expect("decode command names an encoding");
+                let input = self.resolve_binary_input(&args[1..].join(" "))?;
+                let text = String::from_utf8(input).map_err(|_| KeyForgeError::type_error("Encoded input must be text"))?;
+                let decoded = match encoding::bytes_to_value(encoding.decode(&text).map_err(KeyForgeError::type_error)?) {
+                    ParsedValue::String(s) => s,
+                    // Written as a `0x...` literal, so `set key $(hex_decode ...)` stores bytes
+                    bytes => value_to_string(&bytes),
+                };
 
-            match base64::decode_base64(&input) {
-                Ok(decoded) => {
-                    if !capture_output {
-                        println!("{}", decoded);
-                        Ok(String::new())
-                    } else {
-                        Ok(decoded)
-                    }
+                if !capture_output {
+                    self.write_line(&decoded)?;
+                    Ok(String::new())
+                } else {
+                    Ok(decoded)
                 }
-                Err(e) => Err(e),
             }
-        }
 
-        "remove_string_char" => {
-            if args.len() < 3 {
-                return Err("Usage: remove_string_char <variable_name> <index>".to_string());
-            }
+            "remove_string_char" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: remove_string_char <variable_name> <index>"));
+                }
 
-            let name = &args[1];
-            let index_arg = &args[2];
-            let mut store = get_variable_store().lock().unwrap();
-
-            // Handle command substitution for index
-            let index_value = if index_arg.starts_with("$(") && index_arg.ends_with(')') {
-                let command_content = &index_arg[2..index_arg.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-
-                match execute_command(&command_args, true) {
-                    Ok(output) => {
-                        // Parse the command output as integer
-                        output
-                            .trim()
-                            .parse::<i32>()
-                            .map_err(|_| "Command output must be a valid integer".to_string())?
+                let name = &args[1];
+                let index_arg = &args[2];
+
+                // Handle command substitution for index
+                let index_value = if index_arg.starts_with("$(") && index_arg.ends_with(')') {
+                    let command_content = &index_arg[2..index_arg.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    match self.execute_command(&command_args, true) {
+                        Ok(output) => {
+                            // Parse the command output as integer
+                            output
+                                .trim()
+                                .parse::<i64>()
+                                .map_err(|_| KeyForgeError::type_error("Command output must be a valid integer"))?
+                        }
+                        Err(e) => return Err(e.prefixed("Error executing index command")),
                     }
-                    Err(e) => return Err(format!("Error executing index command: {}", e)),
-                }
-            } else {
-                // Handle direct value or variable reference for index
-                if index_arg.starts_with('$') && is_valid_identifier(&index_arg[1..]) {
-                    let var_name = &index_arg[1..];
-                    // Try to get integer value from variable
-                    if let Ok(int_val) = store.get_int_data(var_name) {
-                        int_val
+                } else {
+                    // Handle direct value or variable reference for index
+                    if index_arg.starts_with('$') && is_variable_reference(&index_arg[1..]) {
+                        let var_name = &index_arg[1..];
+                        // Try to get integer value from variable
+                        if let Ok(int_val) = self.variables.get_int_data(var_name) {
+                            int_val
+                        } else {
+                            return Err(self.variables.wrong_type(var_name, "an integer"));
+                        }
                     } else {
-                        return Err(format!("Variable {} not found or not an integer", var_name));
+                        // Parse as direct integer value
+                        index_arg
+                            .parse::<i64>()
+                            .map_err(|_| KeyForgeError::type_error("Index must be a valid integer"))?
                     }
-                } else {
-                    // Parse as direct integer value
-                    index_arg
-                        .parse::<i32>()
-                        .map_err(|_| "Index must be a valid integer".to_string())?
+                };
+
+                if index_value < 0 {
+                    return Err(KeyForgeError::runtime("Index cannot be negative"));
                 }
-            };
 
-            if index_value < 0 {
-                return Err("Index cannot be negative".to_string());
-            }
+                // Get the original string value for output
+                // let original_string = store.get_string_data(name)
+                //     .map_err(|e| format!("Error getting string variable '{}': {}", name, e))?;
 
-            // Get the original string value for output
-            // let original_string = store.get_string_data(name)
-            //     .map_err(|e| format!("Error getting string variable '{}': {}", name, e))?;
+                let store = &mut self.variables;
 
-            // Perform the character removal
-            match store.remove_string_char(name, index_value as usize) {
-                Ok(()) => {
-                    if capture_output {
-                        // In capture mode, return the modified string
-                        store
-                            .get_string_data(name)
-                            .map_err(|e| format!("Error getting updated string: {}", e))
-                    } else {
-                        // // In normal mode, print information about the operation
-                        // let updated_string = store.get_string_data(name)
-                        //     .map_err(|e| format!("Error getting updated string: {}", e))?;
-                        // println!("{}", format!("Removed character at index {} from string '{}'", index_value, name).green());
-                        // println!("{}: \"{}\"", "Original".yellow(), original_string);
-                        // println!("{}: \"{}\"", "Updated".green(), updated_string);
-                        Ok(String::new())
+                // Perform the character removal
+                match store.remove_string_char(name, index_value as usize) {
+                    Ok(()) => {
+                        if capture_output {
+                            // In capture mode, return the modified string
+                            store
+                                .get_string_data(name)
+                                .map_err(|e| e.prefixed("Error getting updated string"))
+                        } else {
+                            // // In normal mode, print information about the operation
+                            // let updated_string = store.get_string_data(name)
+                            //     .map_err(|e| format!("Error getting updated string: {}", e))?;
+                            // println!("Removed character at index {} from string '{}'", index_value, name).green();
+                            // println!("{}: \"{}\"", "Original".yellow(), original_string);
+                            // println!("{}: \"{}\"", "Updated".green(), updated_string);
+                            Ok(String::new())
+                        }
                     }
+                    Err(e) => Err(e),
                 }
-                Err(e) => Err(e),
             }
-        }
 
-        "push" => {
-            if args.len() < 3 {
-                return Err("Usage: push <array_name> <value>".to_string());
-            }
+            "push" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: push <array_name> <value>"));
+                }
 
-            let array_name = &args[1];
-            let value_str = &args[2..].join(" ");
+                let array_name = &args[1];
+                let value_str = &args[2..].join(" ");
 
-            // Use our new expression evaluator for the value to push
-            let parsed_value = super::expression::evaluate_expression(value_str)?;
+                // Use our new expression evaluator for the value to push
+                let parsed_value = self.evaluate_expression(value_str)?;
 
-            let mut store = get_variable_store().lock().unwrap();
+                let store = &mut self.variables;
 
-            if let Ok(mut array) = store.get_array_data(array_name) {
-                array.push(parsed_value);
-                store.add_data_to_array(array_name.to_string(), array);
-                Ok(String::new())
-            } else {
-                // Create new array if it doesn't exist
-                let new_array = vec![parsed_value];
-                store.add_data_to_array(array_name.to_string(), new_array);
-                Ok(String::new())
+                if let Ok(mut array) = store.get_array_data(array_name) {
+                    array.push(parsed_value);
+                    store.add_data_to_array(array_name.to_string(), array);
+                    Ok(String::new())
+                } else {
+                    // Create new array if it doesn't exist
+                    let new_array = vec![parsed_value];
+                    store.add_data_to_array(array_name.to_string(), new_array);
+                    Ok(String::new())
+                }
             }
-        }
 
-        "pop" => {
-            if args.len() < 2 {
-                return Err("Usage: pop <array_name>".to_string());
-            }
+            "pop" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: pop <array_name>"));
+                }
 
-            let array_name = &args[1];
-            let mut store = get_variable_store().lock().unwrap();
+                let array_name = &args[1];
+                let store = &mut self.variables;
 
-            if let Ok(mut array) = store.get_array_data(array_name) {
-                if let Some(popped_value) = array.pop() {
-                    store.add_data_to_array(array_name.to_string(), array);
-                    let result = value_to_string(&popped_value);
-                    if capture_output {
-                        Ok(result)
+                if let Ok(mut array) = store.get_array_data(array_name) {
+                    if let Some(popped_value) = array.pop() {
+                        store.add_data_to_array(array_name.to_string(), array);
+                        let result = value_to_string(&popped_value);
+                        if capture_output {
+                            Ok(result)
+                        } else {
+                            self.write_line(&result)?;
+                            Ok(String::new())
+                        }
                     } else {
-                        println!("{}", result);
-                        Ok(String::new())
+                        Err(KeyForgeError::runtime("Array is empty"))
                     }
                 } else {
-                    Err("Array is empty".to_string())
+                    Err(KeyForgeError::runtime(format!("Array '{}' not found", array_name)))
                 }
-            } else {
-                Err(format!("Array '{}' not found", array_name))
             }
-        }
 
-        "len" => {
-            if args.len() < 2 {
-                return Err("Usage: len <variable_name>".to_string());
-            }
+            "len" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: len <variable_name>"));
+                }
 
-            let var_name = &args[1];
-            let store = get_variable_store().lock().unwrap();
-
-            let length = if let Ok(array) = store.get_array_data(var_name) {
-                array.len()
-            } else if let Ok(dict) = store.get_dict_data(var_name) {
-                dict.len()
-            } else if let Ok(string) = store.get_string_data(var_name) {
-                string.chars().count()
-            } else {
-                return Err(format!(
-                    "Variable '{}' not found or not a collection/string",
-                    var_name
-                ));
-            };
-
-            if capture_output {
-                Ok(length.to_string())
-            } else {
-                println!("{}", length);
-                Ok(String::new())
-            }
-        }
+                let var_name = &args[1];
+                let store = &self.variables;
+
+                let length = if let Ok(array) = store.get_array_data(var_name) {
+                    array.len()
+                } else if let Ok(dict) = store.get_dict_data(var_name) {
+                    dict.len()
+                } else if let Ok(string) = store.get_string_data(var_name) {
+                    string.chars().count()
+                } else {
+                    return Err(store.wrong_type(var_name, "a collection or string"));
+                };
 
-        "keys" => {
-            if args.len() < 2 {
-                return Err("Usage: keys <dict_name>".to_string());
+                if capture_output {
+                    Ok(length.to_string())
+                } else {
+                    self.write_line(&length)?;
+                    Ok(String::new())
+                }
             }
 
-            let dict_name = &args[1];
-            let store = get_variable_store().lock().unwrap();
+            "json_parse" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: json_parse <text|$var>"));
+                }
 
-            if let Ok(dict) = store.get_dict_data(dict_name) {
-                let keys: Vec<String> = dict.keys().cloned().collect();
-                let result = format!("[{}]", keys.join(", "));
+                let text = self.resolve_input(&args[1..].join(" "))?;
+                let result = value_to_string(&json::parse(&text).map_err(KeyForgeError::parse)?);
 
                 if capture_output {
                     Ok(result)
                 } else {
-                    println!("{}", result);
+                    self.write_line(&result)?;
                     Ok(String::new())
                 }
-            } else {
-                Err(format!("Dictionary '{}' not found", dict_name))
             }
-        }
 
-        "values" => {
-            if args.len() < 2 {
-                return Err("Usage: values <dict_name>".to_string());
+            "json_stringify" => {
+                let (name, pretty) = match &args[1..] {
+                    [name] => (name, false),
+                    [name, flag] if flag == "--pretty" => (name, true),
+                    _ => return Err(KeyForgeError::arity("Usage: json_stringify <var> [--pretty]")),
+                };
+
+                let name = name.strip_prefix('$').unwrap_or(name);
+                let value = self.variables.lookup(name).ok_or_else(|| KeyForgeError::undefined(name))?;
+                let result = json::stringify(value, pretty);
+
+                if capture_output {
+                    Ok(result)
+                } else {
+                    self.write_line(&result)?;
+                    Ok(String::new())
+                }
             }
 
-            let dict_name = &args[1];
-            let store = get_variable_store().lock().unwrap();
+            "json_get" => {
+                if args.len() != 3 {
+                    return Err(KeyForgeError::arity("Usage: json_get <var> <path>"));
+                }
 
-            if let Ok(dict) = store.get_dict_data(dict_name) {
-                let values: Vec<String> = dict.values().map(value_to_string).collect();
-                let result = format!("[{}]", values.join(", "));
+                let name = args[1].strip_prefix('$').unwrap_or(&args[1]);
+                let value = self.variables.lookup(name).ok_or_else(|| KeyForgeError::undefined(name))?;
+                let result = value_to_string(json::get_path(value, &args[2]).map_err(KeyForgeError::runtime)?);
 
                 if capture_output {
                     Ok(result)
                 } else {
-                    println!("{}", result);
+                    self.write_line(&result)?;
                     Ok(String::new())
                 }
-            } else {
-                Err(format!("Dictionary '{}' not found", dict_name))
             }
-        }
 
-        "get" => {
-            if args.len() < 3 {
-                return Err("Usage: get <collection_name> <key/index>".to_string());
-            }
+            "keys" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: keys <dict_name>"));
+                }
 
-            let collection_name = &args[1];
-            let key_str = &args[2];
-            let store = get_variable_store().lock().unwrap();
+                let dict_name = &args[1];
+                let store = &self.variables;
 
-            // Try as array first
-            if let Ok(array) = store.get_array_data(collection_name) {
-                let index: usize = key_str
-                    .parse()
-                    .map_err(|_| "Array index must be a non-negative integer".to_string())?;
+                if let Ok(dict) = store.get_dict_data(dict_name) {
+                    let keys: Vec<String> = dict.keys().cloned().collect();
+                    let result = format!("[{}]", keys.join(", "));
 
-                if index < array.len() {
-                    let result = value_to_string(&array[index]);
                     if capture_output {
                         Ok(result)
                     } else {
-                        println!("{}", result);
+                        self.write_line(&result)?;
                         Ok(String::new())
                     }
                 } else {
-                    Err(format!(
-                        "Index {} out of bounds for array '{}'",
-                        index, collection_name
-                    ))
+                    Err(KeyForgeError::runtime(format!("Dictionary '{}' not found", dict_name)))
                 }
             }
-            // Try as dictionary
-            else if let Ok(dict) = store.get_dict_data(collection_name) {
-                if let Some(value) = dict.get(key_str) {
-                    let result = value_to_string(value);
+
+            "values" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: values <dict_name>"));
+                }
+
+                let dict_name = &args[1];
+                let store = &self.variables;
+
+                if let Ok(dict) = store.get_dict_data(dict_name) {
+                    let values: Vec<String> = dict.values().map(value_to_string).collect();
+                    let result = format!("[{}]", values.join(", "));
+
                     if capture_output {
                         Ok(result)
                     } else {
-                        println!("{}", result);
+                        self.write_line(&result)?;
                         Ok(String::new())
                     }
                 } else {
-                    Err(format!(
-                        "Key '{}' not found in dictionary '{}'",
-                        key_str, collection_name
-                    ))
+                    Err(KeyForgeError::runtime(format!("Dictionary '{}' not found", dict_name)))
                 }
-            } else {
-                Err(format!("Collection '{}' not found", collection_name))
             }
-        }
 
-        "multi_arg_command" => {
-            if args.len() < 2 {
-                return Err("Usage: multi_arg_command <arg1> <arg2> ...".to_string());
-            }
+            "get" => {
+                if args.len() < 3 {
+                    return Err(KeyForgeError::arity("Usage: get <collection_name> <key/index>"));
+                }
+
+                let collection_name = &args[1];
+                let key_str = &args[2];
+                let store = &self.variables;
 
-            let processed_args = &args[1..].join(" ");
+                // Try as array first
+                if let Ok(array) = store.get_array_data(collection_name) {
+                    let index: usize = key_str
+                        .parse()
+                        .map_err(|_| KeyForgeError::type_error("Array index must be a non-negative integer"))?;
 
-            for (i, arg) in processed_args.chars().enumerate() {
-                println!("Arg {}: {}", i + 1, arg);
+                    if index < array.len() {
+                        let result = value_to_string(&array[index]);
+                        if capture_output {
+                            Ok(result)
+                        } else {
+                            self.write_line(&result)?;
+                            Ok(String::new())
+                        }
+                    } else {
+                        Err(KeyForgeError::runtime(format!(
+                            "Index {} out of bounds for array '{}'",
+                            index, collection_name
+                        )))
+                    }
+                }
+                // Try as dictionary
+                else if let Ok(dict) = store.get_dict_data(collection_name) {
+                    if let Some(value) = dict.get(key_str) {
+                        let result = value_to_string(value);
+                        if capture_output {
+                            Ok(result)
+                        } else {
+                            self.write_line(&result)?;
+                            Ok(String::new())
+                        }
+                    } else {
+                        Err(KeyForgeError::runtime(format!(
+                            "Key '{}' not found in dictionary '{}'",
+                            key_str, collection_name
+                        )))
+                    }
+                } else {
+                    Err(KeyForgeError::runtime(format!("Collection '{}' not found", collection_name)))
+                }
             }
 
-            Ok(String::new())
+            "multi_arg_command" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: multi_arg_command <arg1> <arg2> ..."));
+                }
 
-        }
+                let processed_args = &args[1..].join(" ");
 
-        "write_file" => {
-            if args.len() < 3 {
-                return Err(String::from("Usage: write_file <filename> <content> <append>"));
-            }
+                for (i, arg) in processed_args.chars().enumerate() {
+                    self.write_line(&format!("Arg {}: {}", i + 1, arg))?;
+                }
 
-            let filename = &args[1];
-            let content = &args[2];
-            let append = &args[3];
-
-            println!("filename: {}", filename);
-            println!("content: {}", content);
-            println!("append: {}", append);
-
-            let filename = if filename.starts_with("$(") && filename.ends_with(')') {
-                let command_content = &filename[2..filename.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-
-                match execute_command(&command_args, true) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
-                }
-            } else if filename.starts_with('$') {
-                let var_name = &filename[1..];
-                let store = get_variable_store().lock().unwrap();
-                match store.get_string_data(var_name) {
-                    Ok(value) => value,
-                    Err(e) => return Err(format!("Undefined variable: {}", e)),
-                }
-            } else {
-                filename.to_string()
-            };
-
-            let content = if content.starts_with("$(") && content.ends_with(')') {
-                let command_content = &&content[2..content.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-                
-                match execute_command(&command_args, true) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
-                }
-            } else if content.starts_with('$') {
-                let var_name = &content[1..];
-                let store = get_variable_store().lock().unwrap();
-                match store.get_string_data(var_name) {
-                    Ok(value) => value,
-                    Err(e) => return Err(format!("Undefined variable: {}", e)),
-                }
-            } else {
-                content.to_string()
-            };
-            
-            // FIXED: Use 'append' variable instead of 'content'
-            let append = if append.starts_with("$(") && append.ends_with(')') {
-                let command_content = &append[2..append.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
-
-                match execute_command(&command_args, true) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
-                }
-            } else if append.starts_with('$') {
-                let var_name = &append[1..]; // FIXED: Use append instead of content
-                let store = get_variable_store().lock().unwrap();
-                match store.get_string_data(var_name) {
-                    Ok(value) => value,
-                    Err(e) => return Err(format!("Undefined variable: {}", e)),
-                }
-            } else {
-                append.to_string()
-            };
-            
-            let should_append: bool = if append == "a" {
-                true
-            } else if append == "w" {
-                false
-            } else {
-                return Err(format!("Unknown mode for write_file '{}' use can use only 'w' or 'a'", append));
-            };
-
-            let result = utils::write_to_file_with_mode(&filename, &content, should_append);
-
-            match result {
-                Ok(()) => Ok(String::new()),
-                Err(e) => match e.kind() {
-                    std::io::ErrorKind::PermissionDenied => {
-                        Err(String::from("Permission denied"))
-                    },
-                    std::io::ErrorKind::NotFound => {
-                        Err(String::from("Directory does not exist"))
-                    },
-                    std::io::ErrorKind::AlreadyExists => {
-                        Err(String::from("File exist but blocked"))
-                    },
-                    _ => Err(String::from("Error write file"))
-                },
-            }
-        }
+                Ok(String::new())
 
-        "read_file" => {
-            if args.len() < 2 {
-                return Err(String::from("Usage: read_file <filename>"));
             }
 
-            let filename = &args[1];
+            "write_file" => {
+                if args.len() < 4 {
+                    return Err(KeyForgeError::arity("Usage: write_file <filename> <content> <append>"));
+                }
+
+                let filename = &args[1];
+                let content = &args[2];
+                let append = &args[3];
+
+                self.write_line(&format!("filename: {}", filename))?;
+                self.write_line(&format!("content: {}", content))?;
+                self.write_line(&format!("append: {}", append))?;
+
+                let filename = if filename.starts_with("$(") && filename.ends_with(')') {
+                    let command_content = &filename[2..filename.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    self.execute_command(&command_args, true)?
+                } else if let Some(var_name) = filename.strip_prefix('$') {
+                    let store = &self.variables;
+                    store.get_string_data(var_name)?
+                } else {
+                    filename.to_string()
+                };
+
+                let content = if content.starts_with("$(") && content.ends_with(')') {
+                    let command_content = &&content[2..content.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    self.execute_command(&command_args, true)?
+                } else if let Some(var_name) = content.strip_prefix('$') {
+                    let store = &self.variables;
+                    store.get_string_data(var_name)?
+                } else {
+                    content.to_string()
+                };
 
-            println!("filename: {}", filename);
+                // FIXED: Use 'append' variable instead of 'content'
+                let append = if append.starts_with("$(") && append.ends_with(')') {
+                    let command_content = &append[2..append.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
 
-            let filename = if filename.starts_with("$(") && filename.ends_with(')') {
-                let command_content = &filename[2..filename.len() - 1];
-                let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+                    self.execute_command(&command_args, true)?
+                } else if let Some(var_name) = append.strip_prefix('$') {
+                    // FIXED: Use append instead of content
+                    let store = &self.variables;
+                    store.get_string_data(var_name)?
+                } else {
+                    append.to_string()
+                };
 
-                match execute_command(&command_args, true) {
-                    Ok(output) => output,
-                    Err(e) => return Err(e),
+                let should_append: bool = if append == "a" {
+                    true
+                } else if append == "w" {
+                    false
+                } else {
+                    return Err(KeyForgeError::runtime(format!("Unknown mode for write_file '{}' use can use only 'w' or 'a'", append)));
+                };
+
+                let result = utils::write_to_file_with_mode(&filename, &content, should_append);
+
+                match result {
+                    Ok(()) => Ok(String::new()),
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::PermissionDenied => {
+                            Err(KeyForgeError::runtime("Permission denied"))
+                        },
+                        std::io::ErrorKind::NotFound => {
+                            Err(KeyForgeError::runtime("Directory does not exist"))
+                        },
+                        std::io::ErrorKind::AlreadyExists => {
+                            Err(KeyForgeError::runtime("File exist but blocked"))
+                        },
+                        _ => Err(KeyForgeError::runtime("Error write file"))
+                    },
                 }
-            } else if filename.starts_with('$') {
-                let var_name = &filename[1..];
-                let store = get_variable_store().lock().unwrap();
-                match store.get_string_data(var_name) {
-                    Ok(value) => value,
-                    Err(e) => return Err(format!("Undefined variable: {}", e)),
+            }
+
+            "read_file" => {
+                if args.len() < 2 {
+                    return Err(KeyForgeError::arity("Usage: read_file <filename>"));
                 }
-            } else {
-                filename.to_string()
-            };
 
-            let result = utils::read_from_file(&filename);
+                let filename = &args[1];
 
-            match result {
-                Ok(content) => {
-                    return if capture_output {
-                        Ok(content)
-                    } else {
-                        println!("Read content: {}", content);
-                        Ok(String::new())
+                self.write_line(&format!("filename: {}", filename))?;
+
+                let filename = if filename.starts_with("$(") && filename.ends_with(')') {
+                    let command_content = &filename[2..filename.len() - 1];
+                    let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+
+                    self.execute_command(&command_args, true)?
+                } else if let Some(var_name) = filename.strip_prefix('$') {
+                    let store = &self.variables;
+                    store.get_string_data(var_name)?
+                } else {
+                    filename.to_string()
+                };
+
+                let result = utils::read_from_file(&filename);
+
+                match result {
+                    Ok(content) => {
+                        if capture_output {
+                            Ok(content)
+                        } else {
+                            self.write_line(&format!("Read content: {}", content))?;
+                            Ok(String::new())
+                        }
                     }
-                }
-                Err(e) => match e.kind() {
-                    std::io::ErrorKind::PermissionDenied => {
-                        Err(String::from("Permission denied"))
-                    },
-                    std::io::ErrorKind::NotFound => {
-                        Err(String::from("File does not exist"))
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::PermissionDenied => {
+                            Err(KeyForgeError::runtime("Permission denied"))
+                        },
+                        std::io::ErrorKind::NotFound => {
+                            Err(KeyForgeError::runtime("File does not exist"))
+                        },
+                        _ => Err(KeyForgeError::runtime("Error reading file"))
                     },
-                    _ => Err(String::from("Error reading file"))
-                },
+                }
             }
-        }
 
-        _ => {
-            if capture_output {
-                Err(format!(
-                    "Command '{}' cannot be used in variable assignment",
-                    args[0]
-                ))
-            } else {
-                Err(format!("Unknown command {}", args[0]))
+            name if self.functions.contains_key(name) => {
+                let function = self.functions[name].clone();
+                self.call_function(&function, &args[1..])
             }
+
+            _ => {
+                if capture_output {
+                    Err(KeyForgeError::runtime(format!(
+                        "Command '{}' cannot be used in variable assignment",
+                        args[0]
+                    )))
+                } else {
+                    Err(KeyForgeError::runtime(format!("Unknown command {}", args[0])))
+                }
+            }
+        }
+    }
+
+    // Resolves the input of an encoder or decoder to raw bytes. `$name`, or a bare name of an
+    // existing variable, gives the bytes of a bytes variable or the text of any other value;
+    // everything else goes through resolve_input.
+    fn resolve_binary_input(&mut self, raw: &str) -> Result<Vec<u8>, KeyForgeError> {
+        match self.binary_input_name(raw).map(|name| (name, self.variables.lookup(name))) {
+            Some((_, Some(ParsedValue::Bytes(bytes)))) => Ok(bytes.clone()),
+            Some((_, Some(ParsedValue::String(text)))) => Ok(text.as_bytes().to_vec()),
+            Some((_, Some(other))) => Ok(value_to_string(other).into_bytes()),
+            Some((name, None)) => Err(KeyForgeError::undefined(name)),
+            None => Ok(self.resolve_input(raw)?.into_bytes()),
+        }
+    }
+
+    // Whether `raw` refers to a bytes variable, in the sense of resolve_binary_input.
+    fn is_bytes_input(&self, raw: &str) -> bool {
+        let value = self.binary_input_name(raw).and_then(|name| self.variables.lookup(name));
+        matches!(value, Some(ParsedValue::Bytes(_)))
+    }
+
+    fn binary_input_name<'a>(&self, raw: &'a str) -> Option<&'a str> {
+        match raw.strip_prefix('$') {
+            Some(name) if is_variable_reference(name) => Some(name),
+            Some(_) => None,
+            None => Some(raw).filter(|name| is_valid_identifier(name) && self.variables.has_variable(name)),
         }
     }
+
+    // Resolves a single command input: `$(command)` is executed, `$name` is looked up
+    // through resolve_to_string and anything else is taken literally.
+    pub(crate) fn resolve_input(&mut self, raw: &str) -> Result<String, KeyForgeError> {
+        if raw.starts_with("$(") && raw.ends_with(')') {
+            let command_content = &raw[2..raw.len() - 1];
+            let command_args: Vec<String> = input_mode::tokenize_input(command_content);
+            self.execute_command(&command_args, true)
+        } else if raw.starts_with('$') {
+            resolve_to_string(&self.variables, raw)
+        } else {
+            Ok(raw.to_string())
+        }
+    }
+}
+
+/// Splits a file argument from the options after it. A `$(...)` filename may have been split
+/// into several words at its spaces, so words are joined until the parentheses balance.
+fn split_filename_args(args: &[String]) -> (String, &[String]) {
+    let mut filename = String::new();
+    let mut depth = 0i32;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            filename.push(' ');
+        }
+        filename.push_str(arg);
+        depth += arg.matches('(').count() as i32 - arg.matches(')').count() as i32;
+        if depth <= 0 {
+            return (filename, &args[i + 1..]);
+        }
+    }
+    (filename, &[])
+}
+
+/// Quotes `word` again if it would not survive being re-parsed as a single word.
+fn quote_word(word: &str) -> String {
+    let needs_quotes = word.is_empty() || word.contains(|c: char| c.is_whitespace() || c == ';');
+    if !needs_quotes || (word.starts_with("$(") && word.ends_with(')')) {
+        word.to_string()
+    } else if word.contains('"') {
+        format!("'{}'", word)
+    } else {
+        format!("\"{}\"", word)
+    }
 }
@@ -1,8 +1,9 @@
 use std::str::CharIndices;
-use super::execute_command::execute_command;
-use super::key_forge::{get_variable_store, ParsedValue};
+use super::error::KeyForgeError;
+use super::interpreter::Interpreter;
+use super::key_forge::{parse_value, ParsedValue};
 use super::key_forge::input_mode::tokenize_input;
-use std::collections::HashMap;
+use super::parser::parse_arithmetic;
 
 #[derive(Debug, Clone)]
 pub enum Token {
@@ -85,6 +86,17 @@ impl<'a> Lexer<'a> {
                         }
                     }
                 }
+                '"' | '\'' => {
+                    // Quoted text is one token, quotes included, so commas inside it survive
+                    let mut text = String::from(c);
+                    while let Some((_, next)) = self.next_char() {
+                        text.push(next);
+                        if next == c {
+                            break;
+                        }
+                    }
+                    return Some(Token::Text(text));
+                }
                 ' ' | '\t' | '\n' | '\r' => continue, // Skip whitespace
                 _ => {
                     let mut text = String::new();
@@ -104,192 +116,74 @@ impl<'a> Lexer<'a> {
     }
 }
 
-/// Evaluates an expression that may contain variables ($var), command substitutions ($(cmd)),
-/// and array literals ([1, 2, 3]). Returns a ParsedValue result.
-pub fn evaluate_expression(expr: &str) -> Result<ParsedValue, String> {
-    let mut lexer = Lexer::new(expr);
-    let mut tokens = Vec::new();
-    while let Some(token) = lexer.next_token() {
-        tokens.push(token);
-    }
-
-    parse_tokens(&tokens)
-}
-
-fn parse_tokens(tokens: &[Token]) -> Result<ParsedValue, String> {
-    // If first token is '[' and last is ']', treat as array
-    if tokens.first().map_or(false, |t| matches!(t, Token::ArrayStart)) &&
-       tokens.last().map_or(false, |t| matches!(t, Token::ArrayEnd)) {
-        return parse_array(&tokens[1..tokens.len()-1]);
-    }
-
-    // Otherwise evaluate as a single value
-    match tokens.get(0) {
-        Some(Token::Variable(var)) => {
-            let store = get_variable_store().lock().unwrap();
-            if let Ok(val) = store.get_int_data(var) {
-                Ok(ParsedValue::Int(val))
-            } else if let Ok(val) = store.get_float_data(var) {
-                Ok(ParsedValue::Float(val))
-            } else if let Ok(val) = store.get_string_data(var) {
-                Ok(ParsedValue::String(val))
-            } else {
-                Err(format!("Variable '{}' not found", var))
-            }
+impl Interpreter {
+    /// Evaluates an expression that may contain variables ($var), command substitutions ($(cmd)),
+    /// array literals ([1, 2, 3]) and arithmetic (($x + 1) * 2). Returns a ParsedValue result.
+    pub fn evaluate_expression(&mut self, expr: &str) -> Result<ParsedValue, KeyForgeError> {
+        if let Some(arithmetic) = parse_arithmetic(expr) {
+            return self.evaluate_arithmetic(&arithmetic).map(ParsedValue::from);
         }
-        Some(Token::Command(cmd)) => {
-            let args = tokenize_input(&cmd);
-            let result = execute_command(&args, true)?;
-            parse_value(&result)
-        }
-        Some(Token::Text(text)) => {
-            parse_value(text)
-        }
-        _ => Err("Invalid expression".to_string())
-    }
-}
 
-fn parse_array(tokens: &[Token]) -> Result<ParsedValue, String> {
-    let mut elements = Vec::new();
-    let mut current_tokens = Vec::new();
+        // The token lexer has no notion of dictionaries, so `{key: value}` is parsed as a whole
+        let trimmed = expr.trim();
+        if trimmed.starts_with('{') && trimmed.ends_with('}') {
+            return Ok(parse_value(trimmed));
+        }
 
-    for token in tokens {
-        match token {
-            Token::Comma if !current_tokens.is_empty() => {
-                elements.push(parse_tokens(&current_tokens)?);
-                current_tokens.clear();
-            }
-            Token::Comma => continue, // Skip empty elements
-            token => current_tokens.push(token.clone()),
+        let mut lexer = Lexer::new(expr);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token);
         }
-    }
 
-    if !current_tokens.is_empty() {
-        elements.push(parse_tokens(&current_tokens)?);
+        self.parse_tokens(&tokens)
     }
 
-    Ok(ParsedValue::Array(elements))
-}
-
-fn parse_value(raw: &str) -> Result<ParsedValue, String> {
-    let trimmed = raw.trim();
-    
-    // Try to parse as array: [1, 2, 3]
-    if let Some(array_str) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
-        let elements: Vec<&str> = split_array_elements(array_str);
-        let mut parsed_elements = Vec::new();
-        
-        for element in elements {
-            if !element.is_empty() {
-                parsed_elements.push(parse_value(element)?);
-            }
+    fn parse_tokens(&mut self, tokens: &[Token]) -> Result<ParsedValue, KeyForgeError> {
+        // If first token is '[' and last is ']', treat as array
+        if tokens.first().is_some_and(|t| matches!(t, Token::ArrayStart)) &&
+           tokens.last().is_some_and(|t| matches!(t, Token::ArrayEnd)) {
+            return self.parse_array(&tokens[1..tokens.len()-1]);
         }
-        return Ok(ParsedValue::Array(parsed_elements));
-    }
 
-    // Try to parse as dictionary: {key: value, key2: value2}
-    if let Some(dict_str) = trimmed.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
-        let mut dict = HashMap::new();
-        let pairs: Vec<&str> = split_dict_pairs(dict_str);
-        
-        for pair in pairs {
-            if let Some((key, value)) = pair.split_once(':') {
-                let key = key.trim().to_string();
-                let value = parse_value(value.trim())?;
-                dict.insert(key, value);
+        // Otherwise evaluate as a single value
+        match tokens.first() {
+            Some(Token::Variable(var)) => {
+                self.variables
+                    .get_value(var)
+                    .ok_or_else(|| KeyForgeError::undefined(var.as_str()))
+            }
+            Some(Token::Command(cmd)) => {
+                let args = tokenize_input(cmd);
+                let result = self.execute_command(&args, true)?;
+                Ok(parse_value(&result))
             }
+            Some(Token::Text(text)) => Ok(parse_value(text)),
+            _ => Err(KeyForgeError::runtime("Invalid expression"))
         }
-        return Ok(ParsedValue::Dictionary(dict));
     }
 
-    // Try integer
-    if let Ok(iv) = trimmed.parse::<i32>() {
-        return Ok(ParsedValue::Int(iv));
-    }
-
-    // Try float
-    if let Ok(fv) = trimmed.parse::<f64>() {
-        return Ok(ParsedValue::Float(fv));
-    }
+    fn parse_array(&mut self, tokens: &[Token]) -> Result<ParsedValue, KeyForgeError> {
+        let mut elements = Vec::new();
+        let mut current_tokens = Vec::new();
 
-    // Handle quoted strings
-    let s = if let Some(stripped) = trimmed.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
-        stripped.to_string()
-    } else if let Some(stripped) = trimmed.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
-        stripped.to_string()
-    } else {
-        trimmed.to_string()
-    };
-
-    Ok(ParsedValue::String(s))
-}
-
-// Helper function to split array elements considering nested structures
-fn split_array_elements(s: &str) -> Vec<&str> {
-    let mut elements = Vec::new();
-    let mut start = 0;
-    let mut depth = 0;
-    let mut in_quotes = false;
-    let mut quote_char = '\0';
-
-    for (i, c) in s.char_indices() {
-        match c {
-            '"' | '\'' if !in_quotes => {
-                in_quotes = true;
-                quote_char = c;
-            }
-            _ if in_quotes && c == quote_char => {
-                in_quotes = false;
-            }
-            '[' | '{' if !in_quotes => depth += 1,
-            ']' | '}' if !in_quotes => depth -= 1,
-            ',' if !in_quotes && depth == 0 => {
-                elements.push(&s[start..i]);
-                start = i + 1;
+        for token in tokens {
+            match token {
+                Token::Comma if !current_tokens.is_empty() => {
+                    elements.push(self.parse_tokens(&current_tokens)?);
+                    current_tokens.clear();
+                }
+                Token::Comma => continue, // Skip empty elements
+                token => current_tokens.push(token.clone()),
             }
-            _ => {}
         }
-    }
-    
-    if start < s.len() {
-        elements.push(&s[start..]);
-    }
-    
-    elements.iter().map(|s| s.trim()).collect()
-}
-
-// Helper function to split dictionary pairs considering nested structures
-fn split_dict_pairs(s: &str) -> Vec<&str> {
-    let mut pairs = Vec::new();
-    let mut start = 0;
-    let mut depth = 0;
-    let mut in_quotes = false;
-    let mut quote_char = '\0';
 
-    for (i, c) in s.char_indices() {
-        match c {
-            '"' | '\'' if !in_quotes => {
-                in_quotes = true;
-                quote_char = c;
-            }
-            _ if in_quotes && c == quote_char => {
-                in_quotes = false;
-            }
-            '[' | '{' if !in_quotes => depth += 1,
-            ']' | '}' if !in_quotes => depth -= 1,
-            ',' if !in_quotes && depth == 0 => {
-                pairs.push(&s[start..i]);
-                start = i + 1;
-            }
-            _ => {}
+        if !current_tokens.is_empty() {
+            elements.push(self.parse_tokens(&current_tokens)?);
         }
+
+        Ok(ParsedValue::Array(elements))
     }
-    
-    if start < s.len() {
-        pairs.push(&s[start..]);
-    }
-    
-    pairs.iter().map(|s| s.trim()).collect()
 }
 
 #[cfg(test)]
@@ -0,0 +1,156 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use hmac::{Mac, SimpleHmac};
+use sha2::digest::core_api::BlockSizeUser;
+use sha2::{Digest, Sha224, Sha256, Sha512};
+
+use super::encoding::{from_hex, to_hex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha224,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha224" => Ok(Self::Sha224),
+            "sha256" => Ok(Self::Sha256),
+            "sha512" => Ok(Self::Sha512),
+            _ => Err(format!("Unknown hash algorithm '{}' (use sha224, sha256 or sha512)", name)),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha224 => Sha224::digest(data).to_vec(),
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    pub fn hmac_sign(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha224 => keyed_hmac::<Sha224>(key, message).finalize().into_bytes().to_vec(),
+            Self::Sha256 => keyed_hmac::<Sha256>(key, message).finalize().into_bytes().to_vec(),
+            Self::Sha512 => keyed_hmac::<Sha512>(key, message).finalize().into_bytes().to_vec(),
+        }
+    }
+
+    /// Checks `tag` against the HMAC of `message`. The comparison runs in constant time.
+    pub fn hmac_verify(self, key: &[u8], message: &[u8], tag: &[u8]) -> bool {
+        match self {
+            Self::Sha224 => keyed_hmac::<Sha224>(key, message).verify_slice(tag).is_ok(),
+            Self::Sha256 => keyed_hmac::<Sha256>(key, message).verify_slice(tag).is_ok(),
+            Self::Sha512 => keyed_hmac::<Sha512>(key, message).verify_slice(tag).is_ok(),
+        }
+    }
+}
+
+/// HMAC of `message` under `key`, ready to be finalized or verified. Signing and verifying both
+/// go through here, so they always compute the same MAC.
+fn keyed_hmac<D: Digest + BlockSizeUser + Clone>(key: &[u8], message: &[u8]) -> SimpleHmac<D> {
+    // HMAC accepts keys of any length, so new_from_slice cannot fail here
+    let mut mac = SimpleHmac::<D>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestEncoding {
+    Hex,
+    Base64,
+}
+
+impl DigestEncoding {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "hex" | "--hex" => Ok(Self::Hex),
+            "base64" | "--base64" => Ok(Self::Base64),
+            _ => Err(format!("Unknown output encoding '{}' (use hex or base64)", name)),
+        }
+    }
+
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Hex => to_hex(bytes),
+            Self::Base64 => STANDARD.encode(bytes),
+        }
+    }
+}
+
+/// Decodes an HMAC key. `hex:<digits>` and `base64:<data>` select an encoding,
+/// anything else is used as raw UTF-8 bytes.
+pub fn decode_key(raw: &str) -> Result<Vec<u8>, String> {
+    if let Some(hex) = raw.strip_prefix("hex:") {
+        from_hex(hex.trim())
+    } else if let Some(b64) = raw.strip_prefix("base64:") {
+        STANDARD
+            .decode(b64.trim())
+            .map_err(|e| format!("Invalid base64 key: {}", e))
+    } else {
+        Ok(raw.as_bytes().to_vec())
+    }
+}
+
+/// Decodes a tag produced by `hmac_sign`, accepting either hex or base64 output.
+pub fn decode_tag(raw: &str) -> Result<Vec<u8>, String> {
+    from_hex(raw.trim()).or_else(|_| {
+        STANDARD
+            .decode(raw.trim())
+            .map_err(|_| format!("Tag '{}' is neither hex nor base64", raw))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(
+            DigestEncoding::Hex.encode(&HashAlgorithm::Sha256.digest(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            DigestEncoding::Hex.encode(&HashAlgorithm::Sha224.digest(b"abc")),
+            "23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7"
+        );
+        assert_eq!(
+            DigestEncoding::Base64.encode(&HashAlgorithm::Sha256.digest(b"")),
+            "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+    }
+
+    #[test]
+    fn test_hmac_rfc4231_case_2() {
+        let key = decode_key("Jefe").unwrap();
+        let message = b"what do ya want for nothing?";
+        let tag = HashAlgorithm::Sha256.hmac_sign(&key, message);
+        assert_eq!(
+            to_hex(&tag),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let hex_tag = decode_tag(&to_hex(&tag)).unwrap();
+        let b64_tag = decode_tag(&DigestEncoding::Base64.encode(&tag)).unwrap();
+        assert!(HashAlgorithm::Sha256.hmac_verify(&key, message, &hex_tag));
+        assert!(HashAlgorithm::Sha256.hmac_verify(&decode_key("hex:4a656665").unwrap(), message, &b64_tag));
+        assert!(!HashAlgorithm::Sha256.hmac_verify(b"jefe", message, &hex_tag));
+
+        for (algorithm, expected) in [
+            (HashAlgorithm::Sha224, "a30e01098bc6dbbf45690f3a7e9e6d0f8bbea2a39e6148008fd05e44"),
+            (
+                HashAlgorithm::Sha512,
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+                 9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            ),
+        ] {
+            let tag = algorithm.hmac_sign(&key, message);
+            assert_eq!(to_hex(&tag), expected);
+            assert!(algorithm.hmac_verify(&key, message, &tag));
+        }
+    }
+}
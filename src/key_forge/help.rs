@@ -1,438 +1,772 @@
 use colored::Colorize;
-
-pub fn show_all_help() {
-    println!("{}", "Commands of key_forge".green());
-    println!("");
-
-    println!("{}", "get_random_num : use for get random num with diapason".blue());
-    println!("Examples:");
-    println!(" get_random_num 1 100    - generates random integer between 1-100");
-    println!(" get_random_num 0.5 5.5  - generates random float between 0.5-5.5");
-    println!("");
-
-    println!("{}", "get_random_char : use for get random char from alphabet".blue());
-    println!("Examples:");
-    println!(" get_random_char      - return random lowercase char example 'a'");
-    println!(" get_random_char 1    - return random uppercase char example 'B'");
-    println!("");
-
-    println!("{}", "repeat : use for repeat one command n times".blue());
-    println!("Examples:");
-    println!(" repeat 10 get_random_num 1 100 - repeat command get_random_num 10 times");
-    println!("");
-
-    println!("{}", "set : use for set variable with value".blue());
-    println!("Examples:");
-    println!(" set my_var 42                      - set integer variable");
-    println!(" set my_var 3.14                    - set float variable");
-    println!(" set my_var \"hello\"               - set string variable");
-    println!(" set my_var $(get_random_num 1 100) - set with command result");
-    println!("{}", "set <collection_name> <key/index> <value>".green());
-    println!("Set a value in an array (by index) or dictionary (by key).");
-    println!("Examples:");
-    println!(" set my_array 0 42          - set array element at index 0");
-    println!(" set my_dict age 31         - set dictionary value for key 'age'");
-    println!("");
-
-    println!("{}", "print : use for print variable value or literal".blue());
-    println!("Examples:");
-    println!(" print my_var                  - print variable value");
-    println!(" print \"Hello World\"         - print literal string");
-    println!(" print 123                     - print literal number");
-    println!(" print $(get_random_num 1 100) - print output command get_random_num");
-    println!("");
+use std::io::{self, Write};
+
+pub fn show_all_help(out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", "Commands of key_forge".green())?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "get_random_num : use for get random num with diapason".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " get_random_num 1 100    - generates random integer between 1-100")?;
+    writeln!(out, " get_random_num 0.5 5.5  - generates random float between 0.5-5.5")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "get_random_char : use for get random char from alphabet".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " get_random_char      - return random lowercase char example 'a'")?;
+    writeln!(out, " get_random_char 1    - return random uppercase char example 'B'")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "seed : make random commands reproducible".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " seed 42              - same numbers, passwords, tokens and UUIDs on every run")?;
+    writeln!(out, " seed reset           - back to OS entropy")?;
+    writeln!(out, " key_forge --seed 42 fixtures.kf - seed from the command line")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "gen_password : generate password with cryptographically secure random".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " gen_password                           - 16 chars from lower, upper, digits and symbols")?;
+    writeln!(out, " gen_password 24 --lower --digits       - 24 chars from selected classes only")?;
+    writeln!(out, " gen_password 20 --min 2 --no-ambiguous - at least 2 chars of each class, no 0/O/1/l/I/|")?;
+    writeln!(out, " gen_password 32 --alphabet abcdef0123  - use custom alphabet")?;
+    writeln!(out, " set pass $(gen_password 20)            - store password in variable")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "gen_bytes/gen_token : random bytes and API tokens".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " gen_bytes 32                           - 32 random bytes as hex")?;
+    writeln!(out, " gen_bytes 32 base64url                 - as URL-safe base64 (or base64, bytes)")?;
+    writeln!(out, " gen_token kf_live_ 30                  - kf_live_ plus 30 base62 characters")?;
+    writeln!(out, " gen_token kf_ 30 --checksum crc32      - append a checksum for offline validation")?;
+    writeln!(out, " verify_token $token crc32              - true if the checksum matches")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "uuid_v4/uuid_v7/uuid_nil : generate UUID".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " uuid_v4                    - random UUID")?;
+    writeln!(out, " uuid_v7                    - time-ordered UUID")?;
+    writeln!(out, " repeat 100 uuid_v7         - 100 UUIDs in creation order")?;
+    writeln!(out, " set id $(uuid_v4)          - store UUID in variable")?;
+    writeln!(out, " set info $(uuid_parse $id) - validate UUID, returns dictionary with version")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "repeat : use for repeat one command n times".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " repeat 10 get_random_num 1 100 - repeat command get_random_num 10 times")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "set : use for set variable with value".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set my_var 42                      - set integer variable")?;
+    writeln!(out, " set my_var 3.14                    - set float variable")?;
+    writeln!(out, " set my_var \"hello\"               - set string variable")?;
+    writeln!(out, " set my_var $(get_random_num 1 100) - set with command result")?;
+    writeln!(out, "{}", "set <collection_name> <key/index> <value>".green())?;
+    writeln!(out, "Set a value in an array (by index) or dictionary (by key).")?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set my_array 0 42          - set array element at index 0")?;
+    writeln!(out, " set my_dict age 31         - set dictionary value for key 'age'")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "print : use for print variable value or literal".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " print my_var                  - print variable value")?;
+    writeln!(out, " print \"Hello World\"         - print literal string")?;
+    writeln!(out, " print 123                     - print literal number")?;
+    writeln!(out, " print $(get_random_num 1 100) - print output command get_random_num")?;
+    writeln!(out)?;
     
 
-    println!("{}", "exit/quit : exit the program".blue());
-    println!("Examples:");
-    println!(" exit                 - exit with code 0");
-    println!(" exit 1              - exit with code 1");
-    println!("");
-
-    println!("{}", "vl : use for show variables list".blue());
-    println!("Examples:");
-    println!(" vl - show all variables");
-    println!(" vl i - show only int variables");
-    println!(" vl f - show only float variables");
-    println!(" vl s - show only string variables");
-    println!("");
-
-    println!("{}", "execute_file : for execute commands in file".blue());
-    println!("Examples:");
-    println!(" execute_file filename.txt - execute command in file filename.txt");
-    println!("");
-
-    println!("{}", "to_file : use for write output to file".blue());
-    println!("Examples:");
-    println!(" to_file filename.txt $(get_random_num 1 100) - write to file output of get_random_num");
-    println!("");
-
-    println!("{}", "add : for add value to variable".blue());
-    println!("Examples:");
-    println!("add x 10                      - add 10 to variable x");
-    println!("add x y                       - add other to x");
-    println!("add x $(get_random_num 1 100) - add output of get_random_num");
-    println!();
+    writeln!(out, "{}", "exit/quit : exit the program".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " exit                 - exit with code 0")?;
+    writeln!(out, " exit 1              - exit with code 1")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "vl : use for show variables list".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " vl - show all variables")?;
+    writeln!(out, " vl i - show only int variables")?;
+    writeln!(out, " vl f - show only float variables")?;
+    writeln!(out, " vl s - show only string variables")?;
+    writeln!(out, " vl o - show only bool, null and bytes variables")?;
+    writeln!(out, " vl api_* - show variables whose name starts with api_")?;
+    writeln!(out, " vl bytes *key - show bytes variables whose name ends with key")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "rm : remove variables of any type".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " rm x")?;
+    writeln!(out, " rm scores settings")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "type_of : show the type of a variable".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " type_of x                  - int, float, string, bool, null, bytes, array or dict")?;
+    writeln!(out, " if $(type_of x) == \"int\" then print \"number\"")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "execute_file : for execute commands in file".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " execute_file filename.txt - execute command in file filename.txt")?;
+    writeln!(out, " set out $(execute_file gen.kf) - capture what the script prints")?;
+    writeln!(out, " execute_file gen.kf --count 3 - run gen.kf with its own $1..$N")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "to_file : use for write output to file".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " to_file filename.txt $(get_random_num 1 100) - write to file output of get_random_num")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "add : for add value to variable".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, "add x 10                      - add 10 to variable x")?;
+    writeln!(out, "add x y                       - add other to x")?;
+    writeln!(out, "add x $(get_random_num 1 100) - add output of get_random_num")?;
+    writeln!(out)?;
     
-    println!("{}", "mul : for multiply values".blue());
-    println!("Examples:");
-    println!("mul x 5                          - multiply x * 3 and store in x");
-    println!("mul x y                          - multiply variables x y and store in x");
-    println!("mul result $(get_random_num 2 5) - multiply random number by 10");
-    println!("");
-
-    println!("{}", "div : for divide values".blue());
-    println!("Examples:");
-    println!("div x 2                              - divide 10 / 2 and store in x");
-    println!("div x z                              - divide y / z and store in x");
-    println!("div result 100 $(get_random_num 2 5) - divide 100 by random number");
-    println!("");
-
-    println!("{}", "push_to_string_back : append value to the end of a string variable".blue());
-    println!("Examples:");
-    println!(" push_to_string_back my_string \" appended text\" - append literal text");
-    println!(" push_to_string_back my_string $(get_random_char) - append random character");
-    println!(" push_to_string_back str1 str2 - append value of str2 to str1");
-    println!("");
-
-    println!("{}", "num_to_string : convert number to string and store in variable".blue());
-    println!("Examples:");
-    println!(" set str $(num_to_string 42)                        - convert number 42 to string");
-    println!(" set str_my_number_var num_to_string $my_number_var - convert variable value to string");
-    println!(" num_to_string $(get_random_num 1 100)              - convert command output to string");
-    println!("");
-
-    println!("{}", "clear : clear screen".blue());
-    println!("Examples");
-    println!(" clear - clear screen");
-    println!("");
-
-    println!("{}", "if : if operator".blue());
-    println!("Examples");
-    println!(" if $x == 0 then print 'x = 0'");
-    println!(" if $x == 0 then print 'x = 0' else print 'x != 0'");
-    println!(" if $input == 'grn' then get_random_num 1 100");
-    println!("");
-
-    println!("{}", "for : for command");
-    println!("Examples");
-    println!(" for i in 1..5 do print $i");
-    println!("");
-
-    println!("{}", "while : while command");
-    println!("Examples");
-    println!(" while $i < 10 do execute_file my_program.kf");
-    println!("");
-
-    println!("{}", "save_state : save all variables to file".blue());
-    println!("Examples:");
-    println!(" save_state state.txt                    - save to state.txt");
-    println!(" save_state $filename                   - save to variable filename");
-    println!(" save_state $(get_random_char 1).txt    - save to random filename");
-    println!("");
-
-    println!("{}", "load_state : load variables from file".blue());
-    println!("Examples:");
-    println!(" load_state state.txt                   - load from state.txt");
-    println!(" load_state $filename                   - load from variable filename");
-    println!(" load_state $(echo state).txt           - load from command result");
-    println!("");
-
-    println!("{}", "base64_encode : encode string with base64".blue());
-    println!("Examples:");
-    println!(" set encode_string $(base64_encode string)");
-    println!("");
-
-    println!("{}", "base64_decode : decode string with base64".blue());
-    println!("Examples:");
-    println!(" set decode_string $(base64_decode encode_string)");
-    println!("");
-
-    println!("{}", "remove_string_char : remove one char in string by index");
-    println!("Examples");
-    println!(" remove_string_char string_variable index");
-    println!("");
-
-    println!("{}", "Array and Dictionary Support".blue().bold());
-    println!("");
+    writeln!(out, "{}", "mul : for multiply values".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, "mul x 5                          - multiply x * 3 and store in x")?;
+    writeln!(out, "mul x y                          - multiply variables x y and store in x")?;
+    writeln!(out, "mul result $(get_random_num 2 5) - multiply random number by 10")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "div : for divide values".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, "div x 2                              - divide 10 / 2 and store in x")?;
+    writeln!(out, "div x z                              - divide y / z and store in x")?;
+    writeln!(out, "div result 100 $(get_random_num 2 5) - divide 100 by random number")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "push_to_string_back : append value to the end of a string variable".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " push_to_string_back my_string \" appended text\" - append literal text")?;
+    writeln!(out, " push_to_string_back my_string $(get_random_char) - append random character")?;
+    writeln!(out, " push_to_string_back str1 str2 - append value of str2 to str1")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "num_to_string : convert number to string and store in variable".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set str $(num_to_string 42)                        - convert number 42 to string")?;
+    writeln!(out, " set str_my_number_var num_to_string $my_number_var - convert variable value to string")?;
+    writeln!(out, " num_to_string $(get_random_num 1 100)              - convert command output to string")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "clear : clear screen".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " clear - clear screen")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "if : if operator".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " if $x == 0 then print 'x = 0'")?;
+    writeln!(out, " if $x == 0 then print 'x = 0' else print 'x != 0'")?;
+    writeln!(out, " if $input == 'grn' then get_random_num 1 100")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "for : for command".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " for i in 1..5 do print $i")?;
+    writeln!(out, " for i in 10..0 step 2 do print $i      - count down: 10 8 6 4 2")?;
+    writeln!(out, " for key, value in $dict do print $key  - iterate over a dictionary")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "while : while command".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " while $i < 10 do execute_file my_program.kf")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "fn : define a function".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " fn greet(name) {{ print \"Hello, $name\" }}")?;
+    writeln!(out, " fn double(x) {{ set r $x ; mul r 2 ; return $r }}")?;
+    writeln!(out, " set y $(double 21)                     - call and capture the return value")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "global : make a variable inside a block refer to the global one".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " for i in 0..3 {{ global last $i }}     - last survives the loop, i does not")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "getopt : parse the options a script was started with".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " key_forge gen.kf -v --length 24 out.txt - run a script with arguments")?;
+    writeln!(out, " print $argc $1                          - count and first argument ($argv holds all)")?;
+    writeln!(out, " getopt opts verbose|v length|l:         - flag -v/--verbose, option -l/--length <value>")?;
+    writeln!(out, " get opts length                         - 24, or null when not given")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "save_state : save all variables to file".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " save_state state.json                   - save as versioned JSON")?;
+    writeln!(out, " save_state state.toml                   - save as TOML (chosen by extension)")?;
+    writeln!(out, " save_state state.dat --format toml     - pick the format explicitly")?;
+    writeln!(out, " save_state $filename                   - save to variable filename")?;
+    writeln!(out, " save_state vault.json --encrypt        - encrypt with a passphrase")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "load_state : load variables from file".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " load_state state.json                  - replace all variables")?;
+    writeln!(out, " load_state state.json --merge          - keep variables not in the file")?;
+    writeln!(out, " load_state state.json key counter      - load only key and counter")?;
+    writeln!(out, " load_state old_state.txt               - old type:name:value files still load")?;
+    writeln!(out, " load_state vault.json --decrypt        - load an encrypted file")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "hex/base32/base58/base64/base64url _encode/_decode : binary-to-text codecs".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set encode_string $(base64_encode string)")?;
+    writeln!(out, " set decode_string $(base64_decode encode_string)")?;
+    writeln!(out, " base32_encode $secret --no-pad         - RFC 4648 base32 without = padding")?;
+    writeln!(out, " base58_encode $key                     - Bitcoin alphabet")?;
+    writeln!(out, " base64url_encode $key                  - URL-safe, no padding")?;
+    writeln!(out, " set key $(hex_decode 00ff10)           - non-UTF-8 data is stored as bytes")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "sha224/sha256/sha512 : hash text, variable or file with SHA-2".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " sha256 \"hello\"                        - hex digest of literal")?;
+    writeln!(out, " sha512 $data --base64                 - base64 digest of variable value")?;
+    writeln!(out, " sha256 --file data.bin                - digest of file contents")?;
+    writeln!(out, " set digest $(sha224 --file $filename) - store digest in variable")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "hmac_sign/hmac_verify : sign and verify messages with HMAC-SHA2".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " hmac_sign sha256 $key $payload                - hex tag of payload")?;
+    writeln!(out, " hmac_sign sha512 hex:6b6579 $payload --base64 - hex key, base64 tag")?;
+    writeln!(out, " set ok $(hmac_verify sha256 $key $payload $tag) - true or false")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "remove_string_char : remove one char in string by index".blue())?;
+    writeln!(out, "Examples")?;
+    writeln!(out, " remove_string_char string_variable index")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "Array and Dictionary Support".blue().bold())?;
+    writeln!(out)?;
     
-    println!("{}", "Arrays: ordered collections of values".blue());
-    println!("Examples:");
-    println!(" set arr [1, 2, 3, 4]                - create array with values");
-    println!(" push arr 5                          - add element to end of array");
-    println!(" pop arr                             - remove and return last element");
-    println!(" get arr 0                           - get element at index 0");
-    println!(" set arr 0 10                        - set element at index 0 to 10");
-    println!(" len arr                             - get length of array");
-    println!("");
-
-    println!("{}", "Dictionaries: key-value pairs".blue());
-    println!("Examples:");
-    println!(" set dict {{name: \"John\", age: 30}}   - create dictionary");
-    println!(" set dict name \"Jane\"               - set value for key");
-    println!(" get dict name                       - get value for key");
-    println!(" keys dict                           - get all keys");
-    println!(" values dict                         - get all values");
-    println!(" len dict                            - get number of key-value pairs");
-    println!("");
-
-    println!("{}", "write_file : write content to file with mode".blue());
-    println!("Examples:");
-    println!(" write_file \"output.txt\" \"Hello World\" \"w\"    - overwrite file");
-    println!(" write_file \"log.txt\" \"New entry\" \"a\"         - append to file");
-    println!(" write_file $filename $content \"w\"               - use variables");
-    println!(" write_file test.txt $(get_random_num 1 100) \"w\" - use command output");
-    println!("");
-
-    println!("{}", "read_file : read content from file".blue());
-    println!("Examples:");
-    println!(" read_file \"data.txt\"                          - read and print file content");
-    println!(" set content $(read_file \"config.txt\")         - read file into variable");
-    println!(" read_file $filename                            - read using variable");
-    println!(" read_file $(echo \"file\").txt                 - read using command output");
-    println!("");
-
-    println!("{}", "help : show this help message".blue());
+    writeln!(out, "{}", "Arrays: ordered collections of values".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set arr [1, 2, 3, 4]                - create array with values")?;
+    writeln!(out, " push arr 5                          - add element to end of array")?;
+    writeln!(out, " pop arr                             - remove and return last element")?;
+    writeln!(out, " get arr 0                           - get element at index 0")?;
+    writeln!(out, " set arr 0 10                        - set element at index 0 to 10")?;
+    writeln!(out, " len arr                             - get length of array")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "Dictionaries: key-value pairs".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set dict {{name: \"John\", age: 30}}   - create dictionary")?;
+    writeln!(out, " set dict name \"Jane\"               - set value for key")?;
+    writeln!(out, " get dict name                       - get value for key")?;
+    writeln!(out, " keys dict                           - get all keys")?;
+    writeln!(out, " values dict                         - get all values")?;
+    writeln!(out, " len dict                            - get number of key-value pairs")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "json_parse/json_stringify/json_get : read and write JSON".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " set cfg $(json_parse $(read_file config.json)) - JSON text to nested dict/array")?;
+    writeln!(out, " json_get cfg servers.0.host         - dotted path")?;
+    writeln!(out, " json_get cfg /servers/0/host        - JSON Pointer")?;
+    writeln!(out, " json_stringify cfg --pretty         - variable as indented JSON")?;
+    writeln!(out, " to_file out.json json_stringify cfg - write a JSON fixture")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "write_file : write content to file with mode".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " write_file \"output.txt\" \"Hello World\" \"w\"    - overwrite file")?;
+    writeln!(out, " write_file \"log.txt\" \"New entry\" \"a\"         - append to file")?;
+    writeln!(out, " write_file $filename $content \"w\"               - use variables")?;
+    writeln!(out, " write_file test.txt $(get_random_num 1 100) \"w\" - use command output")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "read_file : read content from file".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " read_file \"data.txt\"                          - read and print file content")?;
+    writeln!(out, " set content $(read_file \"config.txt\")         - read file into variable")?;
+    writeln!(out, " read_file $filename                            - read using variable")?;
+    writeln!(out, " read_file $(echo \"file\").txt                 - read using command output")?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "help : show this help message".blue())?;
+    writeln!(out)?;
+
+    writeln!(out, "{}", "REPL commands : only at the interactive prompt".blue())?;
+    writeln!(out, "Examples:")?;
+    writeln!(out, " :reset                 - clear all variables (functions are kept)")?;
+    writeln!(out, " :load setup.kf         - run a script in the current session")?;
+    writeln!(out, " :time gen_password 64  - run a statement and show how long it took")?;
+    writeln!(out, " :history               - list the saved history")?;
+    writeln!(out, " :save-session demo.kf  - write the commands that succeeded as a script")?;
+    Ok(())
 }
 
-pub fn show_command_list() {
-    println!("{}: {}", "get_random_num".blue(), "use for get random num with diapason");
-    println!("{}: {}", "repeat".blue(), "use for repeat one command n times");
-    println!("{}: {}", "set".blue(), "use for set variable with value");
-    println!("{}: {}", "print".blue(), "use for print variable value or literal");
-    println!("{}: {}", "exit/quit".blue(), "exit the program");
-    println!("{}: {}", "vl".blue(), "use for show variables list");
-    println!("{}: {}", "execute_file".blue(), "for execute commands in file");
-    println!("{}: {}", "to_file".blue(), "use for write output to file");
-    println!("{}: {}", "add".blue(), "for add value to variable");
-    println!("{}: {}", "mul".blue(), "for multiply values");
-    println!("{}: {}", "div".blue(), "for divide values");
-    println!("{}: {}", "if".blue(), "if operator");
-    println!("{}: {}", "for".blue(), "for operator");
-    println!("{}: {}", "while".blue(), "while operator");
-    println!("{}: {}", "push_to_string_back".blue(), "for push to string back other string");
-    println!("{}: {}", "num_to_string".blue(), "convert number to string and return string");
-    println!("{}: {}", "base64_encode".blue(), "encode string with base64");
-    println!("{}: {}", "base64_decode".blue(), "decode string with base64");
-    println!("{}: {}", "remove_string_char".blue(), "remove one char in string by index");
-    println!("{}: {}", "push".blue(), "add element to array");
-    println!("{}: {}", "pop".blue(), "remove and return last element from array");
-    println!("{}: {}", "len".blue(), "get length of array, dictionary, or string");
-    println!("{}: {}", "keys".blue(), "get all keys from dictionary");
-    println!("{}: {}", "values".blue(), "get all values from dictionary");
-    println!("{}: {}", "get".blue(), "get element from array or dictionary");
-    println!("{}: {}", "write_file".blue(), "write content to file with mode selection");
-    println!("{}: {}", "read_file".blue(), "read content from file");
-    println!("{}: {}", "help".blue(), "show all commands");
+pub fn show_command_list(out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}: use for get random num with diapason", "get_random_num".blue())?;
+    writeln!(out, "{}: seed the random commands for reproducible output", "seed".blue())?;
+    writeln!(out, "{}: generate password with cryptographically secure random", "gen_password".blue())?;
+    writeln!(out, "{}: generate random bytes as hex, base64, base64url or bytes", "gen_bytes".blue())?;
+    writeln!(out, "{}: generate an API token with a prefix and optional checksum", "gen_token".blue())?;
+    writeln!(out, "{}: check the checksum of a token", "verify_token".blue())?;
+    writeln!(out, "{}: generate random, time-ordered or nil UUID", "uuid_v4/uuid_v7/uuid_nil".blue())?;
+    writeln!(out, "{}: validate UUID and return its version", "uuid_parse".blue())?;
+    writeln!(out, "{}: use for repeat one command n times", "repeat".blue())?;
+    writeln!(out, "{}: use for set variable with value", "set".blue())?;
+    writeln!(out, "{}: use for print variable value or literal", "print".blue())?;
+    writeln!(out, "{}: exit the program", "exit/quit".blue())?;
+    writeln!(out, "{}: use for show variables list", "vl".blue())?;
+    writeln!(out, "{}: remove variables", "rm".blue())?;
+    writeln!(out, "{}: show the type of a variable", "type_of".blue())?;
+    writeln!(out, "{}: for execute commands in file", "execute_file".blue())?;
+    writeln!(out, "{}: use for write output to file", "to_file".blue())?;
+    writeln!(out, "{}: for add value to variable", "add".blue())?;
+    writeln!(out, "{}: for multiply values", "mul".blue())?;
+    writeln!(out, "{}: for divide values", "div".blue())?;
+    writeln!(out, "{}: if operator", "if".blue())?;
+    writeln!(out, "{}: for operator", "for".blue())?;
+    writeln!(out, "{}: while operator", "while".blue())?;
+    writeln!(out, "{}: define a function with parameters", "fn".blue())?;
+    writeln!(out, "{}: return a value from a function", "return".blue())?;
+    writeln!(out, "{}: declare a global variable inside a block or function", "global".blue())?;
+    writeln!(out, "{}: parse script options into a dictionary", "getopt".blue())?;
+    writeln!(out, "{}: for push to string back other string", "push_to_string_back".blue())?;
+    writeln!(out, "{}: convert number to string and return string", "num_to_string".blue())?;
+    writeln!(out, "{}: encode string with base64", "base64_encode".blue())?;
+    writeln!(out, "{}: decode string with base64", "base64_decode".blue())?;
+    writeln!(out, "{}: encode or decode hex", "hex_encode/hex_decode".blue())?;
+    writeln!(out, "{}: encode or decode RFC 4648 base32", "base32_encode/base32_decode".blue())?;
+    writeln!(out, "{}: encode or decode base58 (Bitcoin alphabet)", "base58_encode/base58_decode".blue())?;
+    writeln!(out, "{}: encode or decode URL-safe base64 without padding", "base64url_encode/base64url_decode".blue())?;
+    writeln!(out, "{}: hash text, variable or file with SHA-2", "sha224/sha256/sha512".blue())?;
+    writeln!(out, "{}: sign message with HMAC-SHA2", "hmac_sign".blue())?;
+    writeln!(out, "{}: verify HMAC-SHA2 tag in constant time", "hmac_verify".blue())?;
+    writeln!(out, "{}: remove one char in string by index", "remove_string_char".blue())?;
+    writeln!(out, "{}: add element to array", "push".blue())?;
+    writeln!(out, "{}: remove and return last element from array", "pop".blue())?;
+    writeln!(out, "{}: get length of array, dictionary, or string", "len".blue())?;
+    writeln!(out, "{}: get all keys from dictionary", "keys".blue())?;
+    writeln!(out, "{}: get all values from dictionary", "values".blue())?;
+    writeln!(out, "{}: get element from array or dictionary", "get".blue())?;
+    writeln!(out, "{}: turn JSON text into nested arrays and dictionaries", "json_parse".blue())?;
+    writeln!(out, "{}: write a variable as JSON", "json_stringify".blue())?;
+    writeln!(out, "{}: get a nested value by dotted path or JSON Pointer", "json_get".blue())?;
+    writeln!(out, "{}: write content to file with mode selection", "write_file".blue())?;
+    writeln!(out, "{}: read content from file", "read_file".blue())?;
+    writeln!(out, "{}: show all commands", "help".blue())?;
+    Ok(())
 }
 
-pub fn show_command_help(name: &str) {
+pub fn show_command_help(out: &mut dyn Write, name: &str) -> io::Result<()> {
     match name {
         "get_random_num" => {
-            println!("{}", "get_random_num <min> <max>".green());
-            println!("Generate random integer or float in range [min, max) (min < max)");
-            println!("Examples:");
-            println!(" get_random_num 1 100");
-            println!(" get_random_num 0.5 5.5");
+            writeln!(out, "{}", "get_random_num <min> <max>".green())?;
+            writeln!(out, "Generate random integer or float in range [min, max) (min < max)")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " get_random_num 1 100")?;
+            writeln!(out, " get_random_num 0.5 5.5")?;
         }
         "get_random_char" => {
-            println!("{}", "get_random_char [mode]".green());
-            println!("Return a random character from alphabet. mode=1 for uppercase");
-            println!("Examples:");
-            println!(" get_random_char");
-            println!(" get_random_char 1");
+            writeln!(out, "{}", "get_random_char [mode]".green())?;
+            writeln!(out, "Return a random character from alphabet. mode=1 for uppercase")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " get_random_char")?;
+            writeln!(out, " get_random_char 1")?;
+        }
+        "seed" => {
+            writeln!(out, "{}", "seed [<u64>|reset]".green())?;
+            writeln!(out, "Switch every random command (get_random_num, get_random_char, gen_password,")?;
+            writeln!(out, "gen_bytes, gen_token, uuid_v4, uuid_v7) to a ChaCha8 stream seeded with <u64>, so a")?;
+            writeln!(out, "script produces the same output on every run. Seeded output is predictable: use it")?;
+            writeln!(out, "for test data only; gen_password, gen_bytes and gen_token warn on stderr the first")?;
+            writeln!(out, "time they run seeded. Seeded uuid_v7 counts up from 2024-01-01T00:00:00Z instead of the")?;
+            writeln!(out, "current time. The salt and nonce of save_state --encrypt always come from the OS.")?;
+            writeln!(out, "seed reset returns to OS entropy;")?;
+            writeln!(out, "seed alone shows the current seed, or os. key_forge --seed <u64> seeds at startup.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " seed 42")?;
+            writeln!(out, " seed reset")?;
+        }
+        "gen_password" => {
+            writeln!(out, "{}", "gen_password [length] [--lower] [--upper] [--digits] [--symbols] [--no-ambiguous] [--min <n>] [--alphabet <chars>]".green())?;
+            writeln!(out, "Generate a password using the operating system CSPRNG. Default length is 16, at most 4096.")?;
+            writeln!(out, "Without class flags all four classes (lower, upper, digits, symbols) are used.")?;
+            writeln!(out, "  --no-ambiguous   - exclude easily confused characters (0 O 1 l I |)")?;
+            writeln!(out, "  --min <n>        - at least n characters from every selected class")?;
+            writeln!(out, "  --alphabet <str> - draw only from the given characters (class flags are ignored)")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " gen_password 24 --min 2")?;
+            writeln!(out, " set pass $(gen_password 12 --lower --digits --no-ambiguous)")?;
+        }
+        "uuid_v4" | "uuid_v7" | "uuid_nil" => {
+            writeln!(out, "{}", "uuid_v4 | uuid_v7 | uuid_nil".green())?;
+            writeln!(out, "uuid_v4 returns a random UUID, uuid_v7 a time-ordered UUID (sorts in creation order),")?;
+            writeln!(out, "uuid_nil the all-zero UUID. Works with repeat, to_file and $(...) capture.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " repeat 10 uuid_v7")?;
+            writeln!(out, " to_file ids.txt repeat 1000 uuid_v4")?;
+            writeln!(out, " set id $(uuid_v7)")?;
+        }
+        "gen_bytes" => {
+            writeln!(out, "{}", "gen_bytes <n> [hex|base64|base64url|bytes]".green())?;
+            writeln!(out, "Generate n cryptographically secure random bytes. Output is hex by default;")?;
+            writeln!(out, "bytes gives a 0x... literal, so $(...) stores a bytes value.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " gen_bytes 32")?;
+            writeln!(out, " set key $(gen_bytes 32 bytes)")?;
+        }
+        "gen_token" => {
+            writeln!(out, "{}", "gen_token <prefix> <n> [--checksum crc32|sha256]".green())?;
+            writeln!(out, "Generate <prefix> followed by n random base62 characters. With --checksum, a")?;
+            writeln!(out, "checksum of everything before it is appended (crc32: 6 characters, sha256: the")?;
+            writeln!(out, "first 6 bytes of SHA-256 in 9 characters), so typos are caught without a lookup.")?;
+            writeln!(out, "The prefix may contain letters, digits, _ and -.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " gen_token kf_live_ 30")?;
+            writeln!(out, " set api_key $(gen_token kf_live_ 30 --checksum crc32)")?;
+        }
+        "verify_token" => {
+            writeln!(out, "{}", "verify_token <token|$var> <crc32|sha256>".green())?;
+            writeln!(out, "Check the checksum suffix of a token made with gen_token --checksum. Returns true or false.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " if $(verify_token $api_key crc32) == true then print \"well-formed\"")?;
+        }
+        "uuid_parse" => {
+            writeln!(out, "{}", "uuid_parse <uuid|$var>".green())?;
+            writeln!(out, "Validate a UUID and return a dictionary with uuid, version and variant keys.")?;
+            writeln!(out, "Time-based UUIDs (v1, v6, v7) also get timestamp_ms.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set info $(uuid_parse $id)")?;
+            writeln!(out, " get info version")?;
         }
         "repeat" => {
-            println!("{}", "repeat <count> <command>".green());
-            println!("Execute <command> <count> times. Command can be a substitution using $(...) .");
-            println!("Example: repeat 10 get_random_num 1 100");
+            writeln!(out, "{}", "repeat <count> <command>".green())?;
+            writeln!(out, "Execute <command> <count> times. Command can be a substitution using $(...) .")?;
+            writeln!(out, "Example: repeat 10 get_random_num 1 100")?;
         }
         "set" => {
-            println!("{}", "set <name> <value>".green());
-            println!("Set variable <name> to <value>. Value may be an int, float, quoted string,");
-            println!("another variable (using $name), or a command substitution: $(command)");
-            println!("Examples:");
-            println!(" set my_var 42");
-            println!(" set my_var \"hello\"");
-            println!(" set my_var $(get_random_num 1 100)");
+            writeln!(out, "{}", "set <name> <value>".green())?;
+            writeln!(out, "Set variable <name> to <value>. Value may be an int, float, quoted string,")?;
+            writeln!(out, "another variable (using $name), or a command substitution: $(command)")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set my_var 42")?;
+            writeln!(out, " set my_var \"hello\"")?;
+            writeln!(out, " set my_var $(get_random_num 1 100)")?;
+            writeln!(out, "Arithmetic with + - * / % ** and parentheses is evaluated; int / int truncates:")?;
+            writeln!(out, " set z ($x * 2 + $y) / 3")?;
+            writeln!(out, " set n $(len items) - 1")?;
+            writeln!(out, "Operators between plain numbers need spaces (set x 3 * 4), so 2024-01-05 stays a string.")?;
         }
         "print" => {
-            println!("{}", "print <name or literal>".green());
-            println!("Print variable value or literal. Variables can be referenced with $name inside strings.");
-            println!("Examples:");
-            println!(" print my_var");
-            println!(" print \"Hello $name\"");
+            writeln!(out, "{}", "print <name or literal>".green())?;
+            writeln!(out, "Print variable value or literal. Variables can be referenced with $name inside strings.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " print my_var")?;
+            writeln!(out, " print \"Hello $name\"")?;
         }
         "vl" => {
-            println!("{}", "vl [i|f|s]".green());
-            println!("Show variables. i - ints, f - floats, s - strings. Without arg shows all.");
+            writeln!(out, "{}", "vl [type] [pattern]".green())?;
+            writeln!(out, "Show variables sorted by name and grouped by type. Without arguments shows all.")?;
+            writeln!(out, "Types: int (i), float (f), string (s), bool (b), null (n), bytes (x), array (a), dict (d),")?;
+            writeln!(out, "or o for bools, nulls and bytes. The pattern matches names; * stands for any text.")?;
+            writeln!(out, "Inside a block, loop or function the visible local scopes are listed first, innermost first.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " vl a")?;
+            writeln!(out, " vl api_*")?;
+            writeln!(out, " vl string *_name")?;
+        }
+        "rm" => {
+            writeln!(out, "{}", "rm <name...>".green())?;
+            writeln!(out, "Remove one or more variables of any type. Fails if a variable does not exist.")?;
+        }
+        "type_of" => {
+            writeln!(out, "{}", "type_of <name>".green())?;
+            writeln!(out, "Print the type of a variable: int, float, string, bool, null, bytes, array or dict.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " type_of x")?;
+            writeln!(out, " set t $(type_of x)")?;
         }
         "to_file" => {
-            println!("{}", "to_file <filename> <command...>".green());
-            println!("Execute <command...> and append its output to <filename>. Command can be $(...) substitution.");
+            writeln!(out, "{}", "to_file <filename> <command...>".green())?;
+            writeln!(out, "Execute <command...> and append its output to <filename>. Command can be $(...) substitution.")?;
         }
         "add" | "sub" | "mul" | "div" => {
-            println!("{}", "<op> <var> <value>".green());
-            println!("Arithmetic operations on variables. Supported ops: add, sub, mul, div.");
-            println!("Examples:");
-            println!(" add x 1");
-            println!(" mul x 2");
+            writeln!(out, "{}", "<op> <var> <value>".green())?;
+            writeln!(out, "Arithmetic operations on variables. Supported ops: add, sub, mul, div.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " add x 1")?;
+            writeln!(out, " mul x 2")?;
         }
         "num_to_string" => {
-            println!("{}", "num_to_string : convert number to string and store in variable".blue());
-            println!("Examples:");
-            println!(" set str $(num_to_string 42)                        - convert number 42 to string");
-            println!(" set str_my_number_var num_to_string $my_number_var - convert variable value to string");
-            println!(" num_to_string $(get_random_num 1 100)              - convert command output to string");
-            println!("");
+            writeln!(out, "{}", "num_to_string : convert number to string and store in variable".blue())?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set str $(num_to_string 42)                        - convert number 42 to string")?;
+            writeln!(out, " set str_my_number_var num_to_string $my_number_var - convert variable value to string")?;
+            writeln!(out, " num_to_string $(get_random_num 1 100)              - convert command output to string")?;
+            writeln!(out)?;
         }
         "push_to_string_back" => {
-            println!("{}", "push_to_string_back <variable_name> <value>".green());
-            println!("Append value to end of string variable (creates variable if missing). Value may be variable or $(...) command.");
+            writeln!(out, "{}", "push_to_string_back <variable_name> <value>".green())?;
+            writeln!(out, "Append value to end of string variable (creates variable if missing). Value may be variable or $(...) command.")?;
         }
         "if" => {
-            println!("{}", "if <condition> then <command> [else <command>]".green());
-            println!("Evaluate condition and execute then/else command. Conditions support ==, !=, <, >, <=, >= and and/or.");
+            writeln!(out, "{}", "if <condition> then <command> [else <command>]".green())?;
+            writeln!(out, "Evaluate condition and execute then/else command. Conditions support ==, !=, <, >, <=, >= and and/or; a single value such as $flag is tested for truthiness.")?;
+            writeln!(out, "Both sides of a comparison may be arithmetic: if $a + 1 > $b * 2 then ...")?;
         }
         "while" => {
-            println!("{}", "while <condition> do <command|{ ... }>".green());
-            println!("Execute loop while condition is true. Body may be a single command or a block {{ ... }}.");
+            writeln!(out, "{}", "while <condition> do <command|{ ... }>".green())?;
+            writeln!(out, "Execute loop while condition is true. Body may be a single command or a block {{ ... }}.")?;
         }
         "for" => {
-            println!("{}", "for <var> in <start>..<end> do <command|{ ... }>".green());
-            println!("Loop variable takes values start..end-1. Body may be single command or block {{ ... }}.");
+            writeln!(out, "{}", "for <var> in <start>..<end> [step <n>] do <command|{ ... }>".green())?;
+            writeln!(out, "{}", "for <var>[, <var>] in <value> do <command|{ ... }>".green())?;
+            writeln!(out, "Loop variable takes values start..end-1, or counts down to end+1 when start > end. Body may be single command or block {{ ... }}.")?;
+            writeln!(out, "Bounds may be numbers, $variables or $(...); step must be a positive integer.")?;
+            writeln!(out, "Over an array the variables get the element (or index, element), over a dictionary the key")?;
+            writeln!(out, "(or key, value) in sorted key order, and over a string each character (or index, character).")?;
+            writeln!(out, "The loop variable only exists inside the loop; an outer variable with the same name is left untouched.")?;
+        }
+        "fn" => {
+            writeln!(out, "{}", "fn <name>(<param>, ...) { ... }".green())?;
+            writeln!(out, "Define a function. Call it like a command: <name> <arg> ..., or capture its result with $(<name> ...).")?;
+            writeln!(out, "Parameters and variables assigned inside the body are local to the call unless declared with global. Calls may recurse up to {} levels.", super::interpreter::MAX_CALL_DEPTH)?;
+        }
+        "global" => {
+            writeln!(out, "{}", "global <name> [value]".green())?;
+            writeln!(out, "Variables created inside {{ ... }} blocks, loops and functions are local to them.")?;
+            writeln!(out, "After 'global name' assignments to name in the current scope go to the global variable instead.")?;
+            writeln!(out, "With a value it also assigns it, like set.")?;
+        }
+        "getopt" => {
+            writeln!(out, "{}", "getopt <dict_name> [name|n[:]...]".green())?;
+            writeln!(out, "Parse the options of the script arguments ($argv) into a dictionary. Each spec is a")?;
+            writeln!(out, "long name, optionally with a one-letter short name after '|', and a trailing ':' when")?;
+            writeln!(out, "the option takes a value. Flags become true/false, options their value or null.")?;
+            writeln!(out, "Accepts --name, --name value, --name=value, -n value, -nvalue and grouped flags (-vq);")?;
+            writeln!(out, "-- ends the options. Afterwards $argv, $argc and $1..$N hold the other arguments.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " getopt opts verbose|v output|o: count:")?;
+            writeln!(out, " if $(get opts verbose) == true then print $1")?;
+        }
+        "return" => {
+            writeln!(out, "{}", "return [value]".green())?;
+            writeln!(out, "Leave the current function. The value becomes the result of $(<name> ...).")?;
+            writeln!(out, "An arithmetic value such as `return $a + $b` is computed like `set` does.")?;
         }
         "break" => {
-            println!("{}", "break".green());
-            println!("Break out of the nearest loop (only valid in interactive/file mode, not in assignments).");
+            writeln!(out, "{}", "break".green())?;
+            writeln!(out, "Break out of the nearest loop (only valid in interactive/file mode, not in assignments).")?;
         }
         "continue" => {
-            println!("{}", "continue".green());
-            println!("Skip to next iteration of the nearest loop (only valid in interactive/file mode).");
+            writeln!(out, "{}", "continue".green())?;
+            writeln!(out, "Skip to next iteration of the nearest loop (only valid in interactive/file mode).")?;
         }
         "help" => {
-            println!("{}", "help [command]".green());
-            println!("Show general help or help for a single command: help get_random_num");
+            writeln!(out, "{}", "help [command]".green())?;
+            writeln!(out, "Show general help or help for a single command: help get_random_num")?;
         }
 
         "save_state" => {
-            println!("{}", "save_state <filename>".green());
-            println!("Save all variables to a file. Filename can be:");
-            println!("  - direct string: save_state state.txt");
-            println!("  - variable: save_state $filename");
-            println!("  - command result: save_state $(command)");
-            println!("");
+            writeln!(out, "{}", "save_state <filename> [--format json|toml] [--encrypt]".green())?;
+            writeln!(out, "Save all global variables to a file. The file is written under a temporary")?;
+            writeln!(out, "name and renamed into place, so an interrupted save keeps the old file.")?;
+            writeln!(out, "The format is TOML for .toml files and versioned JSON otherwise. Filename can be:")?;
+            writeln!(out, "  - direct string: save_state state.json")?;
+            writeln!(out, "  - variable: save_state $filename")?;
+            writeln!(out, "  - command result: save_state $(command)")?;
+            writeln!(out, "--encrypt seals the file with ChaCha20-Poly1305 under an Argon2id key derived from a")?;
+            writeln!(out, "passphrase. It is prompted for at the prompt and read from KEY_FORGE_PASSPHRASE in")?;
+            writeln!(out, "file and argument mode.")?;
+            writeln!(out)?;
         }
 
         "load_state" => {
-            println!("{}", "load_state <filename>".green());
-            println!("Load variables from file (replaces current state). Filename can be:");
-            println!("  - direct string: load_state state.txt");
-            println!("  - variable: load_state $filename");
-            println!("  - command result: load_state $(command)");
-            println!("");
+            writeln!(out, "{}", "load_state <filename> [--merge] [--decrypt] [name...]".green())?;
+            writeln!(out, "Load variables from a JSON, TOML or old-style type:name:value file; the format")?;
+            writeln!(out, "is detected from the content. The whole file is checked before anything changes.")?;
+            writeln!(out, "  - load_state state.json               - replace all variables")?;
+            writeln!(out, "  - load_state state.json --merge       - keep variables that are not in the file")?;
+            writeln!(out, "  - load_state state.json a b           - load only a and b, keep everything else")?;
+            writeln!(out, "  - load_state vault.json --decrypt     - load a file saved with --encrypt")?;
+            writeln!(out)?;
+        }
+
+        "hex_encode" | "base32_encode" | "base58_encode" | "base64_encode" | "base64url_encode" => {
+            writeln!(out, "{}", format!("{} <text|$var|$(command)>", name).green())?;
+            writeln!(out, "Encode text, or the raw bytes of a bytes variable. A bare variable name also works.")?;
+            writeln!(out, "  - hex_encode: lower-case hex digits")?;
+            writeln!(out, "  - base32_encode: RFC 4648 base32, add --no-pad to drop the = padding")?;
+            writeln!(out, "  - base58_encode: Bitcoin alphabet, no 0, O, I or l")?;
+            writeln!(out, "  - base64_encode: standard base64 with padding")?;
+            writeln!(out, "  - base64url_encode: URL-safe base64 (- and _) without padding")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set encode_string $(base64_encode string)")?;
+            writeln!(out, " base32_encode $secret --no-pad")?;
+            writeln!(out)?;
+        }
+
+        "hex_decode" | "base32_decode" | "base58_decode" | "base64_decode" | "base64url_decode" => {
+            writeln!(out, "{}", format!("{} <text|$var|$(command)>", name).green())?;
+            writeln!(out, "Decode text in the matching encoding. The result is a string when it is valid")?;
+            writeln!(out, "UTF-8 and a 0x... bytes literal otherwise, so $(...) stores it as bytes.")?;
+            writeln!(out, "hex_decode accepts a 0x prefix; base32_decode and base64url_decode accept input")?;
+            writeln!(out, "with or without padding.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set decode_string $(base64_decode encode_string)")?;
+            writeln!(out, " set key $(base58_decode $encoded_key)")?;
+            writeln!(out)?;
+        }
+
+        "sha224" | "sha256" | "sha512" => {
+            writeln!(out, "{}", "sha256 <text|$var|$(command)> [--hex|--base64]".green())?;
+            writeln!(out, "{}", "sha256 --file <filename> [--hex|--base64]".green())?;
+            writeln!(out, "Hash a literal, a variable value or a file's contents. sha224 and sha512 work the same way.")?;
+            writeln!(out, "Output is hex by default, --base64 selects base64. Use $(...) to store the digest.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " sha256 \"hello world\"")?;
+            writeln!(out, " sha512 $secret --base64")?;
+            writeln!(out, " set digest $(sha256 --file $filename)")?;
+            writeln!(out, " write_file digest.txt $(sha224 --file data.bin) \"w\"")?;
         }
 
-        "base64_encode" => {
-            println!("{}", "base64_encode : encode string with base64".blue());
-            println!("Examples:");
-            println!(" set encode_string $(base64_encode string)");
-            println!("");
+        "hmac_sign" => {
+            writeln!(out, "{}", "hmac_sign <sha224|sha256|sha512> <key> <message> [--hex|--base64]".green())?;
+            writeln!(out, "Compute HMAC tag of message. Key is raw text, hex:<digits> / base64:<data>, or a bytes variable.")?;
+            writeln!(out, "Key and message can be literals, $variables or $(command) output.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " hmac_sign sha256 \"secret\" \"payload\"")?;
+            writeln!(out, " set tag $(hmac_sign sha256 base64:c2VjcmV0 $body --base64)")?;
         }
 
-        "base64_decode" => {
-            println!("{}", "base64_decode : decode string with base64".blue());
-            println!("Examples:");
-            println!(" set decode_string $(base64_decode encode_string)");
-            println!("");            
+        "hmac_verify" => {
+            writeln!(out, "{}", "hmac_verify <sha224|sha256|sha512> <key> <message> <tag>".green())?;
+            writeln!(out, "Check a hex or base64 tag in constant time. Returns true or false.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set ok $(hmac_verify sha256 $key $body $tag)")?;
+            writeln!(out, " if $ok == true then print \"signature valid\"")?;
         }
 
         "remove_string_char" => {
-            println!("{}", "remove_string_char : remove one char in string by index");
-            println!("Examples");
-            println!(" remove_string_char string_variable index");
-            println!("");
+            writeln!(out, "{}", "remove_string_char : remove one char in string by index".blue())?;
+            writeln!(out, "Examples")?;
+            writeln!(out, " remove_string_char string_variable index")?;
+            writeln!(out)?;
         }
 
         
         "push" => {
-            println!("{}", "push <array_name> <value>".green());
-            println!("Add an element to the end of an array. Creates the array if it doesn't exist.");
-            println!("Examples:");
-            println!(" push my_array 42");
-            println!(" push my_array \"hello\"");
-            println!(" push my_array $(get_random_num 1 100)");
+            writeln!(out, "{}", "push <array_name> <value>".green())?;
+            writeln!(out, "Add an element to the end of an array. Creates the array if it doesn't exist.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " push my_array 42")?;
+            writeln!(out, " push my_array \"hello\"")?;
+            writeln!(out, " push my_array $(get_random_num 1 100)")?;
         }
         
         "pop" => {
-            println!("{}", "pop <array_name>".green());
-            println!("Remove and return the last element of an array.");
-            println!("Examples:");
-            println!(" pop my_array");
+            writeln!(out, "{}", "pop <array_name>".green())?;
+            writeln!(out, "Remove and return the last element of an array.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " pop my_array")?;
         }
         
         "len" => {
-            println!("{}", "len <variable_name>".green());
-            println!("Get the length of an array, dictionary, or string.");
-            println!("Examples:");
-            println!(" len my_array    - number of elements in array");
-            println!(" len my_dict     - number of key-value pairs");
-            println!(" len my_string   - number of characters");
+            writeln!(out, "{}", "len <variable_name>".green())?;
+            writeln!(out, "Get the length of an array, dictionary, or string.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " len my_array    - number of elements in array")?;
+            writeln!(out, " len my_dict     - number of key-value pairs")?;
+            writeln!(out, " len my_string   - number of characters")?;
         }
         
         "keys" => {
-            println!("{}", "keys <dict_name>".green());
-            println!("Get all keys from a dictionary as an array.");
-            println!("Examples:");
-            println!(" keys my_dict");
+            writeln!(out, "{}", "keys <dict_name>".green())?;
+            writeln!(out, "Get all keys from a dictionary as an array.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " keys my_dict")?;
         }
         
         "values" => {
-            println!("{}", "values <dict_name>".green());
-            println!("Get all values from a dictionary as an array.");
-            println!("Examples:");
-            println!(" values my_dict");
+            writeln!(out, "{}", "values <dict_name>".green())?;
+            writeln!(out, "Get all values from a dictionary as an array.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " values my_dict")?;
         }
         
         "get" => {
-            println!("{}", "get <collection_name> <key/index>".green());
-            println!("Get a value from an array (by index) or dictionary (by key).");
-            println!("Examples:");
-            println!(" get my_array 0     - get first element of array");
-            println!(" get my_dict name   - get value for key 'name'");
+            writeln!(out, "{}", "get <collection_name> <key/index>".green())?;
+            writeln!(out, "Get a value from an array (by index) or dictionary (by key).")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " get my_array 0     - get first element of array")?;
+            writeln!(out, " get my_dict name   - get value for key 'name'")?;
+        }
+
+        "json_parse" => {
+            writeln!(out, "{}", "json_parse <text|$var>".green())?;
+            writeln!(out, "Parse JSON text into nested arrays and dictionaries. Integers stay integers,")?;
+            writeln!(out, "other numbers become floats; true, false and null keep their types.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " set cfg $(json_parse $(read_file config.json))")?;
+            writeln!(out, " set list $(json_parse '[1, 2, 3]')")?;
+        }
+
+        "json_stringify" => {
+            writeln!(out, "{}", "json_stringify <var> [--pretty]".green())?;
+            writeln!(out, "Write a variable as JSON with sorted keys, on one line or indented with --pretty.")?;
+            writeln!(out, "Bytes become a hex string and NaN or infinite floats become null.")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " json_stringify cfg --pretty")?;
+            writeln!(out, " to_file fixture.json json_stringify cfg")?;
+            writeln!(out, " set text \"$(json_stringify cfg)\"   - keep the JSON as a string")?;
+        }
+
+        "json_get" => {
+            writeln!(out, "{}", "json_get <var> <path>".green())?;
+            writeln!(out, "Get a nested value from arrays and dictionaries. The path is dotted (a.0.b) or,")?;
+            writeln!(out, "when it starts with /, a JSON Pointer (/a/0/b, with ~1 for / and ~0 for ~).")?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, " json_get cfg servers.0.host")?;
+            writeln!(out, " set port $(json_get cfg /servers/0/port)")?;
         }
 
         "write_file" => {
-            println!("{}", "write_file <filename> <content> <append>".green());
-            println!("Write content to file. Supports variables ($var) and command substitution ($(...)).");
-            println!("");
-            println!("Parameters:");
-            println!("  filename - can be string, variable, or command output");
-            println!("  content  - can be string, variable, or command output");
-            println!("  append   - 'w' to overwrite, 'a' to append to file");
-            println!("");
-            println!("Examples:");
-            println!("  write_file \"log.txt\" \"New message\" \"a\"      - append to file");
-            println!("  write_file $fname \"Hello\" \"w\"               - use variable for filename");
-            println!("  write_file out.txt $(get_random_char) \"w\"     - use command output");
-            println!("  write_file data.txt $content \"w\"              - use variable for content");
+            writeln!(out, "{}", "write_file <filename> <content> <append>".green())?;
+            writeln!(out, "Write content to file. Supports variables ($var) and command substitution ($(...)).")?;
+            writeln!(out)?;
+            writeln!(out, "Parameters:")?;
+            writeln!(out, "  filename - can be string, variable, or command output")?;
+            writeln!(out, "  content  - can be string, variable, or command output")?;
+            writeln!(out, "  append   - 'w' to overwrite, 'a' to append to file")?;
+            writeln!(out)?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, "  write_file \"log.txt\" \"New message\" \"a\"      - append to file")?;
+            writeln!(out, "  write_file $fname \"Hello\" \"w\"               - use variable for filename")?;
+            writeln!(out, "  write_file out.txt $(get_random_char) \"w\"     - use command output")?;
+            writeln!(out, "  write_file data.txt $content \"w\"              - use variable for content")?;
         }
         
         "read_file" => {
-            println!("{}", "read_file <filename>".green());
-            println!("Read entire file content. Supports variables ($var) and command substitution ($(...)).");
-            println!("Returns file content as string that can be captured in variable.");
-            println!("");
-            println!("Examples:");
-            println!("  read_file \"config.txt\"                    - read and print file");
-            println!("  set data $(read_file \"data.json\")         - store file content in variable");
-            println!("  read_file $filename                        - read using variable");
-            println!("  read_file $(echo \"file\").txt             - read using command output");
-            println!("  print $(read_file \"notes.txt\")           - print file content directly");
+            writeln!(out, "{}", "read_file <filename>".green())?;
+            writeln!(out, "Read entire file content. Supports variables ($var) and command substitution ($(...)).")?;
+            writeln!(out, "Returns file content as string that can be captured in variable.")?;
+            writeln!(out)?;
+            writeln!(out, "Examples:")?;
+            writeln!(out, "  read_file \"config.txt\"                    - read and print file")?;
+            writeln!(out, "  set data $(read_file \"data.json\")         - store file content in variable")?;
+            writeln!(out, "  read_file $filename                        - read using variable")?;
+            writeln!(out, "  read_file $(echo \"file\").txt             - read using command output")?;
+            writeln!(out, "  print $(read_file \"notes.txt\")           - print file content directly")?;
         }
 
         _ => {
-            println!("No detailed help for '{}'. Use help to see available commands.", name);
+            writeln!(out, "No detailed help for '{}'. Use help to see available commands.", name)?;
         }
     }
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,122 @@
+use rand::RngCore;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::{Builder, Uuid, Variant};
+
+use super::key_forge::ParsedValue;
+
+// Largest value of the 12-bit `rand_a` field that uuid_v7 uses as a sequence counter
+const V7_COUNTER_MAX: u16 = 0x0FFF;
+
+/// Unix time in milliseconds (2024-01-01T00:00:00Z) that `uuid_v7` starts from in a seeded
+/// session, so seeded UUIDs do not depend on when the script runs.
+pub const SEEDED_V7_MILLIS: u64 = 1_704_067_200_000;
+
+/// Last timestamp and counter handed out by `uuid_v7`, so UUIDs created in the same
+/// millisecond stay ordered.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UuidV7Clock {
+    last_millis: u64,
+    counter: u16,
+}
+
+pub fn uuid_v4<R: RngCore>(rng: &mut R) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    Builder::from_random_bytes(bytes).into_uuid().hyphenated().to_string()
+}
+
+/// Creates a time-ordered version 7 UUID. Within one millisecond the 12-bit `rand_a` field is
+/// used as a counter (RFC 9562, method 1), so UUIDs generated in a loop sort in creation order.
+/// `now` is the Unix time in milliseconds, normally `now_millis()`.
+pub fn uuid_v7<R: RngCore>(clock: &mut UuidV7Clock, rng: &mut R, now: u64) -> String {
+    let mut random = [0u8; 10];
+    rng.fill_bytes(&mut random);
+
+    let (mut millis, mut counter) = (clock.last_millis, clock.counter);
+
+    if now > millis {
+        millis = now;
+        // Start in the lower half so there is room left to count up
+        counter = u16::from_be_bytes([random[0], random[1]]) & (V7_COUNTER_MAX >> 1);
+    } else if counter < V7_COUNTER_MAX {
+        counter += 1;
+    } else {
+        // Counter exhausted: borrow the next millisecond to keep ordering
+        millis += 1;
+        counter = 0;
+    }
+    clock.last_millis = millis;
+    clock.counter = counter;
+
+    random[..2].copy_from_slice(&counter.to_be_bytes());
+    Builder::from_unix_timestamp_millis(millis, &random)
+        .into_uuid()
+        .hyphenated()
+        .to_string()
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub fn uuid_nil() -> String {
+    Uuid::nil().hyphenated().to_string()
+}
+
+/// Validates `input` and describes it as a dictionary with `uuid`, `version` and `variant`
+/// keys, plus `timestamp_ms` for time-based versions.
+pub fn uuid_parse(input: &str) -> Result<ParsedValue, String> {
+    let uuid = Uuid::parse_str(input.trim())
+        .map_err(|e| format!("Invalid UUID '{}': {}", input.trim(), e))?;
+
+    let variant = match uuid.get_variant() {
+        Variant::NCS => "ncs",
+        Variant::RFC4122 => "rfc4122",
+        Variant::Microsoft => "microsoft",
+        _ => "future",
+    };
+
+    let mut info = HashMap::new();
+    info.insert("uuid".to_string(), ParsedValue::String(uuid.hyphenated().to_string()));
+    info.insert("version".to_string(), ParsedValue::Int(uuid.get_version_num() as i64));
+    info.insert("variant".to_string(), ParsedValue::String(variant.to_string()));
+
+    if let Some(timestamp) = uuid.get_timestamp() {
+        let (seconds, nanos) = timestamp.to_unix();
+        let millis = seconds * 1000 + u64::from(nanos) / 1_000_000;
+        info.insert("timestamp_ms".to_string(), ParsedValue::Int(millis as i64));
+    }
+
+    Ok(ParsedValue::Dictionary(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_v7_is_ordered_and_parsable() {
+        let mut clock = UuidV7Clock::default();
+        let ids: Vec<String> = (0..2000).map(|_| uuid_v7(&mut clock, &mut OsRng, now_millis())).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+        match uuid_parse(&ids[0]).unwrap() {
+            ParsedValue::Dictionary(info) => {
+                assert!(matches!(info.get("version"), Some(ParsedValue::Int(7))));
+                assert!(matches!(info.get("timestamp_ms"), Some(ParsedValue::Int(ms)) if *ms > 0));
+            }
+            other => panic!("unexpected value {:?}", other),
+        }
+
+        assert!(matches!(
+            uuid_parse(&uuid_v4(&mut OsRng)),
+            Ok(ParsedValue::Dictionary(info)) if matches!(info.get("version"), Some(ParsedValue::Int(4)))
+        ));
+        assert!(uuid_parse("not-a-uuid").is_err());
+    }
+}
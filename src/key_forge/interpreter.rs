@@ -0,0 +1,1185 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use colored::Colorize;
+
+use super::arithmetic::Number;
+use super::error::{FrameKind, KeyForgeError};
+use super::ast::{Block, Expr, ExprKind, FunctionDef, LogicalOp, Statement, StatementKind, Word};
+use super::identifiers::UuidV7Clock;
+use super::key_forge::{
+    is_variable_reference, parse_value, resolve_to_string, substitute_variables_in_string, ParsedValue, ScopeKind,
+    Variables,
+};
+use super::parser;
+use super::rng::SessionRng;
+
+/// Environment variable that supplies the state passphrase when there is nobody to prompt.
+pub const PASSPHRASE_ENV: &str = "KEY_FORGE_PASSPHRASE";
+
+/// Deepest allowed nesting of user function calls, so runaway recursion fails with an error
+/// instead of overflowing the stack.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Pending `break`/`continue` request raised inside a loop body.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LoopControl {
+    pub break_requested: bool,
+    pub continue_requested: bool,
+}
+
+/// Where the statements being executed were written, so errors can point at them.
+#[derive(Debug, Clone, Default)]
+pub(crate) enum SourceOrigin {
+    /// REPL input or command line arguments
+    #[default]
+    Input,
+    File(Rc<str>),
+    /// Text that is not part of any script, such as the command inside `$(...)`. Errors get
+    /// the location of the statement that ran it instead.
+    Substitution,
+}
+
+/// A function defined with `fn`, and where its body was written.
+#[derive(Debug, Clone)]
+pub(crate) struct UserFunction {
+    pub(crate) def: Rc<FunctionDef>,
+    origin: SourceOrigin,
+}
+
+/// One KeyForge session: variables, loop-control state and the writer that command output goes to.
+/// Independent interpreters share nothing, so several sessions can live in one process.
+pub struct Interpreter {
+    pub(crate) variables: Variables,
+    pub(crate) loop_control: LoopControl,
+    pub(crate) uuid_clock: UuidV7Clock,
+    pub(crate) rng: SessionRng,
+    pub(crate) output: Box<dyn Write>,
+    pub(crate) functions: HashMap<String, UserFunction>,
+    origin: SourceOrigin,
+    /// Set by the REPL; passphrases are then prompted for instead of read from the environment
+    interactive: bool,
+    call_depth: usize,
+    /// Number of `while`/`for` loops currently running, so `break` and `continue` outside of
+    /// one fail instead of silently ending the script
+    loop_depth: usize,
+    /// Value of a `return` that is unwinding to the function call
+    return_value: Option<String>,
+    /// Script arguments not consumed by `getopt` yet, as typed on the command line
+    pub(crate) script_args: Vec<String>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates a session that prints command output to stdout.
+    pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+
+    /// Creates a session that writes command output to `output` instead of stdout.
+    pub fn with_output<W: Write + 'static>(output: W) -> Self {
+        Self {
+            variables: Variables::new(),
+            loop_control: LoopControl::default(),
+            uuid_clock: UuidV7Clock::default(),
+            rng: SessionRng::Os,
+            output: Box::new(output),
+            functions: HashMap::new(),
+            origin: SourceOrigin::Input,
+            interactive: false,
+            call_depth: 0,
+            loop_depth: 0,
+            return_value: None,
+            script_args: Vec::new(),
+        }
+    }
+
+    /// Marks the session as attached to a terminal, so commands may prompt the user.
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    /// Seeds the random commands of this session (`seed` at the prompt), or returns them to
+    /// OS entropy with `None`.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.rng = seed.map_or(SessionRng::Os, SessionRng::seeded);
+        // uuid_v7 starts counting again from the time of the new random source
+        self.uuid_clock = UuidV7Clock::default();
+    }
+
+    /// Makes the command line of a script available to it: `$0` is the script, `$1..$N` and the
+    /// `$argv` array hold the arguments after it, and `$argc` counts them. They are not globals,
+    /// so `vl`, `save_state`, `load_state` and `:reset` leave them out.
+    pub fn set_script_arguments(&mut self, script: &str, args: &[String]) {
+        self.variables.set_script_name(script);
+        self.set_positional_arguments(args.to_vec());
+    }
+
+    /// Replaces `$1..$N`, `$argc` and `$argv` with `args`.
+    pub(crate) fn set_positional_arguments(&mut self, args: Vec<String>) {
+        self.variables.set_arguments(&args);
+        self.script_args = args;
+    }
+
+    pub fn variables(&self) -> &Variables {
+        &self.variables
+    }
+
+    pub fn variables_mut(&mut self) -> &mut Variables {
+        &mut self.variables
+    }
+
+    /// Parses `line` and executes the statements in it.
+    pub fn run_line(&mut self, line: &str) -> Result<String, KeyForgeError> {
+        self.execute_source(line, false)
+    }
+
+    /// Parses `source` and executes every statement in it. Output of the statements is joined
+    /// with new lines when `capture_output` is set.
+    pub fn execute_source(&mut self, source: &str, capture_output: bool) -> Result<String, KeyForgeError> {
+        let script = parser::parse(source).map_err(|e| match &self.origin {
+            SourceOrigin::Input => KeyForgeError::from(e),
+            SourceOrigin::File(file) => {
+                let span = e.span;
+                KeyForgeError::from(e).without_location().locate(Some(file), span)
+            }
+            SourceOrigin::Substitution => KeyForgeError::from(e).without_location(),
+        })?;
+        self.execute_statements(&script.statements, capture_output)
+    }
+
+    /// Runs text that is not part of the script being executed: the command inside `$(...)`,
+    /// or a statement re-assembled from words.
+    pub(crate) fn execute_substitution(&mut self, source: &str, capture_output: bool) -> Result<String, KeyForgeError> {
+        self.with_origin(SourceOrigin::Substitution, |session| session.execute_source(source, capture_output))
+    }
+
+    /// Reads the script at `path` and executes it. Errors carry the file name and position.
+    pub fn run_file(&mut self, path: &str) -> Result<String, KeyForgeError> {
+        self.execute_file(path, false)
+    }
+
+    /// Like `run_file`, but returns the output of the script instead of printing it when
+    /// `capture_output` is set, as `$(execute_file <path>)` does.
+    pub fn execute_file(&mut self, path: &str, capture_output: bool) -> Result<String, KeyForgeError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| KeyForgeError::io(format!("Failed to read file '{}': {}", path, e)))?;
+        self.with_origin(SourceOrigin::File(Rc::from(path)), |session| {
+            session.execute_source(&source, capture_output)
+        })
+    }
+
+    /// Runs `run` with `args` as the script arguments (`$0` is `script`), or with the current
+    /// ones when `args` is `None`. The caller's arguments are back in place afterwards, so an
+    /// included script may `getopt` without consuming them.
+    pub(crate) fn with_arguments<T>(
+        &mut self,
+        script: &str,
+        args: Option<Vec<String>>,
+        run: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let saved = (self.variables.arguments().clone(), self.script_args.clone());
+        if let Some(args) = args {
+            self.variables.set_script_name(script);
+            self.set_positional_arguments(args);
+        }
+        let result = run(self);
+        self.variables.restore_arguments(saved.0);
+        self.script_args = saved.1;
+        result
+    }
+
+    fn with_origin<T>(&mut self, origin: SourceOrigin, run: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = std::mem::replace(&mut self.origin, origin);
+        let result = run(self);
+        self.origin = previous;
+        result
+    }
+
+    /// Executes `statements` in order, stopping early once `break` or `continue` is requested
+    /// so the enclosing loop can handle it.
+    pub fn execute_statements(&mut self, statements: &[Statement], capture_output: bool) -> Result<String, KeyForgeError> {
+        let mut results = Vec::new();
+
+        for statement in statements {
+            let output = self.execute_statement(statement, capture_output)?;
+            if !output.is_empty() {
+                results.push(output);
+            }
+            if self.should_break() || self.should_continue() || self.return_value.is_some() {
+                break;
+            }
+        }
+
+        Ok(results.join("\n"))
+    }
+
+    pub fn execute_statement(&mut self, statement: &Statement, capture_output: bool) -> Result<String, KeyForgeError> {
+        self.run_statement(statement, capture_output).map_err(|e| match &self.origin {
+            SourceOrigin::Input => e.locate(None, statement.span),
+            SourceOrigin::File(file) => e.locate(Some(file), statement.span),
+            SourceOrigin::Substitution => e,
+        })
+    }
+
+    fn run_statement(&mut self, statement: &Statement, capture_output: bool) -> Result<String, KeyForgeError> {
+        match &statement.kind {
+            StatementKind::Command(words) => {
+                let args = self.command_args(words);
+                self.execute_command(&args, capture_output)
+            }
+            StatementKind::Block(block) => self.execute_block(block, capture_output),
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.evaluate_condition(condition)? {
+                    self.execute_block(then_branch, capture_output)
+                } else if let Some(else_branch) = else_branch {
+                    self.execute_block(else_branch, capture_output)
+                } else {
+                    Ok(String::new())
+                }
+            }
+            StatementKind::While { condition, body } => self.execute_while(condition, body, capture_output),
+            StatementKind::For {
+                variables,
+                iterable,
+                step,
+                body,
+            } => self.execute_for(variables, iterable, step.as_ref(), body, capture_output),
+            StatementKind::Function(function) => {
+                // Positions in a REPL line or argument are meaningless once the next one is read,
+                // so only functions from files keep their own; the others report the call site
+                let origin = match &self.origin {
+                    SourceOrigin::File(file) => SourceOrigin::File(Rc::clone(file)),
+                    _ => SourceOrigin::Substitution,
+                };
+                let function = UserFunction {
+                    def: Rc::clone(function),
+                    origin,
+                };
+                self.functions.insert(function.def.name.clone(), function);
+                Ok(String::new())
+            }
+            StatementKind::Return(words) => {
+                if self.call_depth == 0 {
+                    return Err(KeyForgeError::runtime("'return' can only be used inside a function"));
+                }
+                // `return $a + $b` computes the sum, the same way `set` does
+                let arithmetic = if words.iter().all(|word| !word.quoted) {
+                    let text: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+                    parser::parse_arithmetic(&text.join(" "))
+                } else {
+                    None
+                };
+                let value = match arithmetic {
+                    Some(expr) => self.evaluate_arithmetic(&expr)?.to_string(),
+                    None => {
+                        let mut parts = Vec::with_capacity(words.len());
+                        for word in words {
+                            parts.push(if word.quoted {
+                                substitute_variables_in_string(&self.variables, &word.text)
+                            } else {
+                                self.resolve_input(&word.text)?
+                            });
+                        }
+                        parts.join(" ")
+                    }
+                };
+                self.return_value = Some(value);
+                Ok(String::new())
+            }
+        }
+    }
+
+    /// Runs the statements of `block`, inside a new block scope when it was written with braces.
+    fn execute_block(&mut self, block: &Block, capture_output: bool) -> Result<String, KeyForgeError> {
+        if !block.scoped {
+            return self.execute_statements(&block.statements, capture_output);
+        }
+
+        self.variables.push_scope(ScopeKind::Block);
+        let result = self.execute_statements(&block.statements, capture_output);
+        self.variables.pop_scope();
+        result
+    }
+
+    /// Calls a user-defined function. Arguments are resolved in the caller's scope and bound to
+    /// the parameters in a fresh function scope. The result is the value given to `return`
+    /// (empty without one); the body's own output is printed, not captured.
+    pub(crate) fn call_function(&mut self, function: &UserFunction, args: &[String]) -> Result<String, KeyForgeError> {
+        let UserFunction { def: function, origin } = function;
+        if args.len() != function.params.len() {
+            return Err(KeyForgeError::arity(format!(
+                "Function '{}' expects {} argument(s), got {}",
+                function.name,
+                function.params.len(),
+                args.len()
+            )));
+        }
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(KeyForgeError::runtime(format!(
+                "Maximum call depth of {} exceeded in '{}'",
+                MAX_CALL_DEPTH, function.name
+            )));
+        }
+
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            let variable = arg.strip_prefix('$').filter(|name| is_variable_reference(name));
+            let value = match variable.and_then(|name| self.variables.get_value(name)) {
+                Some(value) => value,
+                None => parse_value(&self.resolve_input(arg)?),
+            };
+            values.push(value);
+        }
+
+        self.variables.push_scope(ScopeKind::Function);
+        for (param, value) in function.params.iter().zip(values) {
+            self.variables.declare_local(param.clone(), value);
+        }
+        // `break`/`continue` inside the function must not reach a loop in the caller
+        let caller_loop_control = std::mem::take(&mut self.loop_control);
+        let caller_loop_depth = std::mem::take(&mut self.loop_depth);
+        self.call_depth += 1;
+
+        let result = self.with_origin(origin.clone(), |session| {
+            session.execute_statements(&function.body.statements, false)
+        });
+
+        self.call_depth -= 1;
+        self.loop_depth = caller_loop_depth;
+        self.loop_control = caller_loop_control;
+        self.variables.pop_scope();
+        let returned = self.return_value.take();
+
+        result.map_err(|e| e.push_frame(FrameKind::Function(function.name.clone())))?;
+        Ok(returned.unwrap_or_default())
+    }
+
+    /// Word texts passed to `execute_command`. `set` and `global` are the exception: their quoted
+    /// values keep their quotes (after `$name` substitution) so `set s "1 + 2"` stores text
+    /// instead of evaluating it.
+    fn command_args(&self, words: &[Word]) -> Vec<String> {
+        let is_set = words
+            .first()
+            .is_some_and(|word| word.is_keyword("set") || word.is_keyword("global"));
+        // `set name value` vs `set collection key value`: the key is passed as is
+        let first_value = if words.len() == 3 { 2 } else { 3 };
+
+        words
+            .iter()
+            .enumerate()
+            .map(|(index, word)| {
+                if is_set && word.quoted && index >= first_value {
+                    let text = substitute_variables_in_string(&self.variables, &word.text);
+                    let quote = if text.contains('"') { '\'' } else { '"' };
+                    format!("{}{}{}", quote, text, quote)
+                } else {
+                    word.text.clone()
+                }
+            })
+            .collect()
+    }
+
+    fn execute_while(&mut self, condition: &Expr, body: &Block, capture_output: bool) -> Result<String, KeyForgeError> {
+        self.loop_depth += 1;
+        let result = self.run_while_iterations(condition, body, capture_output);
+        self.loop_depth -= 1;
+        result
+    }
+
+    fn run_while_iterations(&mut self, condition: &Expr, body: &Block, capture_output: bool) -> Result<String, KeyForgeError> {
+        self.reset_loop_flags();
+        let mut results = Vec::new();
+
+        while self.evaluate_condition(condition)? {
+            let output = self.execute_block(body, capture_output)?;
+            if !output.is_empty() {
+                results.push(output);
+            }
+            if self.should_break() || self.return_value.is_some() {
+                break;
+            }
+            self.set_continue_flag(false);
+        }
+
+        self.reset_loop_flags();
+        Ok(results.join("\n"))
+    }
+
+    fn execute_for(
+        &mut self,
+        variables: &[String],
+        iterable: &Word,
+        step: Option<&Word>,
+        body: &Block,
+        capture_output: bool,
+    ) -> Result<String, KeyForgeError> {
+        let items = match iterable.text.split_once("..") {
+            Some((start, end)) if !iterable.quoted => {
+                if variables.len() != 1 {
+                    return Err(KeyForgeError::runtime("A range loop takes a single variable"));
+                }
+                let start = self.range_bound(start, "start")?;
+                let end = self.range_bound(end, "end")?;
+                let step = match step {
+                    Some(word) => match self.range_bound(&word.text, "step")? {
+                        step if step > 0 => step as usize,
+                        _ => return Err(KeyForgeError::runtime("Step must be a positive integer")),
+                    },
+                    None => 1,
+                };
+                range_items(start, end, step)
+            }
+            _ => {
+                if step.is_some() {
+                    return Err(KeyForgeError::runtime("'step' can only be used with a start..end range"));
+                }
+                let value = self.resolve_iterable(iterable)?;
+                collection_items(value, variables.len() == 2)?
+            }
+        };
+
+        // The loop variables live in their own scope, so they disappear after the loop and
+        // never overwrite variables of the same name outside of it
+        self.variables.push_scope(ScopeKind::Loop);
+        self.loop_depth += 1;
+        let result = self.run_for_iterations(variables, items, body, capture_output);
+        self.loop_depth -= 1;
+        self.variables.pop_scope();
+        result
+    }
+
+    fn run_for_iterations(
+        &mut self,
+        variables: &[String],
+        items: ForItems,
+        body: &Block,
+        capture_output: bool,
+    ) -> Result<String, KeyForgeError> {
+        self.reset_loop_flags();
+        let mut results = Vec::new();
+
+        for values in items {
+            for (name, value) in variables.iter().zip(values) {
+                self.variables.declare_local(name.clone(), value);
+            }
+
+            let output = self.execute_block(body, capture_output)?;
+            if !output.is_empty() {
+                results.push(output);
+            }
+            if self.should_break() || self.return_value.is_some() {
+                break;
+            }
+            self.set_continue_flag(false);
+        }
+
+        self.reset_loop_flags();
+        Ok(results.join("\n"))
+    }
+
+    /// Resolves one side of a `start..end` range or a step: a number, a variable or `$(...)`.
+    fn range_bound(&mut self, text: &str, what: &str) -> Result<i64, KeyForgeError> {
+        let resolved = if text.starts_with("$(") && text.ends_with(')') {
+            self.execute_substitution(&text[2..text.len() - 1], true)?
+        } else {
+            resolve_to_string(&self.variables, text)?
+        };
+        resolved
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| KeyForgeError::type_error(format!("Range {} must be an integer, got '{}'", what, resolved)))
+    }
+
+    /// Value a `for` loop iterates over: a variable, the output of `$(...)`, quoted text or a
+    /// literal such as `[1,2,3]`.
+    fn resolve_iterable(&mut self, word: &Word) -> Result<ParsedValue, KeyForgeError> {
+        if word.quoted {
+            return Ok(ParsedValue::String(substitute_variables_in_string(&self.variables, &word.text)));
+        }
+        if word.text.starts_with("$(") && word.text.ends_with(')') {
+            let output = self.execute_substitution(&word.text[2..word.text.len() - 1], true)?;
+            return Ok(parse_value(&output));
+        }
+        match word.text.strip_prefix('$') {
+            Some(name) => self
+                .variables
+                .get_value(name)
+                .ok_or_else(|| KeyForgeError::undefined(name)),
+            None => Ok(parse_value(&word.text)),
+        }
+    }
+
+    pub fn evaluate_condition(&mut self, condition: &Expr) -> Result<bool, KeyForgeError> {
+        match &condition.kind {
+            ExprKind::Compare { op, left, right } => {
+                let left = self.evaluate_operand(left)?;
+                let right = self.evaluate_operand(right)?;
+                Ok(op.apply(&left, &right))
+            }
+            ExprKind::Logical { op, left, right } => {
+                let left = self.evaluate_condition(left)?;
+                // Both sides are always evaluated, so `$(...)` operands run every time
+                let right = self.evaluate_condition(right)?;
+                Ok(match op {
+                    LogicalOp::And => left && right,
+                    LogicalOp::Or => left || right,
+                })
+            }
+            ExprKind::Words(words) => Ok(self.resolve_condition_value(words)?.is_truthy()),
+            _ => Err(KeyForgeError::runtime("Condition must compare two values")),
+        }
+    }
+
+    fn evaluate_operand(&mut self, operand: &Expr) -> Result<String, KeyForgeError> {
+        match &operand.kind {
+            ExprKind::Words(words) => {
+                let mut parts = Vec::with_capacity(words.len());
+                for word in words {
+                    parts.push(self.resolve_word(word)?);
+                }
+                Ok(parts.join(" "))
+            }
+            _ => Ok(self.evaluate_arithmetic(operand)?.to_string()),
+        }
+    }
+
+    /// Computes an arithmetic expression. Variables and command output must hold numbers.
+    pub fn evaluate_arithmetic(&mut self, expr: &Expr) -> Result<Number, KeyForgeError> {
+        match &expr.kind {
+            ExprKind::Int(v) => Ok(Number::Int(*v)),
+            ExprKind::Float(v) => Ok(Number::Float(*v)),
+            ExprKind::Variable(name) => match self.variables.get_value(name) {
+                Some(ParsedValue::Int(v)) => Ok(Number::Int(v)),
+                Some(ParsedValue::Float(v)) => Ok(Number::Float(v)),
+                Some(ParsedValue::String(text)) => {
+                    Number::parse(&text).ok_or_else(|| {
+                        KeyForgeError::type_error(format!("Variable '{}' is not a number: '{}'", name, text))
+                    })
+                }
+                Some(_) => Err(KeyForgeError::type_error(format!("Variable '{}' is not a number", name))),
+                None => Err(KeyForgeError::undefined(name.as_str())),
+            },
+            ExprKind::Substitution(command) => {
+                let output = self.execute_substitution(command, true)?;
+                Number::parse(&output).ok_or_else(|| {
+                    KeyForgeError::type_error(format!("Output of $({}) is not a number: '{}'", command, output))
+                })
+            }
+            ExprKind::Negate(operand) => self.evaluate_arithmetic(operand)?.negate(),
+            ExprKind::Arithmetic { op, left, right } => {
+                let left = self.evaluate_arithmetic(left)?;
+                let right = self.evaluate_arithmetic(right)?;
+                Number::apply(*op, left, right)
+            }
+            _ => Err(KeyForgeError::runtime("Expected an arithmetic expression")),
+        }
+    }
+
+    /// Value of a condition without a comparison. Variables keep their type, so a boolean
+    /// `false` fails while the string `"false"` passes.
+    fn resolve_condition_value(&mut self, words: &[Word]) -> Result<ParsedValue, KeyForgeError> {
+        if let [word] = words {
+            if let Some(name) = word.text.strip_prefix('$').filter(|name| !word.quoted && is_variable_reference(name)) {
+                return self.variables.get_value(name).ok_or_else(|| KeyForgeError::undefined(name));
+            }
+            if word.quoted {
+                return Ok(ParsedValue::String(self.resolve_word(word)?));
+            }
+        }
+        let mut parts = Vec::with_capacity(words.len());
+        for word in words {
+            parts.push(self.resolve_word(word)?);
+        }
+        Ok(parse_value(&parts.join(" ")))
+    }
+
+    /// Resolves a condition operand: quoted text gets `$name` substitution, `$(...)` runs the
+    /// command, and anything else is looked up as a variable before being taken literally.
+    fn resolve_word(&mut self, word: &Word) -> Result<String, KeyForgeError> {
+        if word.quoted {
+            Ok(substitute_variables_in_string(&self.variables, &word.text))
+        } else if word.text.starts_with("$(") && word.text.ends_with(')') {
+            self.execute_substitution(&word.text[2..word.text.len() - 1], true)
+        } else {
+            resolve_to_string(&self.variables, &word.text)
+        }
+    }
+
+    pub(crate) fn write_line<T: Display + ?Sized>(&mut self, text: &T) -> Result<(), KeyForgeError> {
+        writeln!(self.output, "{}", text).map_err(|e| KeyForgeError::io(format!("Failed to write output: {}", e)))
+    }
+
+    /// Random source of `gen_password`, `gen_bytes` and `gen_token`. In a seeded session the
+    /// first of them warns on stderr that the output is predictable and not fit for secrets.
+    pub(crate) fn secret_rng(&mut self) -> &mut SessionRng {
+        if let Some(seed) = self.rng.take_secret_warning() {
+            let warning = format!(
+                "Warning: random commands are seeded with {}; generated passwords, bytes and tokens are \
+                 predictable and must not be used as secrets (seed reset restores OS entropy)",
+                seed
+            );
+            eprintln!("{}", warning.yellow());
+        }
+        &mut self.rng
+    }
+
+    /// Passphrase for an encrypted state file: prompted for without echo in the REPL (twice
+    /// when `confirm` is set), taken from `KEY_FORGE_PASSPHRASE` in file and argument mode.
+    pub(crate) fn read_passphrase(&self, confirm: bool) -> Result<String, KeyForgeError> {
+        let passphrase = if self.interactive {
+            let passphrase = rpassword::prompt_password("Passphrase: ")?;
+            if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+                return Err(KeyForgeError::runtime("Passphrases do not match"));
+            }
+            passphrase
+        } else {
+            std::env::var(PASSPHRASE_ENV)
+                .map_err(|_| KeyForgeError::runtime(format!("Set {} to the state passphrase", PASSPHRASE_ENV)))?
+        };
+
+        if passphrase.is_empty() {
+            return Err(KeyForgeError::runtime("The passphrase must not be empty"));
+        }
+        Ok(passphrase)
+    }
+
+    pub(crate) fn in_loop(&self) -> bool {
+        self.loop_depth > 0
+    }
+
+    pub(crate) fn set_break_flag(&mut self, value: bool) {
+        self.loop_control.break_requested = value;
+    }
+
+    pub(crate) fn set_continue_flag(&mut self, value: bool) {
+        self.loop_control.continue_requested = value;
+    }
+
+    pub(crate) fn should_break(&self) -> bool {
+        self.loop_control.break_requested
+    }
+
+    pub(crate) fn should_continue(&self) -> bool {
+        self.loop_control.continue_requested
+    }
+
+    pub(crate) fn reset_loop_flags(&mut self) {
+        self.loop_control = LoopControl::default();
+    }
+}
+
+/// Values bound to the loop variables on each iteration of a `for` loop.
+type ForItems = Box<dyn Iterator<Item = Vec<ParsedValue>>>;
+
+/// `start..end` counting up, or down when `start > end`. The end is excluded either way.
+fn range_items(start: i64, end: i64, step: usize) -> ForItems {
+    let values: Box<dyn Iterator<Item = i64>> = if start <= end {
+        Box::new((start..end).step_by(step))
+    } else {
+        Box::new((end + 1..=start).rev().step_by(step))
+    };
+    Box::new(values.map(|i| vec![ParsedValue::Int(i)]))
+}
+
+/// Elements of an array, keys of a dictionary (in sorted order), characters of a string or
+/// bytes (as integers).
+/// With `pairs` set each item also carries the index or, for dictionaries, the value.
+fn collection_items(value: ParsedValue, pairs: bool) -> Result<ForItems, KeyForgeError> {
+    match value {
+        ParsedValue::Array(elements) => Ok(Box::new(elements.into_iter().enumerate().map(move |(i, element)| {
+            if pairs {
+                vec![ParsedValue::Int(i as i64), element]
+            } else {
+                vec![element]
+            }
+        }))),
+        ParsedValue::Dictionary(dict) => {
+            let mut entries: Vec<(String, ParsedValue)> = dict.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(Box::new(entries.into_iter().map(move |(key, value)| {
+                if pairs {
+                    vec![ParsedValue::String(key), value]
+                } else {
+                    vec![ParsedValue::String(key)]
+                }
+            })))
+        }
+        ParsedValue::String(text) => {
+            let chars: Vec<char> = text.chars().collect();
+            Ok(Box::new(chars.into_iter().enumerate().map(move |(i, ch)| {
+                if pairs {
+                    vec![ParsedValue::Int(i as i64), ParsedValue::String(ch.to_string())]
+                } else {
+                    vec![ParsedValue::String(ch.to_string())]
+                }
+            })))
+        }
+        ParsedValue::Bytes(bytes) => Ok(Box::new(bytes.into_iter().enumerate().map(move |(i, byte)| {
+            if pairs {
+                vec![ParsedValue::Int(i as i64), ParsedValue::Int(byte.into())]
+            } else {
+                vec![ParsedValue::Int(byte.into())]
+            }
+        }))),
+        ParsedValue::Int(_) | ParsedValue::Float(_) => {
+            Err(KeyForgeError::type_error("Cannot iterate over a number, use a range such as 0..$n"))
+        }
+        other => Err(KeyForgeError::type_error(format!("Cannot iterate over a value of type {}", other.type_name()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::key_forge::identifiers::SEEDED_V7_MILLIS;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sessions_are_independent() {
+        let first_output = SharedBuffer::default();
+        let mut first = Interpreter::with_output(first_output.clone());
+        let mut second = Interpreter::with_output(SharedBuffer::default());
+
+        first.run_line("set x 10").unwrap();
+        first.run_line("add x 5").unwrap();
+        first.run_line("print \"x is $x\"").unwrap();
+
+        assert_eq!(first.variables().get_int_data("x"), Ok(15));
+        assert!(second.variables().get_int_data("x").is_err());
+        assert!(second.run_line("add x 1").is_err());
+        assert_eq!(String::from_utf8(first_output.0.borrow().clone()).unwrap(), "x is 15\n");
+    }
+
+    #[test]
+    fn test_seeded_sessions_reproduce_every_random_command() {
+        let script = "get_random_num 1 1000; get_random_char 1; gen_password 16; gen_bytes 8; \
+                      gen_token kf_ 12 --checksum crc32; uuid_v4; uuid_v7; uuid_v7";
+        let run = |seed: u64| {
+            let output = SharedBuffer::default();
+            let mut session = Interpreter::with_output(output.clone());
+            session.set_seed(Some(seed));
+            session.run_line(script).unwrap();
+            let bytes = output.0.borrow().clone();
+            String::from_utf8(bytes).unwrap()
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+
+        let mut session = Interpreter::with_output(SharedBuffer::default());
+        session.run_line("seed 7; set a $(gen_bytes 16); seed 7; set b $(gen_bytes 16)").unwrap();
+        assert_eq!(session.variables().get_value("a"), session.variables().get_value("b"));
+        session.run_line("set info $(uuid_parse $(uuid_v7)); set ms $(get info timestamp_ms)").unwrap();
+        assert_eq!(session.variables().get_int_data("ms"), Ok(SEEDED_V7_MILLIS as i64));
+        session.run_line("seed reset").unwrap();
+        assert_eq!(session.rng.seed(), None);
+    }
+
+    #[test]
+    fn test_script_arguments_and_getopt() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        let args: Vec<String> = ["in.txt", "-v", "--length", "24", "out.txt"].iter().map(|a| a.to_string()).collect();
+        session.set_script_arguments("gen.kf", &args);
+
+        session.run_line("print $0 $argc $2; set n $(len argv); fn first(a) { return $a }; print $(first $1)").unwrap();
+        assert_eq!(session.variables().get_int_data("n"), Ok(5));
+
+        session.run_line("getopt opts verbose|v length|l: format:").unwrap();
+        let opts = session.variables().get_dict_data("opts").unwrap();
+        assert_eq!(opts["verbose"], ParsedValue::Bool(true));
+        assert_eq!(opts["length"], ParsedValue::Int(24));
+        assert_eq!(opts["format"], ParsedValue::Null);
+        // Only the non-option arguments are left
+        assert_eq!(session.variables().get_int_data("argc"), Ok(2));
+        assert_eq!(session.variables().get_string_data("2"), Ok("out.txt".to_string()));
+        assert!(!session.variables().has_variable("3"));
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(printed, "gen.kf 5 -v\nin.txt\n");
+        // Bare numbers stay literals even though $1 and $2 exist
+        session.run_line("set total 0; for i in 1..3 do add total i").unwrap();
+        assert_eq!(session.variables().get_int_data("total"), Ok(3));
+        assert!(session.run_line("getopt opts verbose").is_ok());
+        assert!(session.run_line("getopt opts --bad").is_err());
+    }
+
+    #[test]
+    fn test_script_arguments_are_not_globals() {
+        let path = std::env::temp_dir().join(format!("key_forge_arguments_{}.json", std::process::id()));
+        let file = path.to_str().unwrap().to_string();
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session.run_line("set argv \"mine\"").unwrap();
+        session.set_script_arguments("gen.kf", &["a".to_string(), "b".to_string()]);
+
+        assert_eq!(session.variables().get_string_data("argv"), Ok("mine".to_string()));
+        assert_eq!(session.variables().get_int_data("argc"), Ok(2));
+        session.run_line("vl").unwrap();
+        let listed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert!(listed.contains("mine") && !listed.contains("argc") && !listed.contains("gen.kf"), "{}", listed);
+
+        session.run_line(&format!("save_state {}", file)).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(saved.contains("mine") && !saved.contains("argc") && !saved.contains("gen.kf"), "{}", saved);
+
+        // Resetting the globals keeps the arguments
+        session.variables_mut().clear_globals();
+        assert_eq!(session.variables().get_string_data("1"), Ok("a".to_string()));
+        assert_eq!(session.variables().get_array_data("argv").map(|argv| argv.len()), Ok(2));
+        assert!(session.run_line("set 1 changed").is_err());
+        assert_eq!(session.variables().get_string_data("1"), Ok("a".to_string()));
+    }
+
+    #[test]
+    fn test_functions_have_local_scope_and_recursion_limit() {
+        let mut session = Interpreter::with_output(SharedBuffer::default());
+        session
+            .run_line("set a 100; fn add2(a, b) { set total $a; add total b; return $total }")
+            .unwrap();
+        session.run_line("set sum $(add2 3 4)").unwrap();
+
+        assert_eq!(session.variables().get_int_data("sum"), Ok(7));
+        assert_eq!(session.variables().get_int_data("a"), Ok(100));
+        assert!(!session.variables().has_variable("total"));
+        assert!(session.run_line("add2 1").is_err());
+
+        // Assignments inside a function stay local unless the name is declared global
+        session.run_line("set total 1; set count 0; set sum $(add2 5 6)").unwrap();
+        session.run_line("fn bump() { set count $count + 1; global total; set total 50 }; bump").unwrap();
+        assert_eq!(session.variables().get_int_data("sum"), Ok(11));
+        assert_eq!(session.variables().get_int_data("count"), Ok(0));
+        assert_eq!(session.variables().get_int_data("total"), Ok(50));
+        assert!(session.run_line("return 1").is_err());
+
+        session
+            .run_line("fn area(w, h) { return $w * $h + 1 }; fn label(w) { return \"$w + 1\" }")
+            .unwrap();
+        session.run_line("set area $(area 3 4); set label $(label 2)").unwrap();
+        assert_eq!(session.variables().get_int_data("area"), Ok(13));
+        assert_eq!(session.variables().get_string_data("label").unwrap(), "2 + 1");
+
+        // Unoptimized builds need more than the default 2 MiB test thread stack for 64 calls
+        let err = std::thread::Builder::new()
+            .stack_size(32 * 1024 * 1024)
+            .spawn(|| {
+                let mut session = Interpreter::with_output(io::sink());
+                session.run_line("fn forever(n) { return $(forever $n) }").unwrap();
+                session.run_line("forever 1").unwrap_err()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(err.message().starts_with("Maximum call depth"), "{}", err);
+    }
+
+    #[test]
+    fn test_recursion_limit_collapses_repeated_frames() {
+        let path = std::env::temp_dir().join(format!("key_forge_recursion_{}.kf", std::process::id()));
+        let file = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "fn down(n) {\n    down $n\n}\ndown 1\n").unwrap();
+
+        // Unoptimized builds need more than the default 2 MiB test thread stack for 64 calls
+        let script = file.clone();
+        let err = std::thread::Builder::new()
+            .stack_size(32 * 1024 * 1024)
+            .spawn(move || Interpreter::with_output(io::sink()).run_file(&script).unwrap_err())
+            .unwrap()
+            .join()
+            .unwrap();
+        let rendered = err.render(None);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.context().stack.len(), MAX_CALL_DEPTH);
+        assert_eq!(
+            rendered,
+            format!(
+                "error: Maximum call depth of 64 exceeded in 'down'\n --> {file}:2:5\n  |\n2 |     down $n\n  |     ^^^^^^^\n  \
+                 = in function 'down' called at {file}:2:5\n  = ... 62 more frames of function 'down'\n  \
+                 = in function 'down' called at {file}:4:1",
+            )
+        );
+    }
+
+    #[test]
+    fn test_exit_stops_the_script_without_ending_the_process() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session.run_line("fn stop() { exit 3 }").unwrap();
+
+        let err = session.run_line("print a; for i in 0..5 do { stop }; print b").unwrap_err();
+        assert!(matches!(err, KeyForgeError::Exit { code: 3, .. }));
+        assert_eq!(err.exit_code(), Some(3));
+        assert_eq!(session.run_line("quit").unwrap_err().exit_code(), Some(0));
+        assert_eq!(session.run_line("add missing 1").unwrap_err().exit_code(), None);
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(printed, "a\nProgram exit with code 3\nProgram exit with code 0\n");
+    }
+
+    #[test]
+    fn test_missing_operands_are_arity_errors() {
+        let mut session = Interpreter::with_output(io::sink());
+        session.run_line("set x 1").unwrap();
+
+        for line in ["add", "sub x", "mul", "div x", "write_file out.txt hello"] {
+            let err = session.run_line(line).unwrap_err();
+            assert!(matches!(err, KeyForgeError::Arity { .. }), "{line}: {err:?}");
+        }
+    }
+
+    #[test]
+    fn test_break_and_continue_outside_of_a_loop_are_errors() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+
+        let err = session.run_line("print a; break; print b").unwrap_err();
+        assert_eq!(err.message(), "break outside of a loop");
+        let err = session.run_line("continue").unwrap_err();
+        assert_eq!(err.message(), "continue outside of a loop");
+
+        // A function body cannot reach the loop it was called from
+        session.run_line("fn skip() { continue }").unwrap();
+        assert!(session.run_line("for i in 0..2 { skip }").is_err());
+
+        session
+            .run_line("for i in 0..4 { if $i == 1 then continue; if $i == 3 then break; print $i }; print done")
+            .unwrap();
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(printed, "a\n0\n2\ndone\n");
+    }
+
+    #[test]
+    fn test_errors_carry_variant_location_and_call_stack() {
+        let path = std::env::temp_dir().join(format!("key_forge_error_{}.kf", std::process::id()));
+        let file = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "fn bump(n) {\n    add missing $n\n}\nif 1 == 1 then {\n    bump 2\n}\n").unwrap();
+
+        let mut session = Interpreter::with_output(io::sink());
+        let err = session.run_file(&file).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(&err, KeyForgeError::UndefinedVariable { name, .. } if name == "missing"));
+        let context = err.context();
+        assert_eq!(context.file.as_deref(), Some(file.as_str()));
+        assert_eq!(context.span.map(|span| (span.line, span.column)), Some((2, 5)));
+        assert_eq!(context.stack.len(), 1);
+        assert_eq!(context.stack[0].kind, FrameKind::Function("bump".to_string()));
+        assert_eq!(context.stack[0].span.map(|span| (span.line, span.column)), Some((5, 5)));
+
+        assert!(matches!(session.run_line("bump"), Err(KeyForgeError::Arity { .. })));
+        assert!(matches!(session.run_line("if 1 == then"), Err(KeyForgeError::Parse { .. })));
+        assert!(matches!(session.run_file("/nonexistent/script.kf"), Err(KeyForgeError::Io { .. })));
+    }
+
+    #[test]
+    fn test_execute_file_captures_output_and_propagates_errors() {
+        let path = std::env::temp_dir().join(format!("key_forge_include_{}.kf", std::process::id()));
+        let file = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "print one\nfor i in 0..2 do {\n    print $i\n}\n").unwrap();
+
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session.run_line(&format!("set out $(execute_file {})", file)).unwrap();
+        assert_eq!(session.variables().get_string_data("out"), Ok("one\n0\n1".to_string()));
+        assert!(output.0.borrow().is_empty());
+
+        std::fs::write(&path, "print before\nif 1 == 1 then {\n    add missing 1\n}\nprint after\n").unwrap();
+        let err = session.run_line(&format!("execute_file {}", file)).unwrap_err();
+        let result = crate::key_forge::input_mode::file_mode(&mut session, &file);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(err.context().span.map(|span| (span.line, span.column)), Some((3, 5)));
+        assert_eq!(err.context().stack[0].kind, FrameKind::Include(file.clone()));
+        assert!(matches!(result, Err(KeyForgeError::UndefinedVariable { .. })));
+        assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "before\nbefore\n");
+    }
+
+    #[test]
+    fn test_execute_file_gives_included_scripts_their_own_arguments() {
+        let path = std::env::temp_dir().join(format!("key_forge_include_args_{}.kf", std::process::id()));
+        let file = path.to_str().unwrap().to_string();
+        std::fs::write(&path, "getopt opts verbose|v\nset v $(get opts verbose)\nprint $argc $1 $v\n").unwrap();
+
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session.set_script_arguments("main.kf", &["-v".to_string(), "in.txt".to_string(), "out.txt".to_string()]);
+        session.run_line("set name \"gen.txt\"").unwrap();
+        session.run_line(&format!("execute_file {} $name -v", file)).unwrap();
+        // Without arguments the included script sees ours, and its getopt leaves them alone
+        session.run_line(&format!("execute_file {}", file)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(String::from_utf8(output.0.borrow().clone()).unwrap(), "1 gen.txt true\n2 in.txt true\n");
+        assert_eq!(session.variables().get_string_data("0"), Ok("main.kf".to_string()));
+        assert_eq!(session.variables().get_int_data("argc"), Ok(3));
+        assert_eq!(session.script_args[0], "-v");
+    }
+
+    #[test]
+    fn test_bool_null_bytes_and_large_ints() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session
+            .run_line("set user {active: true, nick: null}; set off false; set quoted \"false\"; set key 0x00ff")
+            .unwrap();
+        session.run_line("set big 3000000000; add big 3000000000").unwrap();
+
+        let user = session.variables().get_dict_data("user").unwrap();
+        assert_eq!(user["active"], ParsedValue::Bool(true));
+        assert_eq!(user["nick"], ParsedValue::Null);
+        assert_eq!(session.variables().get_bool_data("off"), Ok(false));
+        assert_eq!(session.variables().get_string_data("quoted"), Ok("false".to_string()));
+        assert_eq!(session.variables().get_bytes_data("key"), Ok(vec![0x00, 0xff]));
+        assert_eq!(session.variables().get_int_data("big"), Ok(6_000_000_000));
+
+        session
+            .run_line("if $off then print a; if $quoted then print b; if $off == false then print c")
+            .unwrap();
+        session.run_line("if $big > 5999999999 then print \"$key $off\"").unwrap();
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(printed, "b\nc\n0x00ff false\n");
+    }
+
+    #[test]
+    fn test_hashes_and_hmac_keys_use_the_bytes_of_bytes_variables() {
+        let mut session = Interpreter::with_output(SharedBuffer::default());
+        session.run_line("set data $(hex_decode ff00); set digest $(sha256 $data)").unwrap();
+        assert_eq!(
+            session.variables().get_string_data("digest"),
+            Ok("ea5dbf9596d187e9500f23e9a680109475341cf4e81f7e043f7d97152c10772f".to_string())
+        );
+
+        // RFC 4231 test case 1, with the key as a bytes variable and as hex: text
+        let tag = "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7";
+        session.run_line("set key 0x0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b; set msg \"Hi There\"").unwrap();
+        session.run_line("set a $(hmac_sign sha256 $key $msg)").unwrap();
+        session.run_line("set b $(hmac_sign sha256 hex:0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b $msg)").unwrap();
+        assert_eq!(session.variables().get_string_data("a"), Ok(tag.to_string()));
+        assert_eq!(session.variables().get_string_data("b"), Ok(tag.to_string()));
+        session.run_line(&format!("set ok $(hmac_verify sha256 $key $msg {})", tag)).unwrap();
+        assert_eq!(session.variables().get_bool_data("ok"), Ok(true));
+    }
+
+    #[test]
+    fn test_one_value_per_name_with_rm_type_of_and_vl_filters() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session
+            .run_line("set x 5; set x \"five\"; set arr [1, 2]; set d {a: 1}; set api_key 0xff; set api_on true")
+            .unwrap();
+
+        assert_eq!(session.variables().get_value("x"), Some(ParsedValue::String("five".to_string())));
+        assert!(session.variables().get_int_data("x").is_err());
+        assert_eq!(session.execute_source("type_of x", true).unwrap(), "string");
+        assert_eq!(session.execute_source("type_of $arr", true).unwrap(), "array");
+
+        session.run_line("rm arr d").unwrap();
+        assert!(!session.variables().has_variable("arr"));
+        assert!(!session.variables().has_variable("d"));
+        assert!(matches!(session.run_line("rm arr"), Err(KeyForgeError::UndefinedVariable { .. })));
+
+        assert_eq!(
+            session.execute_source("vl api_*", true).unwrap(),
+            "=== Boolean Variables ===\napi_on: true\n\n=== Bytes Variables ===\napi_key: 0xff\n"
+        );
+        assert_eq!(
+            session.execute_source("vl int", true).unwrap(),
+            "=== Integer Variables (i64) ===\n"
+        );
+        assert_eq!(
+            session.execute_source("vl s *x*", true).unwrap(),
+            "=== String Variables ===\nx: \"five\"\n"
+        );
+        assert_eq!(
+            session.execute_source("vl bytes a*_*y", true).unwrap(),
+            "=== Bytes Variables ===\napi_key: 0xff\n"
+        );
+        assert!(session.run_line("vl s x extra").is_err());
+    }
+
+    #[test]
+    fn test_loop_and_block_variables_are_local() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session.run_line("set i 100; set sum 0").unwrap();
+        session
+            .run_line("for i in 0..4 { set square $i * $i; add sum square }")
+            .unwrap();
+
+        assert_eq!(session.variables().get_int_data("i"), Ok(100));
+        assert_eq!(session.variables().get_int_data("sum"), Ok(14));
+        assert!(!session.variables().has_variable("square"));
+
+        session.run_line("{ set hidden 1; global shown 2; vl s }").unwrap();
+        assert!(!session.variables().has_variable("hidden"));
+        assert_eq!(session.variables().get_int_data("shown"), Ok(2));
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert!(printed.starts_with("=== Scope Chain (innermost first) ===\nblock: hidden = 1, global shown\n"));
+
+        assert!(session.run_line("{ set x 1; global x }").is_err());
+    }
+
+    #[test]
+    fn test_for_iterates_collections_and_ranges() {
+        let output = SharedBuffer::default();
+        let mut session = Interpreter::with_output(output.clone());
+        session
+            .run_line("set arr [\"a b\", 2]; set info {y: 2, x: 1}; set word \"hé\"; set n 3")
+            .unwrap();
+
+        let script = "for item in $arr do print $item
+            for i, item in $arr do print \"$i=$item\"
+            for key, value in $info do print \"$key=$value\"
+            for key in $info do print $key
+            for ch in $word do print $ch
+            for i in 0..$n do print $i
+            for i in 10..0 step 4 do print $i
+            for i in 0..$(len arr) step 1 do print $i";
+        session.run_line(script).unwrap();
+
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(
+            lines,
+            ["a b", "2", "0=a b", "1=2", "x=1", "y=2", "x", "y", "h", "é", "0", "1", "2", "10", "6", "2", "0", "1"]
+        );
+
+        assert!(session.run_line("for i in 0..3 step 0 do print $i").is_err());
+        assert!(session.run_line("for i in $arr step 2 do print $i").is_err());
+        assert!(session.run_line("for i in $n do print $i").is_err());
+        assert!(session.run_line("for i in $missing do print $i").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use serde_json::{Map, Number, Value};
+
+use super::encoding::to_hex;
+use super::key_forge::ParsedValue;
+
+/// Parses JSON text into nested arrays and dictionaries. Integers that fit in an i64 stay
+/// integers, every other number becomes a float.
+pub fn parse(text: &str) -> Result<ParsedValue, String> {
+    let value: Value = serde_json::from_str(text.trim()).map_err(|e| format!("Invalid JSON: {}", e))?;
+    Ok(from_json(value))
+}
+
+/// JSON text for `value`, with dictionary keys sorted. Bytes become a string of hex digits and
+/// non-finite floats become `null`, as JSON has no way to write either.
+pub fn stringify(value: &ParsedValue, pretty: bool) -> String {
+    let json = to_json(value);
+    let text = if pretty {
+        serde_json::to_string_pretty(&json)
+    } else {
+        serde_json::to_string(&json)
+    };
+    text.expect("a serde_json::Value always serializes")
+}
+
+pub fn from_json(value: Value) -> ParsedValue {
+    match value {
+        Value::Null => ParsedValue::Null,
+        Value::Bool(b) => ParsedValue::Bool(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => ParsedValue::Int(i),
+            None => ParsedValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => ParsedValue::String(s),
+        Value::Array(items) => ParsedValue::Array(items.into_iter().map(from_json).collect()),
+        Value::Object(map) => ParsedValue::Dictionary(map.into_iter().map(|(k, v)| (k, from_json(v))).collect()),
+    }
+}
+
+pub fn to_json(value: &ParsedValue) -> Value {
+    match value {
+        ParsedValue::Int(i) => Value::from(*i),
+        ParsedValue::Float(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+        ParsedValue::String(s) => Value::String(s.clone()),
+        ParsedValue::Bool(b) => Value::Bool(*b),
+        ParsedValue::Null => Value::Null,
+        ParsedValue::Bytes(bytes) => Value::String(to_hex(bytes)),
+        ParsedValue::Array(items) => Value::Array(items.iter().map(to_json).collect()),
+        ParsedValue::Dictionary(dict) => {
+            Value::Object(dict.iter().map(|(k, v)| (k.clone(), to_json(v))).collect::<Map<_, _>>())
+        }
+    }
+}
+
+/// Follows `path` into nested arrays and dictionaries. A path starting with `/` is a JSON
+/// Pointer (RFC 6901, `~1` for `/` and `~0` for `~`); anything else is split at dots
+/// (`servers.0.host`). An empty path is the value itself.
+pub fn get_path<'a>(value: &'a ParsedValue, path: &str) -> Result<&'a ParsedValue, String> {
+    let segments: Vec<String> = if path.is_empty() {
+        Vec::new()
+    } else if let Some(pointer) = path.strip_prefix('/') {
+        pointer.split('/').map(|s| s.replace("~1", "/").replace("~0", "~")).collect()
+    } else {
+        path.split('.').map(str::to_string).collect()
+    };
+
+    let mut current = value;
+    for (depth, segment) in segments.iter().enumerate() {
+        let walked = || segments[..depth].join(".");
+        current = match current {
+            ParsedValue::Dictionary(dict) => dict
+                .get(segment)
+                .ok_or_else(|| format!("Key '{}' not found at '{}'", segment, walked()))?,
+            ParsedValue::Array(items) => {
+                let index: usize = segment
+                    .parse()
+                    .map_err(|_| format!("Array index must be a non-negative integer, got '{}'", segment))?;
+                items
+                    .get(index)
+                    .ok_or_else(|| format!("Index {} out of bounds at '{}' (length {})", index, walked(), items.len()))?
+            }
+            other => {
+                return Err(format!(
+                    "Cannot look up '{}' in a value of type {}",
+                    segment,
+                    other.type_name()
+                ))
+            }
+        };
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_forge::key_forge::{parse_value, value_to_string};
+
+    #[test]
+    fn test_parse_stringify_and_paths() {
+        let value = parse(r#"{"servers": [{"host": "a/b", "port": 8080}], "ratio": 0.5, "tls": null}"#).unwrap();
+
+        assert_eq!(get_path(&value, "servers.0.port"), Ok(&ParsedValue::Int(8080)));
+        assert_eq!(get_path(&value, "/servers/0/host"), Ok(&ParsedValue::String("a/b".to_string())));
+        assert_eq!(get_path(&value, "tls"), Ok(&ParsedValue::Null));
+        assert!(get_path(&value, "servers.1").unwrap_err().contains("out of bounds"));
+        assert!(get_path(&value, "ratio.x").unwrap_err().contains("type float"));
+
+        assert_eq!(
+            stringify(&value, false),
+            r#"{"ratio":0.5,"servers":[{"host":"a/b","port":8080}],"tls":null}"#
+        );
+        assert_eq!(stringify(&ParsedValue::Bytes(vec![0xde, 0xad]), false), r#""dead""#);
+        assert!(parse("{oops}").is_err());
+    }
+
+    #[test]
+    fn test_parsed_json_survives_the_literal_round_trip() {
+        // Command output reaches variables as text, so the literal form must read back the same
+        let value = parse(r#"{"a:b": [1, "two", {"k, v": false}], "": null, "s": "123"}"#).unwrap();
+        assert_eq!(parse_value(&value_to_string(&value)), value);
+    }
+}
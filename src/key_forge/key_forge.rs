@@ -1,132 +1,370 @@
 // [file name]: key_forge.rs
 // [file content begin]
-use lazy_static::lazy_static;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use super::encoding::{from_hex, to_hex};
+use super::error::KeyForgeError;
+use super::interpreter::Interpreter;
+use super::parser;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ParsedValue {
-    Int(i32),
+    Int(i64),
     Float(f64),
     String(String),
+    /// Written `true`/`false`; quoted `"true"` stays a string
+    Bool(bool),
+    Null,
+    /// Binary data such as key material, written as `0x` followed by an even number of hex digits
+    Bytes(Vec<u8>),
     Array(Vec<ParsedValue>),
     Dictionary(HashMap<String, ParsedValue>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl ParsedValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ParsedValue::Int(_) => "int",
+            ParsedValue::Float(_) => "float",
+            ParsedValue::String(_) => "string",
+            ParsedValue::Bool(_) => "bool",
+            ParsedValue::Null => "null",
+            ParsedValue::Bytes(_) => "bytes",
+            ParsedValue::Array(_) => "array",
+            ParsedValue::Dictionary(_) => "dict",
+        }
+    }
+
+    /// Whether a condition holding only this value passes: `false`, `null`, zero, and empty
+    /// strings, bytes and collections fail, everything else passes.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            ParsedValue::Int(v) => *v != 0,
+            ParsedValue::Float(v) => *v != 0.0,
+            ParsedValue::String(s) => !s.is_empty(),
+            ParsedValue::Bool(b) => *b,
+            ParsedValue::Null => false,
+            ParsedValue::Bytes(bytes) => !bytes.is_empty(),
+            ParsedValue::Array(arr) => !arr.is_empty(),
+            ParsedValue::Dictionary(dict) => !dict.is_empty(),
+        }
+    }
+}
+
+/// Every variable of a session, whatever its type. A name holds one value at a time, so
+/// `set x 1` followed by `set x "one"` replaces the integer with the string.
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Variables {
-    pub int_variables: HashMap<String, i32>,
-    pub float_variables: HashMap<String, f64>,
-    pub string_variables: HashMap<String, String>,
-    pub array_variables: HashMap<String, Vec<ParsedValue>>,
-    pub dict_variables: HashMap<String, HashMap<String, ParsedValue>>,
+    /// Global variables
+    values: HashMap<String, ParsedValue>,
+    /// Local scopes stacked on top of the globals, innermost last. They only exist while a
+    /// function, loop or block runs and are never saved.
+    #[serde(skip)]
+    scopes: Vec<Scope>,
+    /// Script arguments: `0`, `1..N`, `argc` and `argv`. They are read-only and kept apart from
+    /// the globals, so listing, saving, loading or resetting variables never touches them.
+    #[serde(skip)]
+    arguments: HashMap<String, ParsedValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    /// Body of a user-defined function. Lookups stop here and continue with the globals, so a
+    /// function never sees its caller's locals.
+    Function,
+    /// A `for` loop; holds the loop variable.
+    Loop,
+    /// A `{ ... }` block, including loop and `if` bodies.
+    Block,
+}
+
+impl ScopeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ScopeKind::Function => "function",
+            ScopeKind::Loop => "loop",
+            ScopeKind::Block => "block",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    pub values: HashMap<String, ParsedValue>,
+    /// Names declared with `global` in this scope; they resolve to the global maps.
+    pub globals: HashSet<String>,
 }
 
 impl Variables {
     pub fn new() -> Self {
-        Self {
-            int_variables: HashMap::new(),
-            float_variables: HashMap::new(),
-            string_variables: HashMap::new(),
-            array_variables: HashMap::new(),
-            dict_variables: HashMap::new(),
+        Self::default()
+    }
+
+    pub fn push_scope(&mut self, kind: ScopeKind) {
+        self.scopes.push(Scope {
+            kind,
+            values: HashMap::new(),
+            globals: HashSet::new(),
+        });
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Creates `name` in the innermost scope, shadowing any outer variable with the same name.
+    /// Outside of any local scope the variable is global.
+    pub fn declare_local(&mut self, name: String, value: ParsedValue) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.values.insert(name, value);
+            }
+            None => {
+                self.values.insert(name, value);
+            }
         }
     }
+    /// Makes `name` refer to the global variable for the rest of the innermost scope, so
+    /// assignments to it no longer create a local. Does nothing outside of any local scope.
+    pub fn declare_global(&mut self, name: &str) -> Result<(), KeyForgeError> {
+        match self.scopes.last_mut() {
+            Some(scope) if scope.values.contains_key(name) => {
+                Err(KeyForgeError::runtime(format!("'{}' is already a local variable in this scope", name)))
+            }
+            Some(scope) => {
+                scope.globals.insert(name.to_string());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Scopes visible from the current position, innermost first, ending with the nearest
+    /// function scope.
+    pub fn scope_chain(&self) -> impl Iterator<Item = &Scope> {
+        let start = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.kind == ScopeKind::Function)
+            .unwrap_or(0);
+        self.scopes[start..].iter().rev()
+    }
+
+    /// Index of the visible local scope that holds `name`, searching from the innermost scope
+    /// up to the nearest function boundary or `global` declaration.
+    fn local_index(&self, name: &str) -> Option<usize> {
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
+            if scope.values.contains_key(name) {
+                return Some(index);
+            }
+            if scope.globals.contains(name) || scope.kind == ScopeKind::Function {
+                break;
+            }
+        }
+        None
+    }
+
+    fn is_declared_global(&self, name: &str) -> bool {
+        self.scope_chain().any(|scope| scope.globals.contains(name))
+    }
+
+    fn local(&self, name: &str) -> Option<&ParsedValue> {
+        self.local_index(name).and_then(|index| self.scopes[index].values.get(name))
+    }
+
+    /// Scope that an assignment to `name` writes to, or `None` for the global maps. Existing
+    /// variables are updated where they live; new ones are created in the innermost scope.
+    /// Inside a function only a `global` declaration reaches a global variable, so a function
+    /// cannot overwrite its caller's variables by reusing their names.
+    fn assignment_target(&self, name: &str) -> Option<usize> {
+        if let Some(index) = self.local_index(name) {
+            return Some(index);
+        }
+        let global = self.is_declared_global(name) || (self.has_global(name) && !self.in_function());
+        if self.scopes.is_empty() || global {
+            None
+        } else {
+            Some(self.scopes.len() - 1)
+        }
+    }
+
+    fn in_function(&self) -> bool {
+        self.scopes.iter().any(|scope| scope.kind == ScopeKind::Function)
+    }
+
+    fn has_global(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
 
     pub fn has_variable(&self, name: &str) -> bool {
-        self.int_variables.contains_key(name)
-            || self.float_variables.contains_key(name)
-            || self.string_variables.contains_key(name)
-            || self.array_variables.contains_key(name)
-            || self.dict_variables.contains_key(name)
+        self.lookup(name).is_some()
     }
 
-    pub fn get_int_data(&self, name: &str) -> Result<i32, String> {
-        self.int_variables
-            .get(name)
-            .copied()
-            .ok_or_else(|| format!("Int variable '{}' not found", name))
+    /// The visible variable called `name`: the innermost local one, otherwise the global one,
+    /// otherwise the script argument. A user's own `argc` or `argv` therefore wins, while `$1`
+    /// always means the argument.
+    pub fn lookup(&self, name: &str) -> Option<&ParsedValue> {
+        if is_positional(name) {
+            return self.arguments.get(name);
+        }
+        self.local(name)
+            .or_else(|| self.values.get(name))
+            .or_else(|| self.arguments.get(name))
     }
 
-    pub fn get_float_data(&self, name: &str) -> Result<f64, String> {
-        self.float_variables
-            .get(name)
-            .copied()
-            .ok_or_else(|| format!("Float variable '{}' not found", name))
+    pub fn get_value(&self, name: &str) -> Option<ParsedValue> {
+        self.lookup(name).cloned()
     }
 
-    pub fn get_string_data(&self, name: &str) -> Result<String, String> {
-        self.string_variables
-            .get(name)
-            .cloned()
-            .ok_or_else(|| format!("String variable '{}' not found", name))
+    /// Global variables of every type, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&String, &ParsedValue)> {
+        self.values.iter()
     }
 
-    pub fn get_array_data(&self, name: &str) -> Result<Vec<ParsedValue>, String> {
-        self.array_variables
-            .get(name)
-            .cloned()
-            .ok_or_else(|| format!("Array variable '{}' not found", name))
+    /// Error for reading `name` as `expected` when it is missing or holds another type.
+    pub(crate) fn wrong_type(&self, name: &str, expected: &str) -> KeyForgeError {
+        if self.has_variable(name) {
+            KeyForgeError::type_error(format!("Variable '{}' is not {}", name, expected))
+        } else {
+            KeyForgeError::undefined(name)
+        }
     }
 
-    pub fn get_dict_data(&self, name: &str) -> Result<HashMap<String, ParsedValue>, String> {
-        self.dict_variables
-            .get(name)
-            .cloned()
-            .ok_or_else(|| format!("Dictionary variable '{}' not found", name))
+    pub fn get_int_data(&self, name: &str) -> Result<i64, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::Int(v)) => Ok(*v),
+            _ => Err(self.wrong_type(name, "an integer")),
+        }
+    }
+
+    pub fn get_float_data(&self, name: &str) -> Result<f64, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::Float(v)) => Ok(*v),
+            _ => Err(self.wrong_type(name, "a float")),
+        }
+    }
+
+    pub fn get_string_data(&self, name: &str) -> Result<String, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::String(v)) => Ok(v.clone()),
+            _ => Err(self.wrong_type(name, "a string")),
+        }
     }
 
-    pub fn add_data_to_int(&mut self, name: String, v: i32) {
-        self.int_variables.insert(name, v);
+    pub fn get_bool_data(&self, name: &str) -> Result<bool, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::Bool(v)) => Ok(*v),
+            _ => Err(self.wrong_type(name, "a boolean")),
+        }
+    }
+
+    pub fn get_bytes_data(&self, name: &str) -> Result<Vec<u8>, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::Bytes(v)) => Ok(v.clone()),
+            _ => Err(self.wrong_type(name, "bytes")),
+        }
+    }
+
+    pub fn get_array_data(&self, name: &str) -> Result<Vec<ParsedValue>, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::Array(v)) => Ok(v.clone()),
+            _ => Err(self.wrong_type(name, "an array")),
+        }
+    }
+
+    pub fn get_dict_data(&self, name: &str) -> Result<HashMap<String, ParsedValue>, KeyForgeError> {
+        match self.lookup(name) {
+            Some(ParsedValue::Dictionary(v)) => Ok(v.clone()),
+            _ => Err(self.wrong_type(name, "a dictionary")),
+        }
+    }
+
+    /// Assigns `value` to `name`, replacing whatever the variable held before. Existing
+    /// variables are updated in the scope they live in.
+    pub fn set_value(&mut self, name: String, value: ParsedValue) {
+        match self.assignment_target(&name) {
+            Some(index) => {
+                self.scopes[index].values.insert(name, value);
+            }
+            None => {
+                self.values.insert(name, value);
+            }
+        }
+    }
+
+    pub fn add_data_to_int(&mut self, name: String, v: i64) {
+        self.set_value(name, ParsedValue::Int(v));
     }
 
     pub fn add_data_to_float(&mut self, name: String, v: f64) {
-        self.float_variables.insert(name, v);
+        self.set_value(name, ParsedValue::Float(v));
     }
 
     pub fn add_data_to_string(&mut self, name: String, v: String) {
-        self.string_variables.insert(name, v);
+        self.set_value(name, ParsedValue::String(v));
     }
 
     pub fn add_data_to_array(&mut self, name: String, v: Vec<ParsedValue>) {
-        self.array_variables.insert(name, v);
+        self.set_value(name, ParsedValue::Array(v));
     }
 
     pub fn add_data_to_dict(&mut self, name: String, v: HashMap<String, ParsedValue>) {
-        self.dict_variables.insert(name, v);
+        self.set_value(name, ParsedValue::Dictionary(v));
+    }
+
+    /// Deletes the visible variable called `name`, local or global, and returns its value.
+    pub fn remove(&mut self, name: &str) -> Option<ParsedValue> {
+        match self.local_index(name) {
+            Some(index) => self.scopes[index].values.remove(name),
+            None => self.values.remove(name),
+        }
     }
 
-    pub fn remove_int_data(&mut self, name: &str) {
-        self.int_variables.remove(name);
+    /// Deletes every global variable. Local scopes are left alone.
+    pub fn clear_globals(&mut self) {
+        self.values.clear();
     }
 
-    pub fn remove_float_data(&mut self, name: &str) {
-        self.float_variables.remove(name);
+    /// Sets `$0`, the script the arguments were given to.
+    pub fn set_script_name(&mut self, script: &str) {
+        self.arguments.insert("0".to_string(), ParsedValue::String(script.to_string()));
     }
 
-    pub fn remove_string_data(&mut self, name: &str) {
-        self.string_variables.remove(name);
+    /// Replaces `$1..$N`, `$argc` and `$argv` with `args`, keeping `$0`.
+    pub fn set_arguments(&mut self, args: &[String]) {
+        self.arguments.retain(|name, _| name == "0");
+        for (index, arg) in args.iter().enumerate() {
+            self.arguments.insert((index + 1).to_string(), parse_value(arg));
+        }
+        self.arguments.insert("argc".to_string(), ParsedValue::Int(args.len() as i64));
+        self.arguments
+            .insert("argv".to_string(), ParsedValue::Array(args.iter().map(|arg| parse_value(arg)).collect()));
     }
 
-    #[allow(dead_code)]
-    pub fn remove_array_data(&mut self, name: &str) {
-        self.array_variables.remove(name);
+    pub fn arguments(&self) -> &HashMap<String, ParsedValue> {
+        &self.arguments
     }
 
-    #[allow(dead_code)]
-    pub fn remove_dict_data(&mut self, name: &str) {
-        self.dict_variables.remove(name);
+    /// Puts back arguments taken from `arguments`, e.g. after an included script replaced them.
+    pub fn restore_arguments(&mut self, arguments: HashMap<String, ParsedValue>) {
+        self.arguments = arguments;
     }
 
-    pub fn remove_string_char(&mut self, name: &str, index: usize) -> Result<(), String> {
+    /// Sets a global variable, whatever scopes are open. Used when restoring saved state.
+    pub fn set_global(&mut self, name: String, value: ParsedValue) {
+        self.values.insert(name, value);
+    }
+    pub fn remove_string_char(&mut self, name: &str, index: usize) -> Result<(), KeyForgeError> {
         let s = self.get_string_data(name)?;
         let mut chars: Vec<char> = s.chars().collect();
         
         if index >= chars.len() {
-            return Err(format!("Index {} out of bounds for string '{}' with length {}", 
-                            index, name, chars.len()));
+            return Err(KeyForgeError::runtime(format!("Index {} out of bounds for string '{}' with length {}", 
+                            index, name, chars.len())));
         }
         
         chars.remove(index);
@@ -136,66 +374,153 @@ impl Variables {
         Ok(())
     }
 
-    pub fn vl(&self, mode: &str) {
-        match mode {
-            "i" => {
-                println!("=== Integer Variables (i32) ===");
-                for (k, v) in &self.int_variables {
-                    println!("{}: {}", k, v);
-                }
-            }
-            "f" => {
-                println!("=== Float Variables (f64) ===");
-                for (k, v) in &self.float_variables {
-                    println!("{}: {}", k, v);
-                }
-            }
-            "s" => {
-                println!("=== String Variables (String) ===");
-                for (k, v) in &self.string_variables {
-                    println!("{}: {}", k, v);
+    /// One line per visible local scope, innermost first, e.g. `loop: i = 2`. Empty at the
+    /// top level.
+    pub fn scope_chain_lines(&self) -> Vec<String> {
+        self.scope_chain()
+            .map(|scope| {
+                let mut names: Vec<&String> = scope.values.keys().collect();
+                names.sort();
+                let mut entries: Vec<String> = names
+                    .into_iter()
+                    .map(|name| format!("{} = {}", name, value_to_string(&scope.values[name])))
+                    .collect();
+                let mut globals: Vec<&String> = scope.globals.iter().collect();
+                globals.sort();
+                entries.extend(globals.into_iter().map(|name| format!("global {}", name)));
+
+                if entries.is_empty() {
+                    format!("{}: (empty)", scope.kind.name())
+                } else {
+                    format!("{}: {}", scope.kind.name(), entries.join(", "))
                 }
+            })
+            .collect()
+    }
+
+    /// Prints the global variables accepted by `filter`, grouped by type and sorted by name,
+    /// after the visible local scopes.
+    pub fn vl(&self, filter: &VariableFilter, out: &mut dyn Write) -> std::io::Result<()> {
+        let chain = self.scope_chain_lines();
+        if !chain.is_empty() {
+            writeln!(out, "=== Scope Chain (innermost first) ===")?;
+            for line in &chain {
+                writeln!(out, "{}", line)?;
             }
-            "a" => {
-                println!("=== Array Variables ===");
-                for (k, v) in &self.array_variables {
-                    println!("{}: {:?}", k, v);
-                }
+            writeln!(out)?;
+        }
+
+        let mut names: Vec<&String> = self.values.keys().filter(|name| filter.matches_name(name)).collect();
+        names.sort();
+
+        let mut first = true;
+        for (type_name, title) in VL_SECTIONS {
+            let of_type: Vec<&&String> = names
+                .iter()
+                .filter(|name| self.values[name.as_str()].type_name() == type_name)
+                .collect();
+            // Without a type filter only the types that have variables are listed
+            let listed = if filter.types.is_empty() {
+                !of_type.is_empty()
+            } else {
+                filter.types.contains(&type_name)
+            };
+            if !listed {
+                continue;
             }
-            "d" => {
-                println!("=== Dictionary Variables ===");
-                for (k, v) in &self.dict_variables {
-                    println!("{}: {:?}", k, v);
-                }
+            if !first {
+                writeln!(out)?;
             }
-            _ => {
-                println!("=== Integer Variables (i32) ===");
-                for (k, v) in &self.int_variables {
-                    println!("{}: {}", k, v);
-                }
-                println!("");
-                println!("=== Float Variables (f64) ===");
-                for (k, v) in &self.float_variables {
-                    println!("{}: {}", k, v);
-                }
-                println!("");
-                println!("=== String Variables (String) ===");
-                for (k, v) in &self.string_variables {
-                    println!("{}: {}", k, v);
-                }
-                println!("");
-                println!("=== Array Variables ===");
-                for (k, v) in &self.array_variables {
-                    println!("{}: {:?}", k, v);
-                }
-                println!("");
-                println!("=== Dictionary Variables ===");
-                for (k, v) in &self.dict_variables {
-                    println!("{}: {:?}", k, v);
-                }
+            first = false;
+
+            writeln!(out, "=== {} ===", title)?;
+            for name in of_type {
+                writeln!(out, "{}: {}", name, value_to_string(&self.values[name.as_str()]))?;
             }
         }
+        if first {
+            writeln!(out, "(no variables)")?;
+        }
+        Ok(())
+    }
+}
+
+/// `vl` sections: the type name each one lists and its title.
+const VL_SECTIONS: [(&str, &str); 8] = [
+    ("int", "Integer Variables (i64)"),
+    ("float", "Float Variables (f64)"),
+    ("string", "String Variables"),
+    ("bool", "Boolean Variables"),
+    ("null", "Null Variables"),
+    ("bytes", "Bytes Variables"),
+    ("array", "Array Variables"),
+    ("dict", "Dictionary Variables"),
+];
+
+/// Which variables `vl` lists: `vl [type] [pattern]`. The type is a name (`int`, `bytes`, ...)
+/// or a one-letter shorthand; the pattern matches names, with `*` standing for any text.
+#[derive(Debug, Default)]
+pub struct VariableFilter {
+    /// Type names to list; all types when empty
+    pub types: Vec<&'static str>,
+    pub pattern: Option<String>,
+}
+
+impl VariableFilter {
+    pub fn parse(args: &[String]) -> Result<Self, KeyForgeError> {
+        let mut filter = Self::default();
+        let mut args = args.iter().peekable();
+
+        if let Some(types) = args.peek().and_then(|arg| Self::types_for(arg)) {
+            filter.types = types;
+            args.next();
+        }
+        filter.pattern = args.next().cloned();
+        if args.next().is_some() {
+            return Err(KeyForgeError::arity("Usage: vl [type] [pattern]"));
+        }
+        Ok(filter)
+    }
+
+    fn types_for(arg: &str) -> Option<Vec<&'static str>> {
+        let types: &[&'static str] = match arg {
+            "i" | "int" => &["int"],
+            "f" | "float" => &["float"],
+            "s" | "string" => &["string"],
+            "b" | "bool" => &["bool"],
+            "n" | "null" => &["null"],
+            "x" | "bytes" => &["bytes"],
+            "a" | "array" => &["array"],
+            "d" | "dict" => &["dict"],
+            "o" => &["bool", "null", "bytes"],
+            _ => return None,
+        };
+        Some(types.to_vec())
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        self.pattern.as_deref().is_none_or(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` stands for any (possibly empty) text.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
     }
+    rest.len() >= last.len() && rest.ends_with(last)
 }
 
 pub fn parse_value(raw: &str) -> ParsedValue {
@@ -218,17 +543,15 @@ pub fn parse_value(raw: &str) -> ParsedValue {
         let pairs: Vec<&str> = split_dict_pairs(dict_str);
         
         for pair in pairs {
-            if let Some((key, value)) = pair.split_once(':') {
-                let key = key.trim().to_string();
-                let value = parse_value(value.trim());
-                dict.insert(key, value);
+            if let Some((key, value)) = split_dict_pair(pair) {
+                dict.insert(key, parse_value(value.trim()));
             }
         }
         return ParsedValue::Dictionary(dict);
     }
 
     // Try integer
-    if let Ok(iv) = trimmed.parse::<i32>() {
+    if let Ok(iv) = trimmed.parse::<i64>() {
         return ParsedValue::Int(iv);
     }
 
@@ -237,6 +560,16 @@ pub fn parse_value(raw: &str) -> ParsedValue {
         return ParsedValue::Float(fv);
     }
 
+    match trimmed {
+        "true" => return ParsedValue::Bool(true),
+        "false" => return ParsedValue::Bool(false),
+        "null" => return ParsedValue::Null,
+        _ => {}
+    }
+    if let Some(bytes) = parse_bytes_literal(trimmed) {
+        return ParsedValue::Bytes(bytes);
+    }
+
     // Handle quoted strings
     let s = if let Some(stripped) = trimmed.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
         stripped.to_string()
@@ -249,6 +582,16 @@ pub fn parse_value(raw: &str) -> ParsedValue {
     ParsedValue::String(s)
 }
 
+/// Decodes a `0x...` bytes literal. `0x` alone is an empty byte string.
+pub(crate) fn parse_bytes_literal(text: &str) -> Option<Vec<u8>> {
+    from_hex(text.strip_prefix("0x")?).ok()
+}
+
+/// Hex digits of `bytes` behind `0x`, the form `parse_value` reads back.
+pub(crate) fn bytes_literal(bytes: &[u8]) -> String {
+    format!("0x{}", to_hex(bytes))
+}
+
 // Helper function to split array elements considering nested structures
 fn split_array_elements(s: &str) -> Vec<&str> {
     let mut elements = Vec::new();
@@ -283,6 +626,35 @@ fn split_array_elements(s: &str) -> Vec<&str> {
     elements.iter().map(|s| s.trim()).collect()
 }
 
+/// `key` as written in a dictionary literal, quoted when it would not survive `parse_value`.
+fn dict_key_literal(key: &str) -> String {
+    let needs_quotes = key.trim().is_empty()
+        || key.trim() != key
+        || key.contains([':', ',', '"', '\'', '[', ']', '{', '}']);
+    if needs_quotes && !key.contains('"') {
+        format!("\"{}\"", key)
+    } else if needs_quotes && !key.contains('\'') {
+        format!("'{}'", key)
+    } else {
+        key.to_string()
+    }
+}
+
+/// Splits `key: value` at the first colon. A key may be quoted, as in JSON, and then contain
+/// colons itself.
+fn split_dict_pair(pair: &str) -> Option<(String, &str)> {
+    let pair = pair.trim();
+    for quote in ['"', '\''] {
+        if let Some(rest) = pair.strip_prefix(quote) {
+            let (key, after) = rest.split_once(quote)?;
+            let value = after.trim_start().strip_prefix(':')?;
+            return Some((key.to_string(), value));
+        }
+    }
+    let (key, value) = pair.split_once(':')?;
+    Some((key.trim().to_string(), value))
+}
+
 // Helper function to split dictionary pairs considering nested structures
 fn split_dict_pairs(s: &str) -> Vec<&str> {
     let mut pairs = Vec::new();
@@ -318,60 +690,28 @@ fn split_dict_pairs(s: &str) -> Vec<&str> {
 }
 
 // Update store_parsed_value to handle arrays and dictionaries
-pub fn store_parsed_value(name: String, value: ParsedValue, _source: Option<&str>) -> Result<(), String> {
-    let mut store = get_variable_store().lock().map_err(|e| format!("Mutex poisoned: {}", e))?;
-
-    match value {
-        ParsedValue::Int(iv) => store.add_data_to_int(name, iv),
-        ParsedValue::Float(fv) => store.add_data_to_float(name, fv),
-        ParsedValue::String(sv) => store.add_data_to_string(name, sv),
-        ParsedValue::Array(arr) => store.add_data_to_array(name, arr),
-        ParsedValue::Dictionary(dict) => store.add_data_to_dict(name, dict),
-    }
-
+pub fn store_parsed_value(store: &mut Variables, name: String, value: ParsedValue) -> Result<(), KeyForgeError> {
+    store.set_value(name, value);
     Ok(())
 }
 
 // Update resolve_to_string to handle arrays and dictionaries
-pub fn resolve_to_string(value: &str) -> Result<String, String> {
-    let key = if value.starts_with('$') { &value[1..] } else { value };
-
-    let store = get_variable_store().lock().unwrap();
-
-    if let Ok(int_val) = store.get_int_data(key) {
-        Ok(int_val.to_string())
-    } else if let Ok(float_val) = store.get_float_data(key) {
-        Ok(float_val.to_string())
-    } else if let Ok(string_val) = store.get_string_data(key) {
-        Ok(string_val)
-    } else if let Ok(array_val) = store.get_array_data(key) {
-        // Convert array to string representation
-        let elements: Vec<String> = array_val.iter().map(|v| value_to_string(v)).collect();
-        Ok(format!("[{}]", elements.join(", ")))
-    } else if let Ok(dict_val) = store.get_dict_data(key) {
-        // Convert dictionary to string representation
-        let pairs: Vec<String> = dict_val.iter()
-            .map(|(k, v)| format!("{}: {}", k, value_to_string(v)))
-            .collect();
-        Ok(format!("{{{}}}", pairs.join(", ")))
-    } else {
+pub fn resolve_to_string(store: &Variables, value: &str) -> Result<String, KeyForgeError> {
+    let key = match value.strip_prefix('$') {
+        Some(name) => name,
+        // A bare number is a literal, not a script argument
+        None if is_positional(value) => "",
+        None => value,
+    };
+
+    match store.get_value(key) {
+        Some(ParsedValue::String(s)) => Ok(s),
+        Some(other) => Ok(value_to_string(&other)),
         // Variable doesn't exist - try parsing as literal
-        let parsed_value = parse_value(value);
-        match parsed_value {
-            ParsedValue::Int(i) => Ok(i.to_string()),
-            ParsedValue::Float(f) => Ok(f.to_string()),
+        None => match parse_value(value) {
             ParsedValue::String(s) => Ok(s),
-            ParsedValue::Array(arr) => {
-                let elements: Vec<String> = arr.iter().map(|v| value_to_string(v)).collect();
-                Ok(format!("[{}]", elements.join(", ")))
-            }
-            ParsedValue::Dictionary(dict) => {
-                let pairs: Vec<String> = dict.iter()
-                    .map(|(k, v)| format!("{}: {}", k, value_to_string(v)))
-                    .collect();
-                Ok(format!("{{{}}}", pairs.join(", ")))
-            }
-        }
+            other => Ok(value_to_string(&other)),
+        },
     }
 }
 
@@ -380,169 +720,22 @@ pub(crate) fn value_to_string(value: &ParsedValue) -> String {
         ParsedValue::Int(i) => i.to_string(),
         ParsedValue::Float(f) => f.to_string(),
         ParsedValue::String(s) => format!("\"{}\"", s),
+        ParsedValue::Bool(b) => b.to_string(),
+        ParsedValue::Null => "null".to_string(),
+        ParsedValue::Bytes(bytes) => bytes_literal(bytes),
         ParsedValue::Array(arr) => {
             let elements: Vec<String> = arr.iter().map(value_to_string).collect();
             format!("[{}]", elements.join(", "))
         }
         ParsedValue::Dictionary(dict) => {
             let pairs: Vec<String> = dict.iter()
-                .map(|(k, v)| format!("{}: {}", k, value_to_string(v)))
+                .map(|(k, v)| format!("{}: {}", dict_key_literal(k), value_to_string(v)))
                 .collect();
             format!("{{{}}}", pairs.join(", "))
         }
     }
 }
 
-// Update save_state_to_file and load_state_from_file to handle arrays and dictionaries
-pub fn save_state_to_file(filename: &str, store: &Variables) -> Result<(), String> {
-    use std::fs::File;
-    use std::io::Write;
-    
-    let mut file = File::create(filename)
-        .map_err(|e| format!("Failed to create file '{}': {}", filename, e))?;
-    
-    // Save integer variables
-    for (name, value) in &store.int_variables {
-        writeln!(file, "int:{}:{}", name, value)
-            .map_err(|e| format!("Failed to write to file: {}", e))?;
-    }
-    
-    // Save float variables
-    for (name, value) in &store.float_variables {
-        writeln!(file, "float:{}:{}", name, value)
-            .map_err(|e| format!("Failed to write to file: {}", e))?;
-    }
-    
-    // Save string variables (escape newlines and colons)
-    for (name, value) in &store.string_variables {
-        let escaped_value = value.replace("\\", "\\\\").replace(":", "\\:").replace("\n", "\\n");
-        writeln!(file, "string:{}:{}", name, escaped_value)
-            .map_err(|e| format!("Failed to write to file: {}", e))?;
-    }
-    
-    // Save array variables (using JSON serialization)
-    for (name, value) in &store.array_variables {
-        let json_value = serde_json::to_string(value)
-            .map_err(|e| format!("Failed to serialize array '{}': {}", name, e))?;
-        let escaped_json = json_value.replace("\\", "\\\\").replace(":", "\\:").replace("\n", "\\n");
-        writeln!(file, "array:{}:{}", name, escaped_json)
-            .map_err(|e| format!("Failed to write to file: {}", e))?;
-    }
-    
-    // Save dictionary variables (using JSON serialization)
-    for (name, value) in &store.dict_variables {
-        let json_value = serde_json::to_string(value)
-            .map_err(|e| format!("Failed to serialize dict '{}': {}", name, e))?;
-        let escaped_json = json_value.replace("\\", "\\\\").replace(":", "\\:").replace("\n", "\\n");
-        writeln!(file, "dict:{}:{}", name, escaped_json)
-            .map_err(|e| format!("Failed to write to file: {}", e))?;
-    }
-    
-    Ok(())
-}
-
-pub fn load_state_from_file(filename: &str, store: &mut Variables) -> Result<(), String> {
-    use std::fs::File;
-    use std::io::{BufRead, BufReader};
-    
-    let file = File::open(filename)
-        .map_err(|e| format!("Failed to open file '{}': {}", filename, e))?;
-    
-    let reader = BufReader::new(file);
-    
-    // Clear existing variables before loading
-    store.int_variables.clear();
-    store.float_variables.clear();
-    store.string_variables.clear();
-    store.array_variables.clear();
-    store.dict_variables.clear();
-    
-    for (line_num, line) in reader.lines().enumerate() {
-        let line = line.map_err(|e| format!("Failed to read line {}: {}", line_num + 1, e))?;
-        let line = line.trim();
-        
-        if line.is_empty() || line.starts_with("//") {
-            continue;
-        }
-        
-        let parts: Vec<&str> = line.splitn(3, ':').collect();
-        if parts.len() != 3 {
-            return Err(format!("Invalid format at line {}: expected 'type:name:value'", line_num + 1));
-        }
-        
-        let var_type = parts[0];
-        let name = parts[1];
-        let value = parts[2];
-        
-        match var_type {
-            "int" => {
-                let int_value = value.parse::<i32>()
-                    .map_err(|e| format!("Invalid integer value at line {}: {}", line_num + 1, e))?;
-                store.add_data_to_int(name.to_string(), int_value);
-            }
-            "float" => {
-                let float_value = value.parse::<f64>()
-                    .map_err(|e| format!("Invalid float value at line {}: {}", line_num + 1, e))?;
-                store.add_data_to_float(name.to_string(), float_value);
-            }
-            "string" => {
-                let unescaped_value = value.replace("\\n", "\n").replace("\\:", ":").replace("\\\\", "\\");
-                store.add_data_to_string(name.to_string(), unescaped_value);
-            }
-            "array" => {
-                let unescaped_value = value.replace("\\n", "\n").replace("\\:", ":").replace("\\\\", "\\");
-                let array_value: Vec<ParsedValue> = serde_json::from_str(&unescaped_value)
-                    .map_err(|e| format!("Invalid array value at line {}: {}", line_num + 1, e))?;
-                store.add_data_to_array(name.to_string(), array_value);
-            }
-            "dict" => {
-                let unescaped_value = value.replace("\\n", "\n").replace("\\:", ":").replace("\\\\", "\\");
-                let dict_value: HashMap<String, ParsedValue> = serde_json::from_str(&unescaped_value)
-                    .map_err(|e| format!("Invalid dict value at line {}: {}", line_num + 1, e))?;
-                store.add_data_to_dict(name.to_string(), dict_value);
-            }
-            _ => return Err(format!("Unknown variable type '{}' at line {}", var_type, line_num + 1)),
-        }
-    }
-    
-    Ok(())
-}
-
-lazy_static! {
-    static ref VARIABLE_STORE: Mutex<Variables> = Mutex::new(Variables::new());
-    static ref BREAK_FLAG: Mutex<bool> = Mutex::new(false);
-    static ref CONTINUE_FLAG: Mutex<bool> = Mutex::new(false);
-}
-
-pub fn set_break_flag(value: bool) {
-    let mut flag = BREAK_FLAG.lock().unwrap();
-    *flag = value;
-}
-
-pub fn set_continue_flag(value: bool) {
-    let mut flag = CONTINUE_FLAG.lock().unwrap();
-    *flag = value;
-}
-
-pub fn should_break() -> bool {
-    let flag = BREAK_FLAG.lock().unwrap();
-    *flag
-}
-
-pub fn should_continue() -> bool {
-    let flag = CONTINUE_FLAG.lock().unwrap();
-    *flag
-}
-
-pub fn reset_loop_flags() {
-    set_break_flag(false);
-    set_continue_flag(false);
-}
-
-pub fn get_variable_store() -> &'static Mutex<Variables> {
-    &*VARIABLE_STORE
-}
-
 pub fn is_valid_identifier(s: &str) -> bool {
     let mut chars = s.chars();
     if let Some(first) = chars.next() {
@@ -556,23 +749,32 @@ pub fn is_valid_identifier(s: &str) -> bool {
     chars.all(|c| c.is_alphanumeric() || c == '_')
 }
 
-pub fn get_random_char(mode: i32) -> Result<char, String> {
-    let mut rng = rand::thread_rng();
-    let base = if mode == 1 { 'A' as u8 } else { 'a' as u8 };
+/// Names of script arguments: `$0` is the script, `$1..$N` the arguments after it.
+pub fn is_positional(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Whether `$name` refers to a variable: any identifier, or a script argument such as `$1`.
+pub fn is_variable_reference(name: &str) -> bool {
+    is_valid_identifier(name) || is_positional(name)
+}
+
+pub fn get_random_char<R: Rng>(rng: &mut R, mode: i32) -> Result<char, KeyForgeError> {
+    let base = if mode == 1 { b'A' } else { b'a' };
     let offset = rng.gen_range(0..26);
     Ok((base + offset) as char)
 }
 
 // Generic wrapper used by execute_command which expects `get_random_num` generic
-pub fn get_random_num<T>(min: T, max: T) -> T
+pub fn get_random_num<R, T>(rng: &mut R, min: T, max: T) -> T
 where
+    R: Rng,
     T: PartialOrd + Copy + rand::distributions::uniform::SampleUniform,
 {
-    let mut rng = rand::thread_rng();
     rng.gen_range(min..max)
 }
 
-pub fn substitute_variables_in_string(s: &str) -> String {
+pub fn substitute_variables_in_string(store: &Variables, s: &str) -> String {
     let mut result = String::new();
     let mut chars = s.chars().peekable();
     
@@ -591,17 +793,14 @@ pub fn substitute_variables_in_string(s: &str) -> String {
             
             if !var_name.is_empty() {
                 // Try to get variable value
-                let store = get_variable_store().lock().unwrap();
-                if let Ok(int_val) = store.get_int_data(&var_name) {
-                    result.push_str(&int_val.to_string());
-                } else if let Ok(float_val) = store.get_float_data(&var_name) {
-                    result.push_str(&float_val.to_string());
-                } else if let Ok(string_val) = store.get_string_data(&var_name) {
-                    result.push_str(&string_val);
-                } else {
-                    // Variable not found, leave as is
-                    result.push('$');
-                    result.push_str(&var_name);
+                match store.get_value(&var_name) {
+                    Some(ParsedValue::String(string_val)) => result.push_str(&string_val),
+                    Some(ParsedValue::Array(_) | ParsedValue::Dictionary(_)) | None => {
+                        // Collections and unknown variables are left as is
+                        result.push('$');
+                        result.push_str(&var_name);
+                    }
+                    Some(value) => result.push_str(&value_to_string(&value)),
                 }
             } else {
                 result.push(c);
@@ -614,138 +813,18 @@ pub fn substitute_variables_in_string(s: &str) -> String {
     result
 }
 
-pub fn evaluate_condition(condition: &str) -> Result<bool, String> {
-    let tokens: Vec<&str> = condition.split_whitespace().collect();
-
-    if tokens.len() >= 3 && (tokens.contains(&"and") || tokens.contains(&"or")) {
-        return evaluate_complex_condition(&tokens);
-    }
-
-    if tokens.len() < 3 {
-        return Err("Condition must have at least 3 parts".to_string());
-    }
-
-    let left = resolve_to_string(tokens[0])?;
-    let operator = tokens[1];
-    let right = resolve_to_string(&tokens[2..].join(" "))?;
-
-    if let (Ok(left_num), Ok(right_num)) = (left.parse::<f64>(), right.parse::<f64>()) {
-        match operator {
-            "==" | "eq" => Ok((left_num - right_num).abs() < f64::EPSILON),
-            "!=" | "ne" => Ok((left_num - right_num).abs() > f64::EPSILON),
-            ">" | "gt" => Ok(left_num > right_num),
-            "<" | "lt" => Ok(left_num < right_num),
-            ">=" | "ge" => Ok(left_num >= right_num),
-            "<=" | "le" => Ok(left_num <= right_num),
-            _ => Err(format!("Unknown operator: {}", operator)),
-        }
-    } else {
-        match operator {
-            "==" | "eq" => Ok(left == right),
-            "!=" | "ne" => Ok(left != right),
-            ">" | "gt" => Ok(left > right),
-            "<" | "lt" => Ok(left < right),
-            ">=" | "ge" => Ok(left >= right),
-            "<=" | "le" => Ok(left <= right),
-            _ => Err(format!("Unknown operator: {}", operator)),
-        }
-    }
-}
-
-fn evaluate_complex_condition(tokens: &[&str]) -> Result<bool, String> {
-    let mut result = None;
-    let mut current_operator = "and";
-    
-    let mut i = 0;
-    while i < tokens.len() {
-        if tokens[i] == "and" || tokens[i] == "or" {
-            current_operator = tokens[i];
-            i += 1;
-            continue;
-        }
-
-        let mut condition_end = i;
-        while condition_end < tokens.len() && tokens[condition_end] != "and" && tokens[condition_end] != "or" {
-            condition_end += 1;
-        }
-        
-        let condition_tokens = &tokens[i..condition_end];
-        if condition_tokens.len() < 3 {
-            return Err("Invalid condition in complex expression".to_string());
-        }
-        
-        let simple_condition = condition_tokens.join(" ");
-        let condition_result = evaluate_condition(&simple_condition)?;
-        
-        result = match result {
-            None => Some(condition_result),
-            Some(current) => match current_operator {
-                "and" => Some(current && condition_result),
-                "or" => Some(current || condition_result),
-                _ => Some(condition_result),
-            },
-        };
-        
-        i = condition_end;
-    }
-    
-    result.ok_or("No conditions found".to_string())
-}
-
-pub fn parse_block_commands(input: &str) -> Vec<String> {
-    let mut commands = Vec::new();
-    let mut current = String::new();
-    let mut brace_count = 0;
-    let mut in_quotes = false;
-    let mut quote_char = '\0';
-    
-    for c in input.chars() {
-        if in_quotes {
-            current.push(c);
-            if c == quote_char {
-                in_quotes = false;
-            }
-        } else {
-            match c {
-                '"' | '\'' => {
-                    in_quotes = true;
-                    quote_char = c;
-                    current.push(c);
-                }
-                '{' => {
-                    brace_count += 1;
-                    current.push(c);
-                }
-                '}' => {
-                    brace_count -= 1;
-                    current.push(c);
-                }
-                ';' if brace_count == 0 => {
-                    if !current.trim().is_empty() {
-                        commands.push(current.trim().to_string());
-                    }
-                    current.clear();
-                }
-                _ => current.push(c),
-            }
-        }
-    }
-    
-    if !current.trim().is_empty() {
-        commands.push(current.trim().to_string());
-    }
-    
-    commands
-}
-
 pub mod input_mode {
 
-    use std::io::{self, BufRead};
+    use std::path::Path;
+
     use colored::Colorize;
-    use std::fs::OpenOptions;
-    use rustyline::Editor;
+    use rustyline::{Config, Editor};
     use rustyline::error::ReadlineError;
 
+    use crate::key_forge::error::KeyForgeError;
+    use crate::key_forge::interpreter::Interpreter;
+    use crate::key_forge::repl::{self, ReplHelper, Session};
+
     pub fn tokenize_input(input: &str) -> Vec<String> {
     let mut parts = Vec::new();
     let mut current = String::new();
@@ -783,34 +862,73 @@ pub mod input_mode {
     parts
     }
 
-    pub fn cli_mode() {
+    pub fn cli_mode(interpreter: &mut Interpreter) {
         println!("{}", "KeyForge CLI mode".green());
+        interpreter.set_interactive(true);
     
-        let mut rl = Editor::<()>::new().unwrap_or_else(|e| {
+        let config = Config::builder()
+            .max_history_size(repl::HISTORY_SIZE)
+            .history_ignore_dups(true)
+            .build();
+        let mut rl = Editor::<ReplHelper>::with_config(config).unwrap_or_else(|e| {
             eprintln!("Error init CLI: {}", e);
             std::process::exit(1);
         });
+        rl.set_helper(Some(ReplHelper::new()));
+        // A missing history file just means this is the first session
+        let mut history_file = repl::history_path();
+        if let Some(path) = &history_file {
+            let _ = rl.load_history(path);
+        }
+        let mut session = Session::new();
+        // Lines of a block or string that is still open, run together once it is closed
+        let mut pending = String::new();
     
         loop {
-            let readline = rl.readline("> ");
+            if let Some(helper) = rl.helper_mut() {
+                helper.refresh(interpreter);
+                helper.set_pending(&pending);
+            }
+            let prompt = if pending.is_empty() { "> " } else { ".. " };
+            let readline = rl.readline(prompt);
             match readline {
                 Ok(line) => {
-                    let input = line.trim();
-                    if input.is_empty() {
+                    if pending.is_empty() && line.trim().is_empty() {
                         continue;
                     }
-                    let _ = rl.add_history_entry(input);
-                    
-                    let args = tokenize_input(input);
-                    if args.is_empty() {
+                    pending.push_str(&line);
+                    pending.push('\n');
+                    if repl::is_incomplete(&pending) {
                         continue;
                     }
-                    
-                    match crate::key_forge::execute_command::execute_command(&args, false) {
-                        Ok(_) => continue,
-                        Err(e) => println!("{}", format!("Error: {}", e).red()),
+                    let source = std::mem::take(&mut pending);
+                    let input = source.trim();
+                    repl::add_history_entry(rl.history_mut(), input);
+                    // Saved after every entry, since `exit` ends the process without coming back here
+                    if let Some(path) = &history_file {
+                        if let Err(e) = save_history(&mut rl, path) {
+                            println!("{}", format!("History will not be saved: {}", e).yellow());
+                            history_file = None;
+                        }
+                    }
+
+                    let result = if input.starts_with(':') {
+                        session.run_meta_command(interpreter, rl.history(), input)
+                    } else {
+                        interpreter.execute_source(input, false).map(|_| session.record(input))
+                    };
+                    if let Err(e) = result {
+                        if let Some(code) = e.exit_code() {
+                            std::process::exit(code);
+                        }
+                        println!("{}", e.render(Some(repl::error_source(input))).red());
                     }
                 },
+                Err(ReadlineError::Interrupted) if !pending.is_empty() => {
+                    // Ctrl-C inside a block only drops the unfinished input
+                    pending.clear();
+                    println!("Input discarded");
+                },
                 Err(ReadlineError::Interrupted) => {
                     println!("Ctrl-C - exit");
                     break;
@@ -827,135 +945,87 @@ pub mod input_mode {
         }
     }
     
-    pub fn file_mode(filename: &str) {
-        if let Ok(file) = OpenOptions::new().read(true).open(filename) {
-            let reader = io::BufReader::new(file);
-    
-            // We'll accumulate lines and handle multi-line blocks enclosed in braces { }
-            let mut buffer = String::new();
-            let mut brace_depth: i32 = 0;
-    
-            let mut current_line_number: u32 = 0;
+    fn save_history(rl: &mut Editor<ReplHelper>, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        rl.save_history(path)?;
+        Ok(())
+    }
 
+    /// Runs the script `filename`. The whole file is parsed up front, so a syntax error anywhere
+    /// stops it before it runs; execution stops at the first failing statement, however deeply
+    /// it is nested. The error carries the file, line and column it happened at.
+    pub fn file_mode(interpreter: &mut Interpreter, filename: &str) -> Result<(), KeyForgeError> {
+        interpreter.run_file(filename).map(|_| ())
+    }
 
-            for line in reader.lines() {
-                if let Ok(l) = line {
-                    current_line_number += 1;
+}
 
-                    // debug use
-                    /*
-                    println!("interpret line: {}", current_line_number);
-                    */
-                    
-                    // Preserve original line trimming for normal commands but keep spaces inside blocks
-                    let trimmed = l.trim().to_string();
-    
-                    // If we're already inside a block, append the raw line (with a space) to buffer
-                    if brace_depth > 0 {
-                        // Use semicolon to separate original lines inside a block so
-                        // `parse_block_commands` (which splits on ';') will produce
-                        // separate commands for each original line.
-                        // Keep spaces around separators so tokenization preserves
-                        // `{` and `}` as separate tokens.
-                        if buffer.ends_with('{') {
-                            // just add a space after the opening brace
-                            buffer.push(' ');
-                            buffer.push_str(trimmed.as_str());
-                        } else {
-                            // separate previous command and this one with ' ; '
-                            buffer.push_str(" ; ");
-                            buffer.push_str(trimmed.as_str());
-                        }
-                        // Update brace depth based on occurrences in this line
-                        brace_depth += trimmed.matches('{').count() as i32;
-                        brace_depth -= trimmed.matches('}').count() as i32;
-    
-                        if brace_depth <= 0 {
-                            // End of block reached; execute the combined command
-                            let args = tokenize_input(buffer.trim());
-                            let _ = crate::key_forge::execute_command::execute_command(&args, false);
-                            buffer.clear();
-                            brace_depth = 0;
-                        }
-                        continue;
-                    }
-    
-                    // Not currently in a block. Check if this line starts a block
-                    if trimmed.contains('{') {
-                        // Start collecting block
-                        buffer = trimmed.clone();
-                        brace_depth += trimmed.matches('{').count() as i32;
-                        brace_depth -= trimmed.matches('}').count() as i32;
-    
-                        if brace_depth <= 0 {
-                            // Opening and closing brace on same line
-                            let args = tokenize_input(buffer.trim());
-                            match crate::key_forge::execute_command::execute_command(&args, false) {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    println!("Error in line {}", current_line_number);
-                                    println!("{e}");
-                                    return; // if error stop interpret program
-                                }
-                            }
-                            buffer.clear();
-                            brace_depth = 0;
-                        }
-                        continue;
-                    }
 
-                    // Regular single-line command
-                    if !trimmed.is_empty() {
-                        let args = tokenize_input(&trimmed);
-                        match crate::key_forge::execute_command::execute_command(&args, false) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                println!("{}", format!("Error in line {}", current_line_number).red());
-                                println!("{}", format!("{}", e).red());
-                                return; // if error stop interpret program
-                            }
-                        }
-                    }
-                }
-            }
-    
-            // If file ends but buffer still contains something, try to execute it
-            if !buffer.trim().is_empty() {
-                let args = tokenize_input(buffer.trim());
-                let _ = crate::key_forge::execute_command::execute_command(&args, false);
-            }
-        } else {
-            println!("{}", format!("Cannot open file '{}'", filename).red());
+/// Runs every command line argument as a full statement (`key_forge arg "set x 10" "print x"`).
+/// Each argument is parsed as one line of a script, so a block opened with `{` may span several
+/// arguments. Nothing runs if the arguments contain a syntax error; otherwise execution stops at
+/// the first failing statement and the error names the argument(s) it came from.
+pub fn interpret_arguments_from_command_line(interpreter: &mut Interpreter, args: &[String]) -> Result<(), KeyForgeError> {
+    let mut source = String::new();
+    let mut argument_starts = Vec::with_capacity(args.len());
+    for arg in args {
+        argument_starts.push(source.len());
+        source.push_str(arg);
+        source.push('\n');
+    }
+    let argument_at = |offset: usize| argument_starts.partition_point(|&start| start <= offset).saturating_sub(1);
+
+    // Positions in the joined arguments mean little to the user, so errors name the argument
+    let script = parser::parse(&source).map_err(|e| {
+        let prefix = format!("Error in argument {}", argument_at(e.span.start) + 1);
+        KeyForgeError::from(e).without_location().prefixed(&prefix)
+    })?;
+
+    for statement in &script.statements {
+        if let Err(e) = interpreter.execute_statement(statement, false) {
+            let prefix = format!(
+                "Error in {}",
+                describe_argument_range(
+                    argument_at(statement.span.start),
+                    argument_at(statement.span.end.saturating_sub(1))
+                )
+            );
+            return Err(e.without_location().prefixed(&prefix));
         }
     }
 
+    Ok(())
 }
 
-
-pub fn interpret_arguments_from_command_line(_args: &[String]) -> Result<(), String> {
-    Err("Not implemented".to_string())
+fn describe_argument_range(start: usize, end: usize) -> String {
+    if start == end {
+        format!("argument {}", start + 1)
+    } else {
+        format!("arguments {}-{}", start + 1, end + 1)
+    }
 }
 
-pub fn resolve_filename(filename_raw: &str) -> Result<String, String> {
+pub fn resolve_filename(interpreter: &mut Interpreter, filename_raw: &str) -> Result<String, KeyForgeError> {
     // Handle command substitution: $(command)
     if filename_raw.starts_with("$(") && filename_raw.ends_with(')') {
         let command_content = &filename_raw[2..filename_raw.len()-1];
         let command_args: Vec<String> = input_mode::tokenize_input(command_content);
         
-        match crate::key_forge::execute_command::execute_command(&command_args, true) {
+        match interpreter.execute_command(&command_args, true) {
             Ok(result) => Ok(result.trim().to_string()),
-            Err(e) => Err(format!("Error executing command: {}", e)),
+            Err(e) => Err(e.prefixed("Error executing command")),
         }
     } 
     // Handle variable reference: $variable
-    else if filename_raw.starts_with('$') && is_valid_identifier(&filename_raw[1..]) {
+    else if filename_raw.starts_with('$') && is_variable_reference(&filename_raw[1..]) {
         let var_name = &filename_raw[1..];
-        let store = get_variable_store().lock().unwrap();
-        
-        if let Ok(string_val) = store.get_string_data(var_name) {
+
+        if let Ok(string_val) = interpreter.variables.get_string_data(var_name) {
             Ok(string_val)
         } else {
-            Err(format!("String variable {} not found", var_name))
+            Err(KeyForgeError::runtime(format!("String variable {} not found", var_name)))
         }
     }
     // Direct filename (remove quotes if present)
@@ -973,27 +1043,13 @@ pub fn resolve_filename(filename_raw: &str) -> Result<String, String> {
 pub mod setters {
 
     use std::collections::HashMap;
-    use super::{get_variable_store, parse_value};
-    use crate::key_forge::execute_command::execute_command;
-    use crate::input_mode::tokenize_input;
+    use super::parse_value;
+    use crate::key_forge::error::KeyForgeError;
+    use crate::key_forge::input_mode::tokenize_input;
+    use crate::key_forge::interpreter::Interpreter;
     use crate::key_forge::key_forge::ParsedValue;
 
-    #[allow(dead_code)]
-    pub fn set_simple_variable(args: &[String]) -> Result<String, String> {
-        let name = args[1].clone();
-        let raw_value = args[2..].join(" ");
-
-        // Use our new expression evaluator that handles variables, commands and arrays
-        match crate::key_forge::expression::evaluate_expression(&raw_value) {
-            Ok(parsed_value) => {
-                crate::key_forge::key_forge::store_parsed_value(name, parsed_value, None)?;
-                Ok(String::new())
-            }
-            Err(e) => Err(format!("Error evaluating expression: {}", e)),
-        }
-    }
-
-    pub fn set_collection_element(args: &[String]) -> Result<String, String> {
+    pub fn set_collection_element(interpreter: &mut Interpreter, args: &[String]) -> Result<String, KeyForgeError> {
         let collection_name = &args[1];
         let key_str = &args[2];
         let value_str = &args[3..].join(" ");
@@ -1002,31 +1058,31 @@ pub mod setters {
             let command_content = &value_str[2..value_str.len() - 1];
             let command_args: Vec<String> = tokenize_input(command_content);
 
-            match execute_command(&command_args, true) {
+            match interpreter.execute_command(&command_args, true) {
                 Ok(output) => parse_value(&output),
-                Err(e) => return Err(format!("Error executing inner command: {}", e)),
+                Err(e) => return Err(e.prefixed("Error executing inner command")),
             }
         } else {
             parse_value(value_str)
         };
 
-        let mut store = get_variable_store().lock().unwrap();
+        let store = &mut interpreter.variables;
 
         // Try as array first
         if let Ok(mut array) = store.get_array_data(collection_name) {
             let index: usize = key_str
                 .parse()
-                .map_err(|_| "Array index must be a non-negative integer".to_string())?;
+                .map_err(|_| KeyForgeError::type_error("Array index must be a non-negative integer"))?;
 
             if index < array.len() {
                 array[index] = parsed_value;
                 store.add_data_to_array(collection_name.to_string(), array);
                 Ok(String::new())
             } else {
-                Err(format!(
+                Err(KeyForgeError::runtime(format!(
                     "Index {} out of bounds for array '{}'",
                     index, collection_name
-                ))
+                )))
             }
         }
         // Try as dictionary
@@ -1062,29 +1118,6 @@ pub mod setters {
 
 }
 
-pub mod base64 {
-    
-    use base64::engine::general_purpose::STANDARD;
-    use base64::Engine as _;
-
-    pub fn encode_base64(input: &str) -> String {
-        STANDARD.encode(input.as_bytes())
-    }
-    
-    pub fn decode_base64(input: &str) -> Result<String, String> {
-        match STANDARD.decode(input) {
-            Ok(decoded_bytes) => {
-                match String::from_utf8(decoded_bytes) {
-                    Ok(decoded_string) => Ok(decoded_string),
-                    Err(_) => Err("invalid UTF-8 data".to_string())
-                }
-            }
-            Err(e) => Err(format!("Error decode Base64: {}", e))
-        }
-    }
-
-}
-
 pub mod utils {
     
     use std::io::Write;
@@ -1110,3 +1143,38 @@ pub mod utils {
         format!("{}{}{}", wrapper, s, wrapper)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_argument_mode_blocks_span_arguments() {
+        let mut interpreter = Interpreter::with_output(std::io::sink());
+        let block = args(&["set i 0", "set total 0", "while $i < 3 do {", "add total i", "add i 1", "}"]);
+        interpret_arguments_from_command_line(&mut interpreter, &block).unwrap();
+        assert_eq!(interpreter.variables().get_int_data("total"), Ok(3));
+    }
+
+    #[test]
+    fn test_argument_mode_errors_name_the_argument() {
+        let mut interpreter = Interpreter::with_output(std::io::sink());
+        let err = interpret_arguments_from_command_line(&mut interpreter, &args(&["set x 1", "add missing 1"]))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Error in argument 2:"), "{}", err);
+
+        let err = interpret_arguments_from_command_line(&mut interpreter, &args(&["set x 1", "if 1 == 1 then {", "add missing 1", "}"]))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Error in arguments 2-4:"), "{}", err);
+
+        // A syntax error anywhere stops everything from running
+        let err = interpret_arguments_from_command_line(&mut interpreter, &args(&["set y 1", "if 1 == then"]))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Error in argument 2:"), "{}", err);
+        assert!(!interpreter.variables().has_variable("y"));
+    }
+}
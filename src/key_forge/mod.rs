@@ -1,9 +1,25 @@
+pub mod arguments;
+pub mod ast;
+pub mod error;
 pub mod execute_command;
 pub mod help;
+pub mod interpreter;
+#[allow(clippy::module_inception)]
 pub mod key_forge;
 pub mod arithmetic;
 pub mod expression;
+pub mod parser;
+pub mod password;
+pub mod repl;
+pub mod hashing;
+pub mod identifiers;
+pub mod rng;
+pub mod tokens;
+pub mod encoding;
+pub mod json;
+pub mod state;
+pub mod vault;
 
 // Re-export the main functions
 pub use key_forge::interpret_arguments_from_command_line;
-pub use key_forge::input_mode;
\ No newline at end of file
+pub use key_forge::input_mode;
@@ -0,0 +1,1174 @@
+use std::rc::Rc;
+
+use super::ast::{
+    ArithOp, Block, CompareOp, Expr, ExprKind, FunctionDef, LogicalOp, ParseError, Script, Span, Statement,
+    StatementKind, Word,
+};
+use super::execute_command::BUILTIN_COMMANDS;
+use super::key_forge::is_valid_identifier;
+
+/// Words with a meaning to the parser, which cannot be used as function names.
+pub const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "while", "do", "for", "in", "step", "fn", "return", "and", "or",
+];
+
+#[derive(Debug, Clone)]
+enum Token {
+    /// `braces` is the net number of unquoted `{` minus `}` in the word, used to keep
+    /// multi-line dictionary literals inside a single command.
+    Word { word: Word, braces: i32 },
+    Separator { newline: bool, span: Span },
+    End(Span),
+}
+
+impl Token {
+    fn span(&self) -> Span {
+        match self {
+            Token::Word { word, .. } => word.span,
+            Token::Separator { span, .. } | Token::End(span) => *span,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word { word, .. } if word.is_keyword(keyword))
+    }
+}
+
+/// Parses script text (a file, a REPL line or the joined command line arguments) into a
+/// statement tree. Statements are separated by new lines or `;`.
+pub fn parse(source: &str) -> Result<Script, ParseError> {
+    let tokens = Lexer::new(source).tokenize()?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let statements = parser.parse_statements(false)?;
+    Ok(Script { statements })
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    /// Nesting depth of an array or dictionary literal such as `["a b", "c"]`, which may span
+    /// several words. Quotes inside a literal are kept so its elements stay strings.
+    literal_depth: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+            literal_depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|&(_, c)| c)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        self.chars.get(self.pos).map_or(self.source.len(), |&(i, _)| i)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn span_from(&self, start: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end: self.offset(),
+            line,
+            column,
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        // `//` only starts a comment where a statement could start
+        let mut statement_start = true;
+
+        while let Some(c) = self.peek() {
+            let (start, line, column) = (self.offset(), self.line, self.column);
+
+            if c == '\n' || c == ';' {
+                self.advance();
+                tokens.push(Token::Separator {
+                    newline: c == '\n',
+                    span: self.span_from(start, line, column),
+                });
+                statement_start = true;
+            } else if c.is_whitespace() {
+                self.advance();
+            } else if statement_start && c == '/' && self.peek_at(1) == Some('/') {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
+                }
+            } else {
+                let token = self.read_word()?;
+                statement_start = matches!(
+                    &token,
+                    Token::Word { word, .. }
+                        if ["{", "}", "then", "else", "do"].iter().any(|k| word.is_keyword(k))
+                );
+                tokens.push(token);
+            }
+        }
+
+        let end = self.span_from(self.offset(), self.line, self.column);
+        tokens.push(Token::End(end));
+        Ok(tokens)
+    }
+
+    fn read_word(&mut self) -> Result<Token, ParseError> {
+        let (start, line, column) = (self.offset(), self.line, self.column);
+        let mut text = String::new();
+        let mut quoted = false;
+        let mut braces = 0;
+
+        while let Some(c) = self.peek() {
+            match c {
+                c if c.is_whitespace() || c == ';' => break,
+                '"' | '\'' if self.literal_depth > 0 => self.read_quoted(&mut text, true)?,
+                '"' | '\'' => {
+                    quoted = true;
+                    self.read_quoted(&mut text, false)?;
+                }
+                '$' if self.peek_at(1) == Some('(') => self.read_substitution(&mut text)?,
+                _ => {
+                    if c == '{' {
+                        braces += 1;
+                    } else if c == '}' {
+                        braces -= 1;
+                    }
+                    // A lone `{` opens a block, `{a:` or `[1,` opens a literal
+                    let opens_literal = self.literal_depth > 0
+                        || (text.is_empty() && self.peek_at(1).is_some_and(|next| !next.is_whitespace()));
+                    if matches!(c, '[' | '{') && opens_literal {
+                        self.literal_depth += 1;
+                    } else if matches!(c, ']' | '}') && self.literal_depth > 0 {
+                        self.literal_depth -= 1;
+                    }
+                    text.push(c);
+                    self.advance();
+                }
+            }
+        }
+
+        Ok(Token::Word {
+            word: Word {
+                text,
+                quoted,
+                span: self.span_from(start, line, column),
+            },
+            braces,
+        })
+    }
+
+    /// Reads a quoted string starting at the opening quote. The quotes themselves are only
+    /// copied into `text` when `keep_quotes` is set (inside `$(...)`).
+    fn read_quoted(&mut self, text: &mut String, keep_quotes: bool) -> Result<(), ParseError> {
+        let (start, line, column) = (self.offset(), self.line, self.column);
+        let quote = self.advance().unwrap_or('"');
+        if keep_quotes {
+            text.push(quote);
+        }
+
+        loop {
+            match self.advance() {
+                Some(c) if c == quote => break,
+                Some(c) => text.push(c),
+                None => {
+                    return Err(ParseError {
+                        message: format!("Unterminated string, expected closing {}", quote),
+                        span: self.span_from(start, line, column),
+                    })
+                }
+            }
+        }
+
+        if keep_quotes {
+            text.push(quote);
+        }
+        Ok(())
+    }
+
+    /// Copies a `$(...)` command substitution verbatim, including nested parentheses and quotes.
+    fn read_substitution(&mut self, text: &mut String) -> Result<(), ParseError> {
+        let (start, line, column) = (self.offset(), self.line, self.column);
+        let mut depth = 0;
+
+        loop {
+            match self.peek() {
+                Some('"') | Some('\'') => self.read_quoted(text, true)?,
+                Some(c) => {
+                    self.advance();
+                    text.push(c);
+                    if c == '(' {
+                        depth += 1;
+                    } else if c == ')' {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+                None => {
+                    return Err(ParseError {
+                        message: "Unclosed command substitution, expected ')'".to_string(),
+                        span: self.span_from(start, line, column),
+                    })
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        // The token list always ends with Token::End, which is never consumed
+        &self.tokens[self.pos.min(self.tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if !matches!(token, Token::End(_)) {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn skip_separators(&mut self) {
+        while matches!(self.peek(), Token::Separator { .. }) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips new lines (but not `;`) when the first token after them is `keyword`.
+    fn skip_newlines_before(&mut self, keyword: &str) -> bool {
+        let mut i = self.pos;
+        while matches!(self.tokens[i], Token::Separator { newline: true, .. }) {
+            i += 1;
+        }
+        if self.tokens[i].is_keyword(keyword) {
+            self.pos = i;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_statements(&mut self, in_block: bool) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = Vec::new();
+
+        loop {
+            self.skip_separators();
+            match self.peek() {
+                Token::End(_) => break,
+                token if token.is_keyword("}") => {
+                    if in_block {
+                        break;
+                    }
+                    return Err(ParseError {
+                        message: "Unexpected '}' without a matching '{'".to_string(),
+                        span: token.span(),
+                    });
+                }
+                _ => {}
+            }
+
+            statements.push(self.parse_statement(false)?);
+
+            match self.peek() {
+                Token::Separator { .. } | Token::End(_) => {}
+                token if in_block && token.is_keyword("}") => {}
+                Token::Word { word, .. } => {
+                    return Err(ParseError {
+                        message: format!("Unexpected '{}', expected ';' or a new line", word.text),
+                        span: word.span,
+                    });
+                }
+            }
+        }
+
+        Ok(statements)
+    }
+
+    /// `stop_at_else` is set while parsing the inline body of an `if`, so that `else` ends the
+    /// body instead of being passed to the command as an argument.
+    fn parse_statement(&mut self, stop_at_else: bool) -> Result<Statement, ParseError> {
+        let token = self.peek();
+        if token.is_keyword("if") {
+            self.parse_if(stop_at_else)
+        } else if token.is_keyword("while") {
+            self.parse_while(stop_at_else)
+        } else if token.is_keyword("for") {
+            self.parse_for(stop_at_else)
+        } else if token.is_keyword("fn") {
+            self.parse_function()
+        } else if token.is_keyword("{") {
+            let block = self.parse_block()?;
+            Ok(Statement {
+                span: block.span,
+                kind: StatementKind::Block(block),
+            })
+        } else if token.is_keyword("return") {
+            let mut statement = self.parse_command(stop_at_else)?;
+            if let StatementKind::Command(words) = &mut statement.kind {
+                statement.kind = StatementKind::Return(words.split_off(1));
+            }
+            Ok(statement)
+        } else {
+            self.parse_command(stop_at_else)
+        }
+    }
+
+    fn parse_command(&mut self, stop_at_else: bool) -> Result<Statement, ParseError> {
+        let mut words: Vec<Word> = Vec::new();
+        let mut depth = 0;
+
+        loop {
+            match self.peek() {
+                Token::Word { word, braces } => {
+                    if depth == 0 && word.is_keyword("}") {
+                        break;
+                    }
+                    if stop_at_else && depth == 0 && !words.is_empty() && word.is_keyword("else") {
+                        break;
+                    }
+                    depth += braces;
+                    words.push(word.clone());
+                    self.pos += 1;
+                }
+                // An unbalanced '{' inside a command (e.g. a dictionary literal) continues it on
+                // the next line
+                Token::Separator { newline: true, .. } if depth > 0 => self.pos += 1,
+                Token::Separator { .. } | Token::End(_) => break,
+            }
+        }
+
+        let (first, last) = match (words.first(), words.last()) {
+            (Some(first), Some(last)) => (first.span, last.span),
+            _ => {
+                return Err(ParseError {
+                    message: "Expected a command".to_string(),
+                    span: self.peek().span(),
+                })
+            }
+        };
+
+        Ok(Statement {
+            kind: StatementKind::Command(words),
+            span: first.to(last),
+        })
+    }
+
+    fn parse_if(&mut self, stop_at_else: bool) -> Result<Statement, ParseError> {
+        let if_span = self.next().span();
+        let condition = self.parse_condition("if", "then", if_span)?;
+        self.expect_keyword("then", "Expected 'then' after condition", if_span)?;
+
+        let then_branch = self.parse_body("then", true)?;
+        let mut span = if_span.to(then_branch.span);
+
+        let else_branch = if self.skip_newlines_before("else") {
+            self.pos += 1;
+            let branch = self.parse_body("else", stop_at_else)?;
+            span = span.to(branch.span);
+            Some(branch)
+        } else {
+            None
+        };
+
+        Ok(Statement {
+            kind: StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            },
+            span,
+        })
+    }
+
+    fn parse_while(&mut self, stop_at_else: bool) -> Result<Statement, ParseError> {
+        let while_span = self.next().span();
+        let condition = self.parse_condition("while", "do", while_span)?;
+        self.expect_keyword("do", "Expected 'do' after condition", while_span)?;
+
+        let body = self.parse_body("do", stop_at_else)?;
+        Ok(Statement {
+            span: while_span.to(body.span),
+            kind: StatementKind::While { condition, body },
+        })
+    }
+
+    fn parse_for(&mut self, stop_at_else: bool) -> Result<Statement, ParseError> {
+        let for_span = self.next().span();
+
+        // `for key, value in` may be written with or without spaces around the comma
+        let mut names: Vec<Word> = Vec::new();
+        while let Token::Word { word, .. } = self.peek() {
+            if word.is_keyword("in") {
+                break;
+            }
+            names.push(word.clone());
+            self.pos += 1;
+        }
+        let names_span = match (names.first(), names.last()) {
+            (Some(first), Some(last)) => first.span.to(last.span),
+            _ => {
+                return Err(ParseError {
+                    message: "Expected a variable name after 'for'".to_string(),
+                    span: self.error_span(for_span),
+                })
+            }
+        };
+        let joined: String = names.iter().map(|word| word.text.as_str()).collect();
+        let variables: Vec<String> = joined.split(',').map(|name| name.trim().to_string()).collect();
+        if variables.len() > 2 || variables.iter().any(|name| !is_valid_identifier(name)) {
+            return Err(ParseError {
+                message: "Expected 'for <name> in' or 'for <key>, <value> in'".to_string(),
+                span: names_span,
+            });
+        }
+
+        self.expect_keyword("in", "Expected 'in' after variable name", names_span)?;
+        let iterable = self.expect_word("Expected a range or value after 'in'", names_span)?;
+
+        let step = if self.peek().is_keyword("step") {
+            let step_span = self.next().span();
+            Some(self.expect_word("Expected a step size after 'step'", step_span)?)
+        } else {
+            None
+        };
+
+        // `do` is optional before a block: `for i in 0..3 { ... }`
+        let keyword = if self.peek().is_keyword("do") {
+            self.pos += 1;
+            "do"
+        } else {
+            "in"
+        };
+
+        let body = self.parse_body(keyword, stop_at_else)?;
+        Ok(Statement {
+            span: for_span.to(body.span),
+            kind: StatementKind::For {
+                variables,
+                iterable,
+                step,
+                body,
+            },
+        })
+    }
+
+    fn parse_function(&mut self) -> Result<Statement, ParseError> {
+        let fn_span = self.next().span();
+
+        let mut signature: Vec<Word> = Vec::new();
+        while let Token::Word { word, .. } = self.peek() {
+            if word.is_keyword("{") {
+                break;
+            }
+            signature.push(word.clone());
+            self.pos += 1;
+        }
+
+        let (first, last) = match (signature.first(), signature.last()) {
+            (Some(first), Some(last)) => (first.span, last.span),
+            _ => {
+                return Err(ParseError {
+                    message: "Expected a function name after 'fn'".to_string(),
+                    span: fn_span,
+                })
+            }
+        };
+        let text: Vec<&str> = signature.iter().map(|word| word.text.as_str()).collect();
+        let (name, params) = parse_signature(&text.join(" ")).map_err(|message| ParseError {
+            message,
+            span: first.to(last),
+        })?;
+
+        if !self.peek().is_keyword("{") {
+            return Err(ParseError {
+                message: "Expected '{' after the parameter list".to_string(),
+                span: self.error_span(last),
+            });
+        }
+        let body = self.parse_block()?;
+        let span = fn_span.to(body.span);
+
+        Ok(Statement {
+            kind: StatementKind::Function(Rc::new(FunctionDef {
+                name,
+                params,
+                body,
+                span,
+            })),
+            span,
+        })
+    }
+
+    /// Parses either a `{ ... }` block or a single inline statement.
+    fn parse_body(&mut self, after: &str, stop_at_else: bool) -> Result<Block, ParseError> {
+        // Allow the body to start on the line after `then`/`else`/`do`
+        if matches!(self.peek(), Token::Separator { newline: true, .. }) {
+            let mut i = self.pos;
+            while matches!(self.tokens[i], Token::Separator { newline: true, .. }) {
+                i += 1;
+            }
+            if matches!(self.tokens[i], Token::Word { .. }) {
+                self.pos = i;
+            }
+        }
+
+        match self.peek() {
+            token if token.is_keyword("{") => self.parse_block(),
+            Token::Word { .. } => {
+                let statement = self.parse_statement(stop_at_else)?;
+                Ok(Block {
+                    span: statement.span,
+                    statements: vec![statement],
+                    scoped: false,
+                })
+            }
+            token => Err(ParseError {
+                message: format!("Expected a command or block after '{}'", after),
+                span: token.span(),
+            }),
+        }
+    }
+
+    fn parse_block(&mut self) -> Result<Block, ParseError> {
+        let open = self.next().span();
+        let statements = self.parse_statements(true)?;
+
+        if !self.peek().is_keyword("}") {
+            return Err(ParseError {
+                message: "Unclosed block, expected '}'".to_string(),
+                span: open,
+            });
+        }
+        let close = self.next().span();
+
+        Ok(Block {
+            statements,
+            span: open.to(close),
+            scoped: true,
+        })
+    }
+
+    fn expect_keyword(&mut self, keyword: &str, message: &str, fallback: Span) -> Result<Span, ParseError> {
+        if self.peek().is_keyword(keyword) {
+            return Ok(self.next().span());
+        }
+        Err(ParseError {
+            message: message.to_string(),
+            span: self.error_span(fallback),
+        })
+    }
+
+    fn expect_word(&mut self, message: &str, fallback: Span) -> Result<Word, ParseError> {
+        match self.next() {
+            Token::Word { word, .. } => Ok(word),
+            _ => Err(ParseError {
+                message: message.to_string(),
+                span: fallback,
+            }),
+        }
+    }
+
+    /// Points errors at the current word, or at `fallback` when the statement ended early.
+    fn error_span(&self, fallback: Span) -> Span {
+        match self.peek() {
+            Token::Word { word, .. } => word.span,
+            _ => fallback,
+        }
+    }
+
+    /// Parses the words up to `terminator` as comparisons (or single values) joined by `and`/`or`.
+    /// Both connectives have the same precedence and are applied left to right.
+    fn parse_condition(&mut self, keyword: &str, terminator: &str, keyword_span: Span) -> Result<Expr, ParseError> {
+        let mut words = Vec::new();
+        while let Token::Word { word, .. } = self.peek() {
+            if word.is_keyword(terminator) {
+                break;
+            }
+            words.push(word.clone());
+            self.pos += 1;
+        }
+
+        if words.is_empty() {
+            return Err(ParseError {
+                message: format!("Expected a condition after '{}'", keyword),
+                span: self.error_span(keyword_span),
+            });
+        }
+
+        let mut expr: Option<Expr> = None;
+        let mut pending: Option<LogicalOp> = None;
+        let mut segment: Vec<Word> = Vec::new();
+        let mut words = words.into_iter();
+
+        loop {
+            let word = words.next();
+            let connective = word.as_ref().and_then(logical_op);
+            let word = match (word, connective) {
+                (Some(word), None) => {
+                    segment.push(word);
+                    continue;
+                }
+                (word, _) => word,
+            };
+
+            if segment.is_empty() {
+                return Err(ParseError {
+                    message: "Missing comparison next to 'and'/'or'".to_string(),
+                    span: word.map_or(keyword_span, |w| w.span),
+                });
+            }
+
+            let comparison = parse_comparison(std::mem::take(&mut segment))?;
+            let combined = match (expr.take(), pending) {
+                (Some(left), Some(op)) => Expr {
+                    span: left.span.to(comparison.span),
+                    kind: ExprKind::Logical {
+                        op,
+                        left: Box::new(left),
+                        right: Box::new(comparison),
+                    },
+                },
+                _ => comparison,
+            };
+
+            pending = connective;
+            if pending.is_none() {
+                return Ok(combined);
+            }
+            expr = Some(combined);
+        }
+    }
+}
+
+fn logical_op(word: &Word) -> Option<LogicalOp> {
+    if word.is_keyword("and") {
+        Some(LogicalOp::And)
+    } else if word.is_keyword("or") {
+        Some(LogicalOp::Or)
+    } else {
+        None
+    }
+}
+
+/// Splits `name(a, b)` into the function name and its parameter names.
+fn parse_signature(signature: &str) -> Result<(String, Vec<String>), String> {
+    let (name, rest) = signature
+        .split_once('(')
+        .ok_or("Expected a parameter list, e.g. fn name(a, b)")?;
+    let params_text = rest
+        .trim_end()
+        .strip_suffix(')')
+        .ok_or("Expected ')' to close the parameter list")?;
+
+    let name = name.trim();
+    if !is_valid_identifier(name) {
+        return Err(format!("Invalid function name '{}'", name));
+    }
+    if KEYWORDS.contains(&name) || BUILTIN_COMMANDS.contains(&name) {
+        return Err(format!("'{}' is a keyword or built-in command and cannot be redefined", name));
+    }
+
+    let mut params: Vec<String> = Vec::new();
+    if !params_text.trim().is_empty() {
+        for param in params_text.split(',').map(str::trim) {
+            if !is_valid_identifier(param) {
+                return Err(format!("Invalid parameter name '{}'", param));
+            }
+            if params.iter().any(|existing| existing == param) {
+                return Err(format!("Duplicate parameter '{}'", param));
+            }
+            params.push(param.to_string());
+        }
+    }
+
+    Ok((name.to_string(), params))
+}
+
+/// Turns a comparison operand into an arithmetic expression when it looks like one (see
+/// `parse_arithmetic`), otherwise keeps the words for plain string resolution.
+fn operand_expr(words: Vec<Word>) -> Expr {
+    if words.iter().all(|word| !word.quoted) {
+        let text: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+        if let Some(expr) = parse_arithmetic_at(&text.join(" "), words[0].span) {
+            return expr;
+        }
+    }
+    words_expr(words)
+}
+
+fn words_expr(words: Vec<Word>) -> Expr {
+    let span = match (words.first(), words.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span::default(),
+    };
+    Expr {
+        kind: ExprKind::Words(words),
+        span,
+    }
+}
+
+fn parse_comparison(words: Vec<Word>) -> Result<Expr, ParseError> {
+    let span = words[0].span.to(words[words.len() - 1].span);
+    let position = words
+        .iter()
+        .position(|w| !w.quoted && CompareOp::from_symbol(&w.text).is_some());
+
+    let Some(index) = position else {
+        // A lone value is tested for truthiness: `if $enabled then ...`
+        if words.len() == 1 {
+            return Ok(words_expr(words));
+        }
+        return Err(ParseError {
+            message: "Expected a comparison operator (==, !=, <, >, <=, >=, eq, ne, lt, gt, le, ge)".to_string(),
+            span,
+        });
+    };
+
+    let op = CompareOp::from_symbol(&words[index].text).expect("checked above");
+    let op_span = words[index].span;
+    let mut left = words;
+    let right = left.split_off(index + 1);
+    left.pop();
+
+    if left.is_empty() || right.is_empty() {
+        return Err(ParseError {
+            message: "Comparison needs a value on both sides of the operator".to_string(),
+            span: op_span,
+        });
+    }
+
+    Ok(Expr {
+        kind: ExprKind::Compare {
+            op,
+            left: Box::new(operand_expr(left)),
+            right: Box::new(operand_expr(right)),
+        },
+        span,
+    })
+}
+
+/// Parses `source` as an arithmetic expression (`+ - * / % **`, parentheses, unary minus,
+/// numbers, `$name` and `$(command)`). Returns `None` when the text is not valid arithmetic or
+/// does not look like a calculation: operators between plain numbers only count when the text
+/// contains spaces or parentheses, so values such as `2024-01-05` stay strings.
+pub fn parse_arithmetic(source: &str) -> Option<Expr> {
+    parse_arithmetic_at(
+        source,
+        Span {
+            start: 0,
+            end: source.len(),
+            line: 1,
+            column: 1,
+        },
+    )
+}
+
+fn parse_arithmetic_at(source: &str, base: Span) -> Option<Expr> {
+    let tokens = lex_arithmetic(source, base)?;
+    let mut parser = ArithParser { tokens, pos: 0 };
+    let expr = parser.parse_sum()?;
+    if parser.pos != parser.tokens.len() {
+        return None;
+    }
+
+    let spaced = source.contains(|c: char| c.is_whitespace() || c == '(');
+    if expr.has_operator() && (spaced || expr.has_reference()) {
+        Some(expr)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ArithToken {
+    Int(i64),
+    Float(f64),
+    Variable(String),
+    Substitution(String),
+    Op(ArithOp),
+    Open,
+    Close,
+}
+
+fn lex_arithmetic(source: &str, base: Span) -> Option<Vec<(ArithToken, Span)>> {
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let begin = i;
+
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                ArithToken::Open
+            }
+            ')' => {
+                i += 1;
+                ArithToken::Close
+            }
+            '+' | '-' | '/' | '%' => {
+                i += 1;
+                ArithToken::Op(match c {
+                    '+' => ArithOp::Add,
+                    '-' => ArithOp::Sub,
+                    '/' => ArithOp::Div,
+                    _ => ArithOp::Rem,
+                })
+            }
+            '*' => {
+                if chars.get(i + 1).is_some_and(|&(_, c)| c == '*') {
+                    i += 2;
+                    ArithToken::Op(ArithOp::Pow)
+                } else {
+                    i += 1;
+                    ArithToken::Op(ArithOp::Mul)
+                }
+            }
+            '$' if chars.get(i + 1).is_some_and(|&(_, c)| c == '(') => {
+                let mut depth = 0;
+                let mut quote: Option<char> = None;
+                let mut j = i + 1;
+                loop {
+                    let &(_, c) = chars.get(j)?;
+                    match (quote, c) {
+                        (Some(q), c) if c == q => quote = None,
+                        (Some(_), _) => {}
+                        (None, '"') | (None, '\'') => quote = Some(c),
+                        (None, '(') => depth += 1,
+                        (None, ')') => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    j += 1;
+                }
+                let inner: String = chars[i + 2..j].iter().map(|&(_, c)| c).collect();
+                i = j + 1;
+                ArithToken::Substitution(inner)
+            }
+            '$' => {
+                let mut j = i + 1;
+                while chars.get(j).is_some_and(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    j += 1;
+                }
+                let name: String = chars[i + 1..j].iter().map(|&(_, c)| c).collect();
+                if !is_valid_identifier(&name) {
+                    return None;
+                }
+                i = j;
+                ArithToken::Variable(name)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut j = i;
+                while chars.get(j).is_some_and(|&(_, c)| c.is_ascii_digit() || c == '.') {
+                    j += 1;
+                }
+                // `2x` or `1.2.3` are words, not numbers
+                if chars.get(j).is_some_and(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    return None;
+                }
+                let text: String = chars[i..j].iter().map(|&(_, c)| c).collect();
+                i = j;
+                if text.contains('.') {
+                    ArithToken::Float(text.parse().ok()?)
+                } else {
+                    ArithToken::Int(text.parse().ok()?)
+                }
+            }
+            _ => return None,
+        };
+
+        let end = chars.get(i).map_or(source.len(), |&(offset, _)| offset);
+        tokens.push((
+            token,
+            Span {
+                start: base.start + start,
+                end: base.start + end,
+                line: base.line,
+                column: base.column + begin,
+            },
+        ));
+    }
+
+    Some(tokens)
+}
+
+/// Recursive descent over arithmetic tokens. Precedence from low to high: `+ -`, `* / %`,
+/// unary minus, `**` (right associative, so `-2 ** 2` is -4 and `2 ** 3 ** 2` is 512).
+struct ArithParser {
+    tokens: Vec<(ArithToken, Span)>,
+    pos: usize,
+}
+
+impl ArithParser {
+    fn peek_op(&self) -> Option<ArithOp> {
+        match self.tokens.get(self.pos) {
+            Some((ArithToken::Op(op), _)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn binary(op: ArithOp, left: Expr, right: Expr) -> Expr {
+        Expr {
+            span: left.span.to(right.span),
+            kind: ExprKind::Arithmetic {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+        }
+    }
+
+    fn parse_sum(&mut self) -> Option<Expr> {
+        let mut left = self.parse_product()?;
+        while let Some(op @ (ArithOp::Add | ArithOp::Sub)) = self.peek_op() {
+            self.pos += 1;
+            let right = self.parse_product()?;
+            left = Self::binary(op, left, right);
+        }
+        Some(left)
+    }
+
+    fn parse_product(&mut self) -> Option<Expr> {
+        let mut left = self.parse_unary()?;
+        while let Some(op @ (ArithOp::Mul | ArithOp::Div | ArithOp::Rem)) = self.peek_op() {
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Self::binary(op, left, right);
+        }
+        Some(left)
+    }
+
+    fn parse_unary(&mut self) -> Option<Expr> {
+        match self.peek_op() {
+            Some(ArithOp::Sub) => {
+                let span = self.tokens[self.pos].1;
+                self.pos += 1;
+                let operand = self.parse_unary()?;
+                Some(Expr {
+                    span: span.to(operand.span),
+                    kind: ExprKind::Negate(Box::new(operand)),
+                })
+            }
+            Some(ArithOp::Add) => {
+                self.pos += 1;
+                self.parse_unary()
+            }
+            _ => self.parse_power(),
+        }
+    }
+
+    fn parse_power(&mut self) -> Option<Expr> {
+        let base = self.parse_primary()?;
+        if self.peek_op() == Some(ArithOp::Pow) {
+            self.pos += 1;
+            let exponent = self.parse_unary()?;
+            return Some(Self::binary(ArithOp::Pow, base, exponent));
+        }
+        Some(base)
+    }
+
+    fn parse_primary(&mut self) -> Option<Expr> {
+        let (token, span) = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+
+        let kind = match token {
+            ArithToken::Int(v) => ExprKind::Int(v),
+            ArithToken::Float(v) => ExprKind::Float(v),
+            ArithToken::Variable(name) => ExprKind::Variable(name),
+            ArithToken::Substitution(command) => ExprKind::Substitution(command),
+            ArithToken::Open => {
+                let inner = self.parse_sum()?;
+                match self.tokens.get(self.pos) {
+                    Some((ArithToken::Close, close)) => {
+                        let close = *close;
+                        self.pos += 1;
+                        // Parentheses only group; keep the inner node with the wider span
+                        return Some(Expr {
+                            span: span.to(close),
+                            kind: inner.kind,
+                        });
+                    }
+                    _ => return None,
+                }
+            }
+            ArithToken::Op(_) | ArithToken::Close => return None,
+        };
+
+        Some(Expr { kind, span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_texts(statement: &Statement) -> Vec<&str> {
+        match &statement.kind {
+            StatementKind::Command(words) => words.iter().map(|w| w.text.as_str()).collect(),
+            other => panic!("expected a command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_quoted_keywords_and_braces_are_plain_words() {
+        let script = parse("if $msg == \"then\" then print \"{ else }\" else print no").unwrap();
+        let StatementKind::If { condition, then_branch, else_branch } = &script.statements[0].kind else {
+            panic!("expected if");
+        };
+
+        let ExprKind::Compare { op, right, .. } = &condition.kind else {
+            panic!("expected comparison");
+        };
+        assert_eq!(*op, CompareOp::Eq);
+        assert!(matches!(&right.kind, ExprKind::Words(w) if w[0].text == "then" && w[0].quoted));
+        assert_eq!(command_texts(&then_branch.statements[0]), ["print", "{ else }"]);
+        assert_eq!(command_texts(&else_branch.as_ref().unwrap().statements[0]), ["print", "no"]);
+    }
+
+    #[test]
+    fn test_nested_blocks_and_spans() {
+        let source = "for i in 0..3 do {\n  if $i == 1 then {\n    print one\n  } else {\n    print other\n  }\n}\nprint $(len \"a b\")";
+        let script = parse(source).unwrap();
+        assert_eq!(script.statements.len(), 2);
+
+        let StatementKind::For { body, .. } = &script.statements[0].kind else {
+            panic!("expected for");
+        };
+        let nested = &body.statements[0];
+        assert_eq!((nested.span.line, nested.span.column), (2, 3));
+        let StatementKind::If { else_branch: Some(else_branch), .. } = &nested.kind else {
+            panic!("expected if/else");
+        };
+        assert_eq!(else_branch.statements[0].span.line, 5);
+
+        assert_eq!(command_texts(&script.statements[1]), ["print", "$(len \"a b\")"]);
+    }
+
+    #[test]
+    fn test_dangling_else_binds_to_inner_if() {
+        let script = parse("if $a == 1 then if $b == 2 then print x else print y").unwrap();
+        let StatementKind::If { then_branch, else_branch, .. } = &script.statements[0].kind else {
+            panic!("expected if");
+        };
+        assert!(else_branch.is_none());
+        assert!(matches!(
+            &then_branch.statements[0].kind,
+            StatementKind::If { else_branch: Some(_), .. }
+        ));
+    }
+
+    #[test]
+    fn test_for_headers_and_literal_quotes() {
+        let script = parse("for key,value in $info step 2 do print $key\nfor k , v in $d { }").unwrap();
+        for statement in &script.statements {
+            let StatementKind::For { variables, .. } = &statement.kind else {
+                panic!("expected for");
+            };
+            assert_eq!(variables.len(), 2);
+        }
+        let StatementKind::For { iterable, step, .. } = &script.statements[0].kind else {
+            unreachable!()
+        };
+        assert_eq!(iterable.text, "$info");
+        assert_eq!(step.as_ref().map(|word| word.text.as_str()), Some("2"));
+        assert!(parse("for a, b, c in $x do print $a").is_err());
+
+        // Quotes inside an array or dictionary literal are kept, a quoted literal is plain text
+        let script = parse("set info {name: \"Bob Smith\"}; set s \"[a, b]\"").unwrap();
+        assert_eq!(command_texts(&script.statements[0]), ["set", "info", "{name:", "\"Bob Smith\"}"]);
+        assert_eq!(command_texts(&script.statements[1]), ["set", "s", "[a, b]"]);
+    }
+
+    #[test]
+    fn test_function_definition() {
+        let script = parse("fn sum (a, b) {\n  return $a\n}").unwrap();
+        let StatementKind::Function(function) = &script.statements[0].kind else {
+            panic!("expected fn");
+        };
+        assert_eq!(function.name, "sum");
+        assert_eq!(function.params, ["a", "b"]);
+        assert!(matches!(&function.body.statements[0].kind, StatementKind::Return(words) if words[0].text == "$a"));
+
+        assert!(parse("fn print(x) { }").is_err());
+        assert!(parse("fn f(a, a) { }").is_err());
+        assert!(parse("fn f() print hi").is_err());
+    }
+
+    #[test]
+    fn test_arithmetic_precedence_and_detection() {
+        let expr = parse_arithmetic("($x * 2 + $y) / 3").unwrap();
+        let ExprKind::Arithmetic { op: ArithOp::Div, left, .. } = &expr.kind else {
+            panic!("expected division at the top, got {:?}", expr.kind);
+        };
+        assert!(matches!(left.kind, ExprKind::Arithmetic { op: ArithOp::Add, .. }));
+
+        let expr = parse_arithmetic("-2 ** 2").unwrap();
+        assert!(matches!(&expr.kind, ExprKind::Negate(inner) if matches!(inner.kind, ExprKind::Arithmetic { op: ArithOp::Pow, .. })));
+
+        assert!(parse_arithmetic("2024-01-05").is_none());
+        assert!(parse_arithmetic("-5").is_none());
+        assert!(parse_arithmetic("$name").is_none());
+        assert!(parse_arithmetic("hello - world").is_none());
+        assert!(parse_arithmetic("$x-1").is_some());
+
+        let script = parse("if $a + 1 > $b then print yes").unwrap();
+        let StatementKind::If { condition, .. } = &script.statements[0].kind else {
+            panic!("expected if");
+        };
+        assert!(matches!(&condition.kind, ExprKind::Compare { left, .. } if left.has_operator()));
+    }
+
+    #[test]
+    fn test_errors_point_at_source() {
+        let err = parse("set x 1\nwhile $x < 3 do {\n  add x 1\n").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 17));
+        assert!(err.message.contains("Unclosed block"));
+
+        let err = parse("if $x 3 then print hi").unwrap_err();
+        assert!(err.message.contains("comparison operator"));
+
+        let err = parse("print \"unterminated").unwrap_err();
+        assert_eq!(err.span.column, 7);
+    }
+}
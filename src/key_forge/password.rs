@@ -0,0 +1,201 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>/?~|";
+// Characters that are easy to confuse when a password is read or typed by hand
+const AMBIGUOUS: &str = "0O1lI|";
+
+pub const DEFAULT_LENGTH: usize = 16;
+/// Longest password `gen_password` makes, so a typo in the length cannot exhaust memory.
+pub const MAX_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct PasswordOptions {
+    pub length: usize,
+    pub lower: bool,
+    pub upper: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub exclude_ambiguous: bool,
+    pub min_per_class: usize,
+    pub alphabet: Option<String>,
+}
+
+impl Default for PasswordOptions {
+    fn default() -> Self {
+        Self {
+            length: DEFAULT_LENGTH,
+            lower: false,
+            upper: false,
+            digits: false,
+            symbols: false,
+            exclude_ambiguous: false,
+            min_per_class: 0,
+            alphabet: None,
+        }
+    }
+}
+
+impl PasswordOptions {
+    /// Parses `gen_password` arguments: `[length] [--lower] [--upper] [--digits] [--symbols]
+    /// [--no-ambiguous] [--min <n>] [--alphabet <chars>]`. Without any class flag all four
+    /// classes are used.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut i = 0;
+
+        while i < args.len() {
+            match args[i].as_str() {
+                "--lower" => options.lower = true,
+                "--upper" => options.upper = true,
+                "--digits" => options.digits = true,
+                "--symbols" => options.symbols = true,
+                "--no-ambiguous" => options.exclude_ambiguous = true,
+                "--min" => {
+                    i += 1;
+                    let raw = args.get(i).ok_or("Option --min requires a number")?;
+                    options.min_per_class = raw
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid value for --min: '{}'", raw))?;
+                }
+                "--alphabet" => {
+                    i += 1;
+                    let raw = args.get(i).ok_or("Option --alphabet requires a value")?;
+                    options.alphabet = Some(raw.clone());
+                }
+                other if other.starts_with("--") => {
+                    return Err(format!("Unknown option for gen_password: {}", other));
+                }
+                other => {
+                    options.length = other
+                        .parse::<usize>()
+                        .map_err(|_| format!("Password length must be a positive integer, got '{}'", other))?;
+                }
+            }
+            i += 1;
+        }
+
+        if !(options.lower || options.upper || options.digits || options.symbols) {
+            options.lower = true;
+            options.upper = true;
+            options.digits = true;
+            options.symbols = true;
+        }
+
+        Ok(options)
+    }
+
+    fn character_classes(&self) -> Result<Vec<Vec<char>>, String> {
+        let keep = |c: &char| !(self.exclude_ambiguous && AMBIGUOUS.contains(*c));
+
+        if let Some(alphabet) = &self.alphabet {
+            let mut chars: Vec<char> = Vec::new();
+            for c in alphabet.chars().filter(keep) {
+                if !chars.contains(&c) {
+                    chars.push(c);
+                }
+            }
+            if chars.is_empty() {
+                return Err("Custom alphabet is empty".to_string());
+            }
+            return Ok(vec![chars]);
+        }
+
+        let enabled = [
+            (self.lower, LOWER),
+            (self.upper, UPPER),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ];
+
+        Ok(enabled
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, set)| set.chars().filter(keep).collect())
+            .collect())
+    }
+}
+
+/// Generates a password from `options`. At least `min_per_class` characters are drawn from each
+/// enabled class (the custom alphabet counts as a single class), the rest from the union of all
+/// classes, and the result is shuffled so the guaranteed characters have no fixed position.
+/// The password is only as unpredictable as `rng`, so pass a `CryptoRng` for real credentials.
+pub fn generate_password<R: Rng>(options: &PasswordOptions, rng: &mut R) -> Result<String, String> {
+    if options.length == 0 || options.length > MAX_LENGTH {
+        return Err(format!("Password length must be between 1 and {}", MAX_LENGTH));
+    }
+
+    let classes = options.character_classes()?;
+    let required = options.min_per_class.checked_mul(classes.len());
+    if required.is_none_or(|required| required > options.length) {
+        return Err(format!(
+            "Length {} is too short to hold {} characters from each of {} classes",
+            options.length,
+            options.min_per_class,
+            classes.len()
+        ));
+    }
+
+    let mut password: Vec<char> = Vec::with_capacity(options.length);
+    for class in &classes {
+        for _ in 0..options.min_per_class {
+            password.push(class[rng.gen_range(0..class.len())]);
+        }
+    }
+
+    let pool: Vec<char> = classes.concat();
+    while password.len() < options.length {
+        password.push(pool[rng.gen_range(0..pool.len())]);
+    }
+
+    password.shuffle(rng);
+    Ok(password.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_min_per_class_is_respected() {
+        let options = PasswordOptions::from_args(&args(&["12", "--digits", "--symbols", "--min", "5"])).unwrap();
+        for _ in 0..50 {
+            let password = generate_password(&options, &mut OsRng).unwrap();
+            assert_eq!(password.chars().count(), 12);
+            assert!(password.chars().filter(|c| DIGITS.contains(*c)).count() >= 5);
+            assert!(password.chars().filter(|c| SYMBOLS.contains(*c)).count() >= 5);
+        }
+    }
+
+    #[test]
+    fn test_alphabet_and_ambiguous_filter() {
+        let options = PasswordOptions::from_args(&args(&["32", "--alphabet", "01lab", "--no-ambiguous"])).unwrap();
+        let password = generate_password(&options, &mut OsRng).unwrap();
+        assert!(password.chars().all(|c| c == 'a' || c == 'b'));
+
+        let too_short = PasswordOptions::from_args(&args(&["3", "--min", "1"])).unwrap();
+        assert!(generate_password(&too_short, &mut OsRng).is_err());
+    }
+
+    #[test]
+    fn test_length_and_min_limits() {
+        let huge_min = PasswordOptions::from_args(&args(&["8", "--min", "9223372036854775807"])).unwrap();
+        assert!(generate_password(&huge_min, &mut OsRng).unwrap_err().contains("too short"));
+
+        let too_long = PasswordOptions::from_args(&args(&["4097"])).unwrap();
+        assert_eq!(
+            generate_password(&too_long, &mut OsRng).unwrap_err(),
+            "Password length must be between 1 and 4096"
+        );
+        let longest = PasswordOptions::from_args(&args(&["4096"])).unwrap();
+        assert_eq!(generate_password(&longest, &mut OsRng).unwrap().chars().count(), MAX_LENGTH);
+    }
+}
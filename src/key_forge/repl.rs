@@ -0,0 +1,676 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use colored::Colorize;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::History;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use super::ast::StatementKind;
+use super::error::KeyForgeError;
+use super::execute_command::BUILTIN_COMMANDS;
+use super::interpreter::Interpreter;
+use super::key_forge::{resolve_filename, ParsedValue};
+use super::parser::{self, KEYWORDS};
+
+/// Entries kept in the history file.
+pub const HISTORY_SIZE: usize = 1000;
+
+/// Commands that only exist at the REPL prompt.
+const META_COMMANDS: &[&str] = &[":history", ":load", ":reset", ":save-session", ":time"];
+
+/// Commands whose first argument names a variable.
+const VARIABLE_COMMANDS: &[&str] = &[
+    "add", "div", "get", "keys", "len", "mul", "pop", "push", "rm", "set", "sub", "type_of", "values",
+];
+
+/// Commands whose first argument is a path. `save_state` and `load_state` take flags first.
+const PATH_COMMANDS: &[&str] = &["execute_file", "load_state", "read_file", "save_state", "to_file", "write_file"];
+
+/// Words after which a new command starts within the same statement.
+const COMMAND_SEPARATORS: &[&str] = &["then", "do", "else"];
+
+/// What the word under the cursor should be completed to.
+#[derive(Debug, PartialEq, Eq)]
+enum Completion<'l> {
+    Command,
+    /// A `$name` reference
+    Reference,
+    /// A bare variable name
+    Variable,
+    /// A key of the dictionary with this name
+    DictKey(&'l str),
+    Path,
+    MetaCommand,
+    None,
+}
+
+/// Start of the word under the cursor and what it should be completed to.
+fn completion_context(line: &str, pos: usize) -> (usize, Completion<'_>) {
+    let before = &line[..pos];
+    let word_start = before.rfind(|c: char| c.is_whitespace() || "(;{}".contains(c)).map_or(0, |i| i + 1);
+    let word = &before[word_start..];
+    if word.starts_with('$') {
+        return (word_start, Completion::Reference);
+    }
+    if let Some(rest) = before.strip_prefix(':') {
+        match rest.split_once(char::is_whitespace) {
+            None => return (0, Completion::MetaCommand),
+            Some(("load" | "save-session", _)) => return (word_start, Completion::Path),
+            // `:time` is followed by a regular statement
+            _ => {}
+        }
+    }
+
+    // Only the current statement matters: a `$(`, block brace or `;` starts a new one
+    let statement_start = before[..word_start].rfind(|c: char| "(;{}".contains(c)).map_or(0, |i| i + 1);
+    let mut command = None;
+    let mut arguments = Vec::new();
+    for word in before[statement_start..word_start].split_whitespace() {
+        if COMMAND_SEPARATORS.contains(&word) || word == ":time" {
+            command = None;
+        } else if command.is_none() {
+            command = Some(word);
+            arguments.clear();
+        } else {
+            arguments.push(word);
+        }
+    }
+
+    let Some(command) = command else {
+        return (word_start, Completion::Command);
+    };
+    let completion = match (command, arguments.as_slice()) {
+        ("rm", _) => Completion::Variable,
+        ("get", [dict]) => Completion::DictKey(dict),
+        (command, []) if VARIABLE_COMMANDS.contains(&command) => Completion::Variable,
+        ("save_state" | "load_state", arguments) if !word.starts_with('-') => {
+            // `--format` takes a value; every other word may be the file
+            if arguments.last() == Some(&"--format") {
+                Completion::None
+            } else {
+                Completion::Path
+            }
+        }
+        (command, []) if PATH_COMMANDS.contains(&command) => Completion::Path,
+        _ => Completion::None,
+    };
+    (word_start, completion)
+}
+
+/// How a piece of REPL input is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Plain,
+    Command,
+    Keyword,
+    String,
+    Number,
+    Variable,
+    Comment,
+    /// A bracket that closes nothing or closes the wrong kind of bracket
+    Unbalanced,
+}
+
+impl Style {
+    fn paint(self, text: &str) -> String {
+        match self {
+            Self::Plain => text.to_string(),
+            Self::Command => text.blue().bold().to_string(),
+            Self::Keyword => text.magenta().bold().to_string(),
+            Self::String => text.green().to_string(),
+            Self::Number => text.yellow().to_string(),
+            Self::Variable => text.cyan().to_string(),
+            Self::Comment => text.dimmed().to_string(),
+            Self::Unbalanced => text.white().on_red().bold().to_string(),
+        }
+    }
+}
+
+/// Splits `source` into styled byte ranges, following the lexer closely enough for coloring:
+/// quotes have no escapes, `//` starts a comment where a statement could start, and `$(`, `(`,
+/// `[` and `{` must be closed by their own kind of bracket. `is_command` tells which words are
+/// commands when they start a statement.
+fn styled_ranges(source: &str, is_command: impl Fn(&str) -> bool) -> Vec<(usize, usize, Style)> {
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let end_of = |i: usize| chars.get(i).map_or(source.len(), |&(offset, _)| offset);
+    let mut ranges = Vec::new();
+    let mut open: Vec<(usize, char)> = Vec::new();
+    let mut statement_start = true;
+    let mut i = 0;
+
+    while let Some(&(start, c)) = chars.get(i) {
+        match c {
+            '\n' | ';' => {
+                statement_start = true;
+                i += 1;
+            }
+            c if c.is_whitespace() => i += 1,
+            '/' if statement_start && chars.get(i + 1).is_some_and(|&(_, next)| next == '/') => {
+                while chars.get(i).is_some_and(|&(_, c)| c != '\n') {
+                    i += 1;
+                }
+                ranges.push((start, end_of(i), Style::Comment));
+            }
+            '"' | '\'' => {
+                i += 1;
+                while chars.get(i).is_some_and(|&(_, next)| next != c) {
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+                ranges.push((start, end_of(i), Style::String));
+                statement_start = false;
+            }
+            '$' if chars.get(i + 1).is_some_and(|&(_, next)| next == '(') => {
+                open.push((start, '('));
+                i += 2;
+                ranges.push((start, end_of(i), Style::Variable));
+                statement_start = true;
+            }
+            '$' => {
+                i += 1;
+                while chars.get(i).is_some_and(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    i += 1;
+                }
+                ranges.push((start, end_of(i), Style::Variable));
+                statement_start = false;
+            }
+            '(' | '[' | '{' => {
+                open.push((start, c));
+                i += 1;
+                statement_start = c == '{';
+            }
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                match open.last() {
+                    Some(&(_, opener)) if opener == expected => {
+                        open.pop();
+                    }
+                    _ => ranges.push((start, start + 1, Style::Unbalanced)),
+                }
+                i += 1;
+                statement_start = c == '}';
+            }
+            _ => {
+                while chars
+                    .get(i)
+                    .is_some_and(|&(_, c)| !c.is_whitespace() && !";\"'$()[]{}".contains(c))
+                {
+                    i += 1;
+                }
+                let word = &source[start..end_of(i)];
+                let style = if KEYWORDS.contains(&word) {
+                    Style::Keyword
+                } else if statement_start && is_command(word) {
+                    Style::Command
+                } else if is_number(word) {
+                    Style::Number
+                } else {
+                    Style::Plain
+                };
+                if style != Style::Plain {
+                    ranges.push((start, end_of(i), style));
+                }
+                statement_start = COMMAND_SEPARATORS.contains(&word);
+            }
+        }
+    }
+    ranges
+}
+
+/// Numbers and `start..end` ranges of numbers.
+fn is_number(word: &str) -> bool {
+    word.split("..").all(|part| part.parse::<f64>().is_ok() && !part.contains(char::is_alphabetic))
+}
+
+/// Whether `source` still has an open quote or bracket, so the REPL should read another line
+/// before running it. Brackets that close nothing are left for the parser to report.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut open = 0usize;
+    let mut quote = None;
+    let mut statement_start = true;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '/' if statement_start && chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '(' | '[' | '{' => open += 1,
+            ')' | ']' | '}' => open = open.saturating_sub(1),
+            _ => {}
+        }
+        if !c.is_whitespace() || c == '\n' {
+            statement_start = matches!(c, '\n' | ';' | '{' | '}');
+        }
+    }
+    quote.is_some() || open > 0
+}
+
+/// Line editor helper for the REPL: completes command names, variables, dictionary keys and
+/// paths, and colors the input. It works from a snapshot of the interpreter taken with `refresh`
+/// before each prompt.
+pub struct ReplHelper {
+    filenames: FilenameCompleter,
+    variables: Vec<String>,
+    dict_keys: HashMap<String, Vec<String>>,
+    functions: Vec<String>,
+    /// Earlier lines of an unfinished block, so brackets opened there are not flagged when closed
+    pending: String,
+}
+
+impl Default for ReplHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplHelper {
+    pub fn new() -> Self {
+        Self {
+            filenames: FilenameCompleter::new(),
+            variables: Vec::new(),
+            dict_keys: HashMap::new(),
+            functions: Vec::new(),
+            pending: String::new(),
+        }
+    }
+
+    /// Sets the lines already entered for the statement being continued.
+    pub fn set_pending(&mut self, pending: &str) {
+        self.pending.clear();
+        self.pending.push_str(pending);
+    }
+
+    fn is_command(&self, word: &str) -> bool {
+        BUILTIN_COMMANDS.contains(&word)
+            || META_COMMANDS.contains(&word)
+            || self.functions.binary_search_by(|f| f.as_str().cmp(word)).is_ok()
+    }
+
+    /// Takes the variable, dictionary key and function names to complete from `interpreter`.
+    pub fn refresh(&mut self, interpreter: &Interpreter) {
+        self.variables.clear();
+        self.dict_keys.clear();
+        for (name, value) in interpreter.variables().globals() {
+            self.variables.push(name.clone());
+            if let ParsedValue::Dictionary(dict) = value {
+                let mut keys: Vec<String> = dict.keys().map(|key| quote_if_needed(key)).collect();
+                keys.sort();
+                self.dict_keys.insert(name.clone(), keys);
+            }
+        }
+        self.variables.sort();
+        self.functions = interpreter.functions.keys().cloned().collect();
+        self.functions.sort();
+    }
+
+    /// Completions for everything except paths, which need the file system.
+    fn candidates(&self, completion: &Completion<'_>, word: &str) -> Vec<String> {
+        let matching = |names: &mut dyn Iterator<Item = &str>| -> Vec<String> {
+            names.filter(|name| name.starts_with(word)).map(str::to_string).collect()
+        };
+        match completion {
+            Completion::Command => {
+                let mut names = matching(
+                    &mut BUILTIN_COMMANDS
+                        .iter()
+                        .chain(KEYWORDS)
+                        .copied()
+                        .chain(self.functions.iter().map(String::as_str)),
+                );
+                names.sort();
+                names.dedup();
+                names
+            }
+            Completion::Reference => self
+                .variables
+                .iter()
+                .filter(|name| name.starts_with(&word[1..]))
+                .map(|name| format!("${}", name))
+                .collect(),
+            Completion::Variable => matching(&mut self.variables.iter().map(String::as_str)),
+            Completion::DictKey(dict) => match self.dict_keys.get(*dict) {
+                Some(keys) => matching(&mut keys.iter().map(String::as_str)),
+                None => Vec::new(),
+            },
+            Completion::MetaCommand => matching(&mut META_COMMANDS.iter().copied()),
+            Completion::Path | Completion::None => Vec::new(),
+        }
+    }
+}
+
+/// Dictionary keys are inserted as one argument, so ones with spaces need quotes.
+fn quote_if_needed(key: &str) -> String {
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        format!("\"{}\"", key)
+    } else {
+        key.to_string()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, completion) = completion_context(line, pos);
+        if completion == Completion::Path {
+            return self.filenames.complete(line, pos, ctx);
+        }
+        let candidates = self
+            .candidates(&completion, &line[start..pos])
+            .into_iter()
+            .map(|name| Pair { display: name.clone(), replacement: name })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        // Styles depend on what came before, so the pending lines are scanned too
+        let offset = self.pending.len();
+        let source = format!("{}{}", self.pending, line);
+        let mut out = String::with_capacity(line.len());
+        let mut written = offset;
+        for (start, end, style) in styled_ranges(&source, |word| self.is_command(word)) {
+            if end <= offset {
+                continue;
+            }
+            let start = start.max(offset);
+            out.push_str(&source[written..start]);
+            out.push_str(&style.paint(&source[start..end]));
+            written = end;
+        }
+        out.push_str(&source[written..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// `$XDG_DATA_HOME/key_forge/history`, which is `~/.local/share/key_forge/history` by default.
+pub fn history_path() -> Option<PathBuf> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(data_home.join("key_forge").join("history"))
+}
+
+/// Adds `entry` as the newest history entry. An older copy of it is dropped, so recalling a
+/// command does not fill the history with repeats.
+pub fn add_history_entry(history: &mut History, entry: &str) {
+    if history.iter().any(|old| old == entry) {
+        let kept: Vec<String> = history.iter().filter(|old| *old != entry).cloned().collect();
+        history.clear();
+        for old in kept {
+            history.add(old);
+        }
+    }
+    history.add(entry);
+}
+
+/// A REPL-only command, written with a leading `:`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MetaCommand<'a> {
+    /// Clears every variable; functions are kept
+    Reset,
+    /// Runs a script in the current session
+    Load(&'a str),
+    /// Runs a statement and reports how long it took
+    Time(&'a str),
+    History,
+    /// Writes the commands that ran without error to a script
+    SaveSession(&'a str),
+}
+
+impl<'a> MetaCommand<'a> {
+    pub fn parse(input: &'a str) -> Result<Self, KeyForgeError> {
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (input, ""),
+        };
+        let command = match name {
+            ":reset" => Self::Reset,
+            ":history" => Self::History,
+            ":load" => Self::Load(argument),
+            ":time" => Self::Time(argument),
+            ":save-session" => Self::SaveSession(argument),
+            _ => {
+                return Err(KeyForgeError::runtime(format!(
+                    "Unknown REPL command '{}' (available: {})",
+                    name,
+                    META_COMMANDS.join(", ")
+                )))
+            }
+        };
+        let needs_argument = matches!(command, Self::Load(_) | Self::Time(_) | Self::SaveSession(_));
+        if needs_argument && argument.is_empty() {
+            let usage = match command {
+                Self::Time(_) => "<statement>",
+                _ => "<file>",
+            };
+            return Err(KeyForgeError::arity(format!("Usage: {} {}", name, usage)));
+        }
+        Ok(command)
+    }
+}
+
+/// What happened in a REPL session so far, for the meta-commands.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Input that ran without error, in order
+    commands: Vec<String>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers `source` for `:save-session` after it ran without error.
+    pub fn record(&mut self, source: &str) {
+        self.commands.push(source.to_string());
+    }
+
+    /// The recorded commands as a script that rebuilds the session.
+    pub fn script(&self) -> String {
+        let mut script = String::from("// KeyForge session saved with :save-session\n");
+        for command in &self.commands {
+            script.push_str(command);
+            script.push('\n');
+        }
+        script
+    }
+
+    /// Runs a line starting with `:`.
+    pub fn run_meta_command(
+        &mut self,
+        interpreter: &mut Interpreter,
+        history: &History,
+        input: &str,
+    ) -> Result<(), KeyForgeError> {
+        match MetaCommand::parse(input)? {
+            MetaCommand::Reset => {
+                interpreter.variables_mut().clear_globals();
+                // Replaying the session has to end up in the same state, so only the function
+                // definitions survive in it
+                self.commands.retain(|source| defines_functions_only(source));
+                println!("All variables cleared");
+            }
+            MetaCommand::Load(raw) => {
+                let file = resolve_filename(interpreter, raw)?;
+                interpreter.run_file(&file)?;
+                self.record(&format!("execute_file {}", quote_if_needed(&file)));
+            }
+            MetaCommand::Time(source) => {
+                let started = Instant::now();
+                let result = interpreter.execute_source(source, false);
+                println!("{}", format!("Time: {:.3} ms", started.elapsed().as_secs_f64() * 1000.0).dimmed());
+                result?;
+                self.record(source);
+            }
+            MetaCommand::History => {
+                for (number, entry) in history.iter().enumerate() {
+                    println!("{:>5}  {}", number + 1, entry);
+                }
+            }
+            MetaCommand::SaveSession(raw) => {
+                let file = resolve_filename(interpreter, raw)?;
+                std::fs::write(&file, self.script())
+                    .map_err(|e| KeyForgeError::io(format!("Failed to write file '{}': {}", file, e)))?;
+                println!("Session saved to {} ({} commands)", file, self.commands.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The text the location of an error from `input` refers to: the statement of a `:time`,
+/// otherwise the input itself.
+pub fn error_source(input: &str) -> &str {
+    match MetaCommand::parse(input) {
+        Ok(MetaCommand::Time(source)) => source,
+        _ => input,
+    }
+}
+
+fn defines_functions_only(source: &str) -> bool {
+    parser::parse(source).is_ok_and(|script| {
+        script.statements.iter().all(|statement| matches!(statement.kind, StatementKind::Function(_)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_context_follows_the_statement() {
+        assert_eq!(completion_context("gen_", 4), (0, Completion::Command));
+        assert_eq!(completion_context("print $co", 9), (6, Completion::Reference));
+        assert_eq!(completion_context("if $x > 1 then pri", 18), (15, Completion::Command));
+        assert_eq!(completion_context("set x $(uuid", 12), (8, Completion::Command));
+        assert_eq!(completion_context("len ", 4), (4, Completion::Variable));
+        assert_eq!(completion_context("rm a b", 6), (5, Completion::Variable));
+        assert_eq!(completion_context("get config ho", 13), (11, Completion::DictKey("config")));
+        assert_eq!(completion_context("read_file da", 12), (10, Completion::Path));
+        assert_eq!(completion_context("save_state --format ", 20), (20, Completion::None));
+        assert_eq!(completion_context("load_state --merge st", 21), (19, Completion::Path));
+        assert_eq!(completion_context("print x", 7), (6, Completion::None));
+    }
+
+    #[test]
+    fn test_candidates_come_from_the_interpreter() {
+        let mut interpreter = Interpreter::with_output(std::io::sink());
+        let source = "set count 1\nset config {host: \"a\", \"max retries\": 3}\nfn greet (name) {\n  print $name\n}";
+        interpreter.execute_source(source, false).unwrap();
+        let mut helper = ReplHelper::new();
+        helper.refresh(&interpreter);
+
+        assert_eq!(helper.candidates(&Completion::Reference, "$co"), ["$config", "$count"]);
+        assert_eq!(helper.candidates(&Completion::Variable, "cou"), ["count"]);
+        assert_eq!(helper.candidates(&Completion::DictKey("config"), ""), ["\"max retries\"", "host"]);
+        assert_eq!(helper.candidates(&Completion::Command, "gre"), ["greet"]);
+        assert!(helper.candidates(&Completion::Command, "wh").contains(&"while".to_string()));
+        assert!(helper.candidates(&Completion::DictKey("count"), "").is_empty());
+    }
+
+    #[test]
+    fn test_history_keeps_the_newest_copy_of_an_entry() {
+        let mut history = History::new();
+        for entry in ["set x 1", "print $x", "set x 1", "vl"] {
+            add_history_entry(&mut history, entry);
+        }
+        let entries: Vec<&String> = history.iter().collect();
+        assert_eq!(entries, ["print $x", "set x 1", "vl"]);
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        assert_eq!(MetaCommand::parse(":load  setup.kf").unwrap(), MetaCommand::Load("setup.kf"));
+        assert_eq!(MetaCommand::parse(":time gen_password 12").unwrap(), MetaCommand::Time("gen_password 12"));
+        assert!(matches!(MetaCommand::parse(":save-session"), Err(KeyForgeError::Arity { .. })));
+        assert!(MetaCommand::parse(":quit").is_err());
+        assert_eq!(completion_context(":sa", 3), (0, Completion::MetaCommand));
+        assert_eq!(completion_context(":load se", 8), (6, Completion::Path));
+        assert_eq!(completion_context(":time gen", 9), (6, Completion::Command));
+
+        // Only function definitions outlive `:reset` in the saved session
+        let mut interpreter = Interpreter::with_output(std::io::sink());
+        let mut session = Session::new();
+        for source in ["set x 1", "fn double (n) {\n  mul n 2\n}"] {
+            interpreter.execute_source(source, false).unwrap();
+            session.record(source);
+        }
+        session.run_meta_command(&mut interpreter, &History::new(), ":reset").unwrap();
+        assert!(!interpreter.variables().has_variable("x"));
+        assert!(interpreter.functions.contains_key("double"));
+        assert_eq!(
+            session.script(),
+            "// KeyForge session saved with :save-session\nfn double (n) {\n  mul n 2\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_blocks_and_quotes_continue_until_balanced() {
+        assert!(is_incomplete("for i in 1..3 do {\n"));
+        assert!(is_incomplete("for i in 1..3 do {\n  print $i\n"));
+        assert!(!is_incomplete("for i in 1..3 do {\n  print $i\n}\n"));
+        assert!(is_incomplete("print \"two\nlines"));
+        assert!(!is_incomplete("print \"{ is text\""));
+        assert!(!is_incomplete("// a comment with {\n"));
+        assert!(is_incomplete("set x $(gen_password 12"));
+        assert!(!is_incomplete("print }"));
+    }
+
+    #[test]
+    fn test_highlighting_styles() {
+        let source = "if $n > 1 then print \"big\" }";
+        let styled: Vec<(&str, Style)> = styled_ranges(source, |word| word == "print")
+            .into_iter()
+            .map(|(start, end, style)| (&source[start..end], style))
+            .collect();
+        assert_eq!(
+            styled,
+            [
+                ("if", Style::Keyword),
+                ("$n", Style::Variable),
+                ("1", Style::Number),
+                ("then", Style::Keyword),
+                ("print", Style::Command),
+                ("\"big\"", Style::String),
+                ("}", Style::Unbalanced),
+            ]
+        );
+        assert!(styled_ranges("for i in 1..3 do { print $i }", |_| false).contains(&(9, 13, Style::Number)));
+        assert!(styled_ranges("set a [1, 2)", |_| false).contains(&(11, 12, Style::Unbalanced)));
+    }
+}
@@ -0,0 +1,101 @@
+use rand::rngs::OsRng;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Random source shared by every random command of a session. It draws from the operating
+/// system until `seed` switches it to a ChaCha8 stream, which makes a script produce the same
+/// output on every run. Seeded output is predictable by design and only meant for test data,
+/// which is why `SessionRng` does not implement `CryptoRng`.
+#[derive(Debug, Clone, Default)]
+pub enum SessionRng {
+    #[default]
+    Os,
+    Seeded {
+        seed: u64,
+        rng: Box<ChaCha8Rng>,
+        /// Whether a secret generator already warned that its output is predictable
+        warned: bool,
+    },
+}
+
+impl SessionRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self::Seeded {
+            seed,
+            rng: Box::new(ChaCha8Rng::seed_from_u64(seed)),
+            warned: false,
+        }
+    }
+
+    /// The seed in use, `None` when drawing from the operating system.
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            Self::Os => None,
+            Self::Seeded { seed, .. } => Some(*seed),
+        }
+    }
+
+    /// The seed, the first time a secret generator (`gen_password`, `gen_bytes`, `gen_token`)
+    /// draws from a seeded stream; `None` afterwards and when drawing from the operating system.
+    pub fn take_secret_warning(&mut self) -> Option<u64> {
+        match self {
+            Self::Seeded { seed, warned, .. } if !*warned => {
+                *warned = true;
+                Some(*seed)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl RngCore for SessionRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Self::Os => OsRng.next_u32(),
+            Self::Seeded { rng, .. } => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Self::Os => OsRng.next_u64(),
+            Self::Seeded { rng, .. } => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::Os => OsRng.fill_bytes(dest),
+            Self::Seeded { rng, .. } => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            Self::Os => OsRng.try_fill_bytes(dest),
+            Self::Seeded { rng, .. } => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_stream() {
+        let mut a = SessionRng::seeded(42);
+        let mut b = SessionRng::seeded(42);
+        let mut c = SessionRng::seeded(43);
+        let (x, y, z) = (a.next_u64(), b.next_u64(), c.next_u64());
+        assert_eq!(x, y);
+        assert_ne!(x, z);
+        assert_eq!(a.seed(), Some(42));
+        assert_eq!(SessionRng::default().seed(), None);
+
+        assert_eq!(a.take_secret_warning(), Some(42));
+        assert_eq!(a.take_secret_warning(), None);
+        assert_eq!(SessionRng::seeded(42).take_secret_warning(), Some(42));
+        assert_eq!(SessionRng::Os.take_secret_warning(), None);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+use super::error::KeyForgeError;
+use super::key_forge::{parse_value, ParsedValue, Variables};
+use super::vault::{self, KdfParams};
+
+/// Schema version written by `save_state`. Files with a higher version are refused rather than
+/// half-understood.
+pub const STATE_VERSION: u32 = 1;
+
+// Type names a line of the old `type:name:value` format starts with
+const LEGACY_TYPES: &[&str] = &["int", "float", "string", "bool", "null", "bytes", "array", "dict"];
+
+/// On-disk layout of a saved session. `variables` is sorted so saved files diff cleanly.
+#[derive(Debug, Serialize, Deserialize)]
+struct StateDocument {
+    version: u32,
+    variables: BTreeMap<String, ParsedValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateFormat {
+    Json,
+    Toml,
+}
+
+impl StateFormat {
+    pub fn from_name(name: &str) -> Result<Self, KeyForgeError> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            _ => Err(KeyForgeError::runtime(format!("Unknown state format '{}' (use json or toml)", name))),
+        }
+    }
+
+    /// TOML for `.toml` files, JSON for everything else.
+    pub fn for_path(path: &str) -> Self {
+        match Path::new(path).extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// What `load_state_from_file` takes from the file and what it does with the session.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Keep variables that are not in the file instead of replacing the whole session
+    pub merge: bool,
+    /// Load only these variables (implies `merge`); every name must be in the file
+    pub names: Vec<String>,
+}
+
+/// Writes every global variable to `filename`, encrypted with `passphrase` when one is given.
+/// The file is written next to its destination first and renamed over it, so a failed save
+/// never leaves a truncated state file behind.
+pub fn save_state_to_file(
+    filename: &str,
+    store: &Variables,
+    format: StateFormat,
+    passphrase: Option<&str>,
+) -> Result<(), KeyForgeError> {
+    let document = StateDocument {
+        version: STATE_VERSION,
+        variables: store.globals().map(|(name, value)| (name.clone(), value.clone())).collect(),
+    };
+
+    let text = match format {
+        StateFormat::Json => serde_json::to_string_pretty(&document).map_err(|e| e.to_string()),
+        StateFormat::Toml => toml::to_string(&document).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| KeyForgeError::runtime(format!("Failed to serialize state: {}", e)))?;
+
+    let content = match passphrase {
+        Some(passphrase) => vault::encrypt(text.as_bytes(), passphrase, KdfParams::default(), &mut OsRng)
+            .map_err(KeyForgeError::runtime)?,
+        None => text.into_bytes(),
+    };
+
+    write_atomically(filename, &content)
+        .map_err(|e| KeyForgeError::io(format!("Failed to write file '{}': {}", filename, e)))
+}
+
+/// Reads a state file in any supported format and applies it to `store` according to
+/// `options`. Encrypted files need `passphrase`, plain ones must not get one. The whole file is
+/// parsed before anything changes, so a corrupt file or a wrong passphrase leaves the session
+/// as it was. Returns the number of variables loaded.
+pub fn load_state_from_file(
+    filename: &str,
+    store: &mut Variables,
+    options: &LoadOptions,
+    passphrase: Option<&str>,
+) -> Result<usize, KeyForgeError> {
+    let data = fs::read(filename).map_err(|e| KeyForgeError::io(format!("Failed to open file '{}': {}", filename, e)))?;
+    let data = match (vault::is_encrypted(&data), passphrase) {
+        (true, Some(passphrase)) => vault::decrypt(&data, passphrase).map_err(KeyForgeError::runtime)?,
+        (true, None) => {
+            return Err(KeyForgeError::runtime(format!(
+                "'{}' is encrypted, load it with load_state --decrypt",
+                filename
+            )))
+        }
+        (false, Some(_)) => return Err(KeyForgeError::runtime(format!("'{}' is not encrypted", filename))),
+        (false, None) => data,
+    };
+    let content = String::from_utf8(data)
+        .map_err(|_| KeyForgeError::parse(format!("Invalid state file '{}': not UTF-8 text", filename)))?;
+    let mut variables = parse_state(&content).map_err(|e| e.prefixed(&format!("Invalid state file '{}'", filename)))?;
+
+    if !options.names.is_empty() {
+        let mut selected = BTreeMap::new();
+        for name in &options.names {
+            let value = variables
+                .remove(name)
+                .ok_or_else(|| KeyForgeError::undefined(name.as_str()).prefixed(filename))?;
+            selected.insert(name.clone(), value);
+        }
+        variables = selected;
+    } else if !options.merge {
+        store.clear_globals();
+    }
+
+    let count = variables.len();
+    for (name, value) in variables {
+        store.set_global(name, value);
+    }
+    Ok(count)
+}
+
+/// Parses a state file, telling the formats apart by their content: JSON starts with `{`, the
+/// legacy format with a `type:` prefix, and anything else is read as TOML.
+pub fn parse_state(content: &str) -> Result<BTreeMap<String, ParsedValue>, KeyForgeError> {
+    let first_line = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("//"));
+
+    let document: StateDocument = match first_line {
+        None => return Ok(BTreeMap::new()),
+        Some(line) if line.starts_with('{') => {
+            serde_json::from_str(content).map_err(|e| KeyForgeError::parse(e.to_string()))?
+        }
+        Some(line) if is_legacy_line(line) => return parse_legacy_state(content),
+        Some(_) => toml::from_str(content).map_err(|e| KeyForgeError::parse(e.to_string().trim_end().to_string()))?,
+    };
+
+    if document.version > STATE_VERSION {
+        return Err(KeyForgeError::runtime(format!(
+            "state version {} is newer than the supported version {}",
+            document.version, STATE_VERSION
+        )));
+    }
+    Ok(document.variables)
+}
+
+fn is_legacy_line(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(kind, _)| LEGACY_TYPES.contains(&kind))
+}
+
+fn unescape_state_value(value: &str) -> String {
+    value.replace("\\n", "\n").replace("\\:", ":").replace("\\\\", "\\")
+}
+
+/// Reads the `type:name:value` lines written before the state format was versioned.
+/// Strings escape `\`, `:` and new lines; collections are embedded JSON.
+fn parse_legacy_state(content: &str) -> Result<BTreeMap<String, ParsedValue>, KeyForgeError> {
+    let mut variables = BTreeMap::new();
+
+    for (line_num, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.splitn(3, ':').collect();
+        if parts.len() != 3 {
+            return Err(KeyForgeError::parse(format!("Invalid format at line {}: expected 'type:name:value'", line_num + 1)));
+        }
+
+        let var_type = parts[0];
+        let name = parts[1];
+        let value = parts[2];
+
+        let parsed = match var_type {
+            "int" => ParsedValue::Int(
+                value
+                    .parse::<i64>()
+                    .map_err(|e| KeyForgeError::parse(format!("Invalid integer value at line {}: {}", line_num + 1, e)))?,
+            ),
+            "float" => ParsedValue::Float(
+                value
+                    .parse::<f64>()
+                    .map_err(|e| KeyForgeError::parse(format!("Invalid float value at line {}: {}", line_num + 1, e)))?,
+            ),
+            "string" => ParsedValue::String(unescape_state_value(value)),
+            "array" => ParsedValue::Array(
+                serde_json::from_str(&unescape_state_value(value))
+                    .map_err(|e| KeyForgeError::parse(format!("Invalid array value at line {}: {}", line_num + 1, e)))?,
+            ),
+            "dict" => ParsedValue::Dictionary(
+                serde_json::from_str(&unescape_state_value(value))
+                    .map_err(|e| KeyForgeError::parse(format!("Invalid dict value at line {}: {}", line_num + 1, e)))?,
+            ),
+            "bool" | "null" | "bytes" => {
+                let parsed = parse_value(value);
+                if parsed.type_name() != var_type {
+                    return Err(KeyForgeError::parse(format!("Invalid {} value at line {}", var_type, line_num + 1)));
+                }
+                parsed
+            }
+            _ => {
+                return Err(KeyForgeError::parse(format!(
+                    "Unknown variable type '{}' at line {}",
+                    var_type,
+                    line_num + 1
+                )))
+            }
+        };
+        variables.insert(name.to_string(), parsed);
+    }
+
+    Ok(variables)
+}
+
+/// Writes `content` to a temporary file in the destination directory and renames it into
+/// place. The rename is atomic on the same file system, so readers see the old file or the
+/// new one, never a partial write. A file that is replaced keeps its permissions; a new one
+/// is only readable by its owner, since state files may hold secrets.
+fn write_atomically(filename: &str, content: &[u8]) -> std::io::Result<()> {
+    let path = Path::new(filename);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file name"))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), std::process::id()));
+    let permissions = fs::metadata(path).ok().map(|metadata| metadata.permissions());
+
+    let written = create_private_file(&temp_path).and_then(|mut file| {
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    let result = written.and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn create_private_file(path: &Path) -> std::io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_store() -> Variables {
+        let mut store = Variables::new();
+        store.set_value("count".to_string(), ParsedValue::Int(i64::MAX));
+        store.set_value("ratio".to_string(), ParsedValue::Float(0.5));
+        store.set_value("name".to_string(), ParsedValue::String("a:b\nc".to_string()));
+        store.set_value("flag".to_string(), ParsedValue::Bool(true));
+        store.set_value("nothing".to_string(), ParsedValue::Null);
+        store.set_value("key".to_string(), ParsedValue::Bytes(vec![0, 255]));
+        store.set_value(
+            "list".to_string(),
+            ParsedValue::Array(vec![ParsedValue::Int(1), ParsedValue::String("two".to_string())]),
+        );
+        store
+    }
+
+    #[test]
+    fn test_round_trips_through_json_and_toml() {
+        let original = sample_store();
+        for format in [StateFormat::Json, StateFormat::Toml] {
+            let path = std::env::temp_dir().join(format!("key_forge_state_{}_{:?}", std::process::id(), format));
+            let path = path.to_str().unwrap();
+            save_state_to_file(path, &original, format, None).unwrap();
+
+            let mut loaded = Variables::new();
+            loaded.set_value("stale".to_string(), ParsedValue::Int(1));
+            assert_eq!(load_state_from_file(path, &mut loaded, &LoadOptions::default(), None).unwrap(), 7);
+            std::fs::remove_file(path).unwrap();
+
+            assert!(!loaded.has_variable("stale"));
+            let mut expected: Vec<_> = original.globals().collect();
+            let mut actual: Vec<_> = loaded.globals().collect();
+            expected.sort_by(|a, b| a.0.cmp(b.0));
+            actual.sort_by(|a, b| a.0.cmp(b.0));
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_saving_keeps_the_mode_of_an_existing_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("key_forge_state_{}_mode", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        save_state_to_file(path, &sample_store(), StateFormat::Json, None).unwrap();
+        assert_eq!(mode(path), 0o600);
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        save_state_to_file(path, &sample_store(), StateFormat::Toml, None).unwrap();
+        assert_eq!(mode(path), 0o640);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_merge_selective_legacy_and_corrupt_files() {
+        let path = std::env::temp_dir().join(format!("key_forge_state_{}_legacy", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "int:a:1\nstring:b:x\\:y\nbytes:c:0xff\n").unwrap();
+
+        let mut store = Variables::new();
+        store.set_value("kept".to_string(), ParsedValue::Int(9));
+        let only_b = LoadOptions { merge: false, names: vec!["b".to_string()] };
+        assert_eq!(load_state_from_file(path, &mut store, &only_b, None).unwrap(), 1);
+        assert_eq!(store.get_string_data("b").unwrap(), "x:y");
+        assert!(store.has_variable("kept") && !store.has_variable("a"));
+
+        let merge = LoadOptions { merge: true, names: Vec::new() };
+        load_state_from_file(path, &mut store, &merge, None).unwrap();
+        assert_eq!(store.get_bytes_data("c").unwrap(), vec![0xff]);
+        assert!(store.has_variable("kept"));
+
+        let missing = LoadOptions { merge: false, names: vec!["zzz".to_string()] };
+        assert!(matches!(
+            load_state_from_file(path, &mut store, &missing, None),
+            Err(KeyForgeError::UndefinedVariable { .. })
+        ));
+
+        std::fs::write(path, "{\"version\": 1, \"variables\": {\"a\": ").unwrap();
+        assert!(load_state_from_file(path, &mut store, &LoadOptions::default(), None).is_err());
+        assert!(store.has_variable("kept"), "a corrupt file must not clear the session");
+
+        std::fs::write(path, "{\"version\": 99, \"variables\": {}}").unwrap();
+        let err = load_state_from_file(path, &mut store, &LoadOptions::default(), None).unwrap_err();
+        assert!(err.message().contains("newer"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Largest `gen_bytes` count and `gen_token` body length, so a typo cannot exhaust memory.
+pub const MAX_LENGTH: usize = 1 << 20;
+
+/// Checksum appended to a token so it can be validated without a database lookup. It covers
+/// everything before it, prefix included, and is written in base62 like the token body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC32 of the token, 6 characters
+    Crc32,
+    /// First 6 bytes of the SHA-256 of the token, 9 characters
+    Sha256,
+}
+
+impl Checksum {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "crc32" => Ok(Self::Crc32),
+            "sha256" => Ok(Self::Sha256),
+            _ => Err(format!("Unknown checksum '{}' (use crc32 or sha256)", name)),
+        }
+    }
+
+    /// Length of the checksum suffix in characters.
+    pub fn suffix_len(self) -> usize {
+        match self {
+            Self::Crc32 => 6,
+            Self::Sha256 => 9,
+        }
+    }
+
+    fn compute(self, text: &str) -> String {
+        let value = match self {
+            Self::Crc32 => u64::from(crc32fast::hash(text.as_bytes())),
+            Self::Sha256 => {
+                let digest = Sha256::digest(text.as_bytes());
+                let mut bytes = [0u8; 8];
+                bytes[2..].copy_from_slice(&digest[..6]);
+                u64::from_be_bytes(bytes)
+            }
+        };
+        base62_fixed(value, self.suffix_len())
+    }
+}
+
+fn check_length(length: usize) -> Result<(), String> {
+    if length == 0 || length > MAX_LENGTH {
+        return Err(format!("Length must be between 1 and {}", MAX_LENGTH));
+    }
+    Ok(())
+}
+
+/// `value` in base62, left-padded with zeros to `width` digits.
+fn base62_fixed(mut value: u64, width: usize) -> String {
+    let mut digits = vec![b'0'; width];
+    for digit in digits.iter_mut().rev() {
+        *digit = BASE62[(value % 62) as usize];
+        value /= 62;
+    }
+    String::from_utf8(digits).expect("base62 digits are ASCII")
+}
+
+/// `length` bytes from `rng`; only as secret as `rng` is, so real keys need a `CryptoRng`.
+pub fn random_bytes<R: RngCore>(length: usize, rng: &mut R) -> Result<Vec<u8>, String> {
+    check_length(length)?;
+    let mut bytes = vec![0u8; length];
+    rng.fill_bytes(&mut bytes);
+    Ok(bytes)
+}
+
+/// Creates `<prefix><length random base62 characters>[checksum]`, e.g. `kf_live_3xY...`.
+/// The prefix may only use letters, digits, `_` and `-`, so tokens survive URLs and shells.
+/// Tokens from a seeded (non-`CryptoRng`) generator are test data, not credentials.
+pub fn generate_token<R: Rng>(
+    prefix: &str,
+    length: usize,
+    checksum: Option<Checksum>,
+    rng: &mut R,
+) -> Result<String, String> {
+    if !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("Token prefix '{}' may only contain letters, digits, '_' and '-'", prefix));
+    }
+    check_length(length)?;
+
+    let mut token = String::with_capacity(prefix.len() + length + 9);
+    token.push_str(prefix);
+    token.extend((0..length).map(|_| BASE62[rng.gen_range(0..BASE62.len())] as char));
+    if let Some(checksum) = checksum {
+        let suffix = checksum.compute(&token);
+        token.push_str(&suffix);
+    }
+    Ok(token)
+}
+
+/// Checks the checksum suffix of a token made by `generate_token`.
+pub fn verify_token(token: &str, checksum: Checksum) -> bool {
+    let Some(split) = token.len().checked_sub(checksum.suffix_len()) else {
+        return false;
+    };
+    match (token.get(..split), token.get(split..)) {
+        (Some(body), Some(suffix)) => checksum.compute(body) == suffix,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_tokens_carry_a_verifiable_checksum() {
+        for checksum in [Checksum::Crc32, Checksum::Sha256] {
+            let token = generate_token("kf_live_", 30, Some(checksum), &mut OsRng).unwrap();
+            assert!(token.starts_with("kf_live_"));
+            assert_eq!(token.len(), 8 + 30 + checksum.suffix_len());
+            assert!(verify_token(&token, checksum));
+
+            // Any changed character is caught
+            let mut tampered = token.into_bytes();
+            tampered[10] = if tampered[10] == b'a' { b'b' } else { b'a' };
+            assert!(!verify_token(&String::from_utf8(tampered).unwrap(), checksum));
+        }
+
+        // The CRC32 check value 0xCBF43926 and the start of SHA-256("123456789"), in base62
+        assert_eq!(Checksum::Crc32.compute("123456789"), "3jZRME");
+        assert_eq!(Checksum::Sha256.compute("123456789"), "06peFSjyi");
+        assert!(!verify_token("abc", Checksum::Sha256));
+        assert!(generate_token("kf live", 10, None, &mut OsRng).is_err());
+        assert!(random_bytes(0, &mut OsRng).is_err());
+        assert_eq!(random_bytes(32, &mut OsRng).unwrap().len(), 32);
+    }
+}
@@ -0,0 +1,137 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{CryptoRng, RngCore};
+
+/// First bytes of every encrypted state file.
+pub const MAGIC: &[u8; 8] = b"KFVAULT1";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+// Magic, three u32 Argon2 parameters, salt and nonce
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN + NONCE_LEN;
+
+// Upper bounds for parameters read from a file, so a crafted header cannot make loading
+// allocate gigabytes or spin for minutes
+const MAX_MEMORY_KIB: u32 = 1 << 20;
+const MAX_ITERATIONS: u32 = 64;
+const MAX_LANES: u32 = 16;
+
+/// Argon2id cost parameters. They are stored in the file header, so files written with
+/// different costs can still be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub lanes: u32,
+}
+
+impl Default for KdfParams {
+    /// The Argon2id costs recommended by OWASP: 19 MiB, two passes, one lane.
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            lanes: 1,
+        }
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts `plaintext` with ChaCha20-Poly1305 under a key derived from `passphrase` with
+/// Argon2id. The header (magic, KDF parameters, salt and nonce) is authenticated as associated
+/// data, so changing any of it makes decryption fail.
+pub fn encrypt<R: RngCore + CryptoRng>(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: KdfParams,
+    rng: &mut R,
+) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill_bytes(&mut salt);
+    rng.fill_bytes(&mut nonce);
+
+    let mut out = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    out.extend_from_slice(MAGIC);
+    for value in [params.memory_kib, params.iterations, params.lanes] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, params)?);
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), Payload { msg: plaintext, aad: &out })
+        .map_err(|_| "Encryption failed".to_string())?;
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Reverses `encrypt`. A wrong passphrase and a modified file cannot be told apart, so both
+/// give the same error.
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    if !is_encrypted(data) || data.len() < HEADER_LEN {
+        return Err("Not an encrypted state file".to_string());
+    }
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+
+    let word = |index: usize| {
+        let start = MAGIC.len() + index * 4;
+        u32::from_le_bytes(header[start..start + 4].try_into().expect("four header bytes"))
+    };
+    let params = KdfParams {
+        memory_kib: word(0),
+        iterations: word(1),
+        lanes: word(2),
+    };
+    if params.memory_kib > MAX_MEMORY_KIB || params.iterations > MAX_ITERATIONS || params.lanes > MAX_LANES {
+        return Err("Encrypted state file asks for unreasonable key derivation costs".to_string());
+    }
+
+    let salt = &header[MAGIC.len() + 12..MAGIC.len() + 12 + SALT_LEN];
+    let nonce: [u8; NONCE_LEN] = header[HEADER_LEN - NONCE_LEN..].try_into().expect("nonce bytes");
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt, params)?);
+    cipher
+        .decrypt(&Nonce::from(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| "Wrong passphrase or corrupted state file".to_string())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<Key, String> {
+    let params = Params::new(params.memory_kib, params.iterations, params.lanes, Some(32))
+        .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    // Cheap costs keep the tests fast; the format does not depend on them
+    const TEST_PARAMS: KdfParams = KdfParams { memory_kib: 64, iterations: 1, lanes: 1 };
+
+    #[test]
+    fn test_round_trip_wrong_passphrase_and_tampered_header() {
+        let sealed = encrypt(b"{\"version\": 1}", "correct horse", TEST_PARAMS, &mut OsRng).unwrap();
+        assert!(is_encrypted(&sealed));
+        assert_eq!(decrypt(&sealed, "correct horse").unwrap(), b"{\"version\": 1}");
+
+        assert_eq!(decrypt(&sealed, "wrong").unwrap_err(), "Wrong passphrase or corrupted state file");
+
+        // Raising the iteration count in the header must not go unnoticed
+        let mut tampered = sealed.clone();
+        tampered[MAGIC.len() + 4] += 1;
+        assert!(decrypt(&tampered, "correct horse").is_err());
+
+        assert!(decrypt(b"{}", "correct horse").is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod key_forge;
+
+pub use key_forge::error::KeyForgeError;
+pub use key_forge::interpreter::Interpreter;
+pub use key_forge::key_forge::{ParsedValue, Variables};
@@ -1,22 +1,44 @@
 use std::env;
 use colored::Colorize;
 
-mod key_forge;
-
-use key_forge::{input_mode, interpret_arguments_from_command_line};
+use key_forge::key_forge::{input_mode, interpret_arguments_from_command_line};
+use key_forge::Interpreter;
 
 fn main() {
     let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut interpreter = Interpreter::new();
+
+    // `--seed <u64>` goes before the mode and makes every random command reproducible
+    if args.first().is_some_and(|arg| arg == "--seed") {
+        match args.get(1).and_then(|seed| seed.parse::<u64>().ok()) {
+            Some(seed) => interpreter.set_seed(Some(seed)),
+            None => {
+                println!("{}", "--seed needs an unsigned 64-bit integer".red().bold());
+                std::process::exit(1);
+            }
+        }
+        args.drain(..2);
+    }
 
     if args.is_empty() {
-        input_mode::cli_mode();
+        input_mode::cli_mode(&mut interpreter);
     } else if args[0] == "arg" {
         args.remove(0);
-        if let Err(e) = interpret_arguments_from_command_line(&args) {
-            println!("{}", format!("{}", e).red().bold());
+        if let Err(e) = interpret_arguments_from_command_line(&mut interpreter, &args) {
+            if let Some(code) = e.exit_code() {
+                std::process::exit(code);
+            }
+            println!("{}", e.to_string().red().bold());
             std::process::exit(1);
         }
     } else if !args[0].is_empty() {
-        input_mode::file_mode(&args[0]);
+        interpreter.set_script_arguments(&args[0], &args[1..]);
+        if let Err(e) = input_mode::file_mode(&mut interpreter, &args[0]) {
+            if let Some(code) = e.exit_code() {
+                std::process::exit(code);
+            }
+            println!("{}", e.render(None).red());
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,43 @@
+use std::process::Command;
+
+fn key_forge(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_key_forge"))
+        .args(args)
+        .output()
+        .expect("key_forge runs")
+}
+
+#[test]
+fn test_argument_mode_exit_status() {
+    let ok = key_forge(&["arg", "set x 2", "print $x"]);
+    assert_eq!(ok.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&ok.stdout), "2\n");
+
+    let failed = key_forge(&["arg", "set x 2", "add missing 1", "print $x"]);
+    assert_eq!(failed.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&failed.stdout);
+    assert!(stdout.contains("Error in argument 2"), "{}", stdout);
+    assert!(!stdout.contains("\n2\n"), "{}", stdout);
+}
+
+#[test]
+fn test_exit_sets_the_exit_status() {
+    let output = key_forge(&["arg", "print a", "exit 3", "print b"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("a\n") && !stdout.contains("b\n"), "{}", stdout);
+}
+
+#[test]
+fn test_file_mode_arguments_and_exit_status() {
+    let path = std::env::temp_dir().join(format!("key_forge_cli_{}.kf", std::process::id()));
+    let script = path.to_str().unwrap();
+    std::fs::write(&path, "print $argc $1\nadd missing $1\nprint after\n").unwrap();
+    let output = key_forge(&[script, "7", "x"]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("2 7\n") && stdout.contains("missing"), "{}", stdout);
+    assert!(!stdout.contains("after"), "{}", stdout);
+}